@@ -1,7 +1,9 @@
 #![doc = include_str!("../../rfcs/001_bagua.md")]
 
-use crate::{App, CubeRng, BUZZER, RNG};
-use embassy_time::Timer;
+use crate::{
+    driver::{Matrix, Motion},
+    App, CubeRng, BUZZER, RNG,
+};
 
 /// 八卦
 #[derive(Debug)]
@@ -117,7 +119,7 @@ impl BaGua {
         Self::bagua(num)
     }
 
-    pub async fn run<T: esp_hal::i2c::Instance>(app: &mut App<'_, T>) {
+    pub async fn run<M: Motion, L: Matrix>(app: &mut App<M, L>) {
         app.ledc.clear();
         loop {
             let accel = app.accel();
@@ -130,9 +132,9 @@ impl BaGua {
                 app.ledc.write_bytes(Self::random());
                 unsafe { BUZZER.assume_init_mut().bagua().await };
             }
-            Timer::after_millis(800).await;
-
-            // TODO: 退出占卦模式
+            if app.wait(800).await {
+                break;
+            }
         }
     }
 }
@@ -1,32 +1,21 @@
-use crate::{BUZZER, RNG};
+use crate::{driver::ToneOutput, BUZZER, RNG};
+use alloc::boxed::Box;
 use cube_rand::CubeRng;
 use embassy_executor::Spawner;
 use embassy_time::Timer;
-use esp_hal::{
-    gpio::GpioPin,
-    ledc::{
-        channel::{self, config::PinConfig},
-        timer, Ledc, LowSpeed,
-    },
-    prelude::{
-        _esp_hal_ledc_channel_ChannelIFace, _esp_hal_ledc_timer_TimerIFace, _fugit_RateExtU32,
-    },
-};
 
 /// 蜂鸣器
-pub struct Buzzer<'d> {
+pub struct Buzzer {
     pub open: bool,
-    pin: GpioPin<11>,
-    ledc: Ledc<'d>,
+    output: Box<dyn ToneOutput>,
     spawner: Spawner,
 }
 
-impl<'d> Buzzer<'d> {
-    pub fn new(pin: GpioPin<11>, ledc: Ledc<'d>, spawner: Spawner) -> Self {
+impl Buzzer {
+    pub fn new(output: impl ToneOutput + 'static, spawner: Spawner) -> Self {
         Self {
             open: true,
-            ledc,
-            pin,
+            output: Box::new(output),
             spawner,
         }
     }
@@ -43,28 +32,8 @@ impl<'d> Buzzer<'d> {
         self.open = !self.open
     }
 
-    /// FIXME: esp_hal::ledc 暂时仅支持固定频率输出，不同频率需要重新配置定时器和通道
     async fn drive(&mut self, frequency: u32, duty_pct: u8) {
-        // 定时器配置:指定 PWM 信号的频率和占空比分辨率
-        let mut lstimer0 = self.ledc.get_timer::<LowSpeed>(timer::Number::Timer0);
-        lstimer0
-            .configure(timer::config::Config {
-                duty: timer::config::Duty::Duty13Bit,
-                clock_source: timer::LSClockSource::APBClk,
-                frequency: frequency.Hz(),
-            })
-            .unwrap();
-        // 通道配置:绑定定时器和输出 PWM 信号的 GPIO
-        let mut channel0 = self
-            .ledc
-            .get_channel(channel::Number::Channel0, &mut self.pin);
-        channel0
-            .configure(channel::config::Config {
-                timer: &lstimer0,
-                duty_pct,
-                pin_config: PinConfig::PushPull,
-            })
-            .unwrap();
+        self.output.drive(frequency, duty_pct);
     }
 
     /// 发声
@@ -109,6 +78,26 @@ impl<'d> Buzzer<'d> {
             .ok();
     }
 
+    /// 暂停和恢复音效
+    pub async fn pause(&mut self) {
+        if !self.open {
+            return;
+        }
+        self.spawner.spawn(tone_task(3000, 100)).ok();
+    }
+
+    /// 退出游戏音效
+    pub async fn exit(&mut self) {
+        if !self.open {
+            return;
+        }
+        self.spawner
+            .spawn(tone_ranges_task(
+                [(3000, 100), (1500, 100), (800, 150)].into_iter(),
+            ))
+            .ok();
+    }
+
     /// 八卦音效
     pub async fn bagua(&mut self) {
         if !self.open {
@@ -234,6 +223,68 @@ impl<'d> Buzzer<'d> {
             .ok();
     }
 
+    /// 方块人移动音效
+    pub async fn cube_man_move(&mut self) {
+        if !self.open {
+            return;
+        }
+        self.spawner.spawn(tone_task(5000, 30)).ok();
+    }
+
+    /// 方块人踩到新的楼梯音效
+    pub async fn cube_man_score(&mut self) {
+        if !self.open {
+            return;
+        }
+        self.spawner
+            .spawn(tone_ranges_task([(2000, 60), (3000, 60)].into_iter()))
+            .ok();
+    }
+
+    /// 方块人受伤音效
+    pub async fn cube_man_hurt(&mut self) {
+        if !self.open {
+            return;
+        }
+        self.spawner
+            .spawn(tone_ranges_task([(300, 80), (200, 80)].into_iter()))
+            .ok();
+    }
+
+    /// 方块人死亡音效
+    pub async fn cube_man_die(&mut self) {
+        if !self.open {
+            return;
+        }
+        self.spawner
+            .spawn(tone_ranges_task(
+                [(500, 300), (300, 300), (100, 500)].into_iter(),
+            ))
+            .ok();
+    }
+
+    /// 躲避方块得分音效
+    pub async fn dodge_cube_score(&mut self) {
+        if !self.open {
+            return;
+        }
+        self.spawner
+            .spawn(tone_ranges_task([(3000, 100), (4000, 100)].into_iter()))
+            .ok();
+    }
+
+    /// 躲避方块死亡音效
+    pub async fn dodge_cube_die(&mut self) {
+        if !self.open {
+            return;
+        }
+        self.spawner
+            .spawn(tone_ranges_task(
+                [(500, 300), (300, 300), (100, 500)].into_iter(),
+            ))
+            .ok();
+    }
+
     /// 推箱子移动音效
     pub async fn sokoban_move(&mut self) {
         if !self.open {
@@ -242,6 +293,28 @@ impl<'d> Buzzer<'d> {
         self.spawner.spawn(tone_task(5000, 100)).ok();
     }
 
+    /// 推箱子撤销音效
+    pub async fn sokoban_undo(&mut self) {
+        if !self.open {
+            return;
+        }
+        self.spawner
+            .spawn(tone_ranges_task([(4000, 80), (3000, 80)].into_iter()))
+            .ok();
+    }
+
+    /// 推箱子重新开始音效
+    pub async fn sokoban_restart(&mut self) {
+        if !self.open {
+            return;
+        }
+        self.spawner
+            .spawn(tone_ranges_task(
+                [(4000, 80), (3000, 80), (2000, 160)].into_iter(),
+            ))
+            .ok();
+    }
+
     /// 休眠音效
     pub async fn sleep(&mut self) {
         if !self.open {
@@ -0,0 +1,260 @@
+//! 传感器校准
+//!
+//! 屏幕朝上水平静止放置,每隔 `INTERVAL` 采集一个样本,共 `SAMPLES` 个,
+//! 加速度偏移为平均值和水平时的 (0, 0, 1g) 之差.
+//!
+//! 加速度或者角速度任意一个轴的最大和最小值相差太多说明在移动,加速度的偏移太大说明没有放平,
+//! 这两种情况不保存结果.角速度只用来检查是否静止,游戏不读取角速度,所以不计算角速度偏移.
+//! 偏移保存在设置中,开机时读取,`App::accel` 读到的是减去偏移之后的值.
+//! 录制输入时加速度偏移记录在日志中,回放时使用录制时的偏移,见 [`crate::replay`].
+
+use crate::{
+    driver::{Accel, Gyro, Matrix, Motion},
+    ui::Ui,
+    App, BUZZER,
+};
+use embassy_time::Timer;
+use log::{info, warn};
+
+/// 样本数量
+pub const SAMPLES: u32 = 200;
+/// 采样间隔,ms
+pub const INTERVAL: u64 = 5;
+/// 开始采样之前等待放平的时间,ms
+const SETTLE: u64 = 2000;
+/// 水平时每个轴允许的偏移,单位 g
+const FLAT: f32 = 0.2;
+/// 静止时加速度每个轴的最大波动,单位 g
+const STILL_ACCEL: f32 = 0.1;
+/// 静止时角速度每个轴的最大波动,单位 °/s
+const STILL_GYRO: f32 = 10.0;
+
+#[rustfmt::skip]
+const SUCCESS: [u8; 8] = [
+    0b00000000,
+    0b00000001,
+    0b00000011,
+    0b00000110,
+    0b10001100,
+    0b11011000,
+    0b01110000,
+    0b00100000,
+];
+
+#[rustfmt::skip]
+const FAILURE: [u8; 8] = [
+    0b10000001,
+    0b01000010,
+    0b00100100,
+    0b00011000,
+    0b00011000,
+    0b00100100,
+    0b01000010,
+    0b10000001,
+];
+
+/// 传感器校准偏移
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// 加速度偏移,单位 g
+    pub accel: [f32; 3],
+}
+
+impl Calibration {
+    /// 减去加速度偏移
+    pub fn accel(&self, accel: Accel) -> Accel {
+        let [x, y, z] = self.accel;
+        Accel::new(accel.x() - x, accel.y() - y, accel.z() - z)
+    }
+
+    /// 放平之后采样计算偏移,成功时返回新的偏移,失败或者退出时返回 `None`
+    pub async fn run<M: Motion, L: Matrix>(app: &mut App<M, L>) -> Option<Self> {
+        app.ledc.write_bytes(Ui::Calibration.ui());
+        if app.wait(SETTLE).await {
+            return None;
+        }
+
+        // 采样使用没有校准过的原始数据
+        let mut sampler = Sampler::default();
+        while sampler.count() < SAMPLES {
+            sampler.add(app.motion.accel(), app.motion.gyro());
+            Timer::after_millis(INTERVAL).await;
+        }
+
+        let result = sampler.finish();
+        match result {
+            Ok(calibration) => {
+                info!("calibration: {calibration:?}");
+                app.ledc.write_bytes(SUCCESS);
+                unsafe { BUZZER.assume_init_mut().menu_confirm().await };
+            }
+            Err(e) => {
+                warn!("calibration failed: {e:?}");
+                app.ledc.write_bytes(FAILURE);
+                unsafe { BUZZER.assume_init_mut().menu_access().await };
+            }
+        }
+        Timer::after_millis(1000).await;
+        result.ok()
+    }
+}
+
+/// 校准失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationError {
+    /// 没有样本
+    Empty,
+    /// 采样期间在移动
+    Moving,
+    /// 没有放平
+    NotFlat,
+}
+
+/// 三个轴的累计值和范围
+#[derive(Debug, Clone, Copy)]
+struct Axes {
+    sum: [f32; 3],
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+impl Default for Axes {
+    fn default() -> Self {
+        Self {
+            sum: [0.0; 3],
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+        }
+    }
+}
+
+impl Axes {
+    fn add(&mut self, v: [f32; 3]) {
+        for i in 0..3 {
+            self.sum[i] += v[i];
+            self.min[i] = self.min[i].min(v[i]);
+            self.max[i] = self.max[i].max(v[i]);
+        }
+    }
+
+    fn mean(&self, count: u32) -> [f32; 3] {
+        self.sum.map(|s| s / count as f32)
+    }
+
+    /// 波动最大的轴的最大和最小值之差
+    fn spread(&self) -> f32 {
+        (0..3)
+            .map(|i| self.max[i] - self.min[i])
+            .fold(0.0, f32::max)
+    }
+}
+
+/// 校准的采样和计算
+#[derive(Debug, Default, Clone)]
+pub struct Sampler {
+    count: u32,
+    accel: Axes,
+    gyro: Axes,
+}
+
+impl Sampler {
+    /// 加入一个样本
+    pub fn add(&mut self, accel: Accel, gyro: Gyro) {
+        self.count += 1;
+        self.accel.add([accel.x(), accel.y(), accel.z()]);
+        self.gyro.add([gyro.x(), gyro.y(), gyro.z()]);
+    }
+
+    /// 样本数量
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// 根据所有样本计算偏移
+    pub fn finish(&self) -> Result<Calibration, CalibrationError> {
+        if self.count == 0 {
+            return Err(CalibrationError::Empty);
+        }
+        if self.accel.spread() > STILL_ACCEL || self.gyro.spread() > STILL_GYRO {
+            return Err(CalibrationError::Moving);
+        }
+        let [x, y, z] = self.accel.mean(self.count);
+        let accel = [x, y, z - 1.0];
+        if accel.iter().any(|v| v.max(-v) > FLAT) {
+            return Err(CalibrationError::NotFlat);
+        }
+        Ok(Calibration { accel })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在平均值附近交替加上 `±spread / 2`
+    fn sampler(accel: [f32; 3], gyro: [f32; 3], spread: f32) -> Sampler {
+        let mut sampler = Sampler::default();
+        for i in 0..SAMPLES {
+            let d = if i % 2 == 0 {
+                spread / 2.0
+            } else {
+                -spread / 2.0
+            };
+            let [ax, ay, az] = accel.map(|v| v + d);
+            let [gx, gy, gz] = gyro.map(|v| v + d);
+            sampler.add(Accel::new(ax, ay, az), Gyro::new(gx, gy, gz));
+        }
+        sampler
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-4),
+            "{a:?} {b:?}"
+        );
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(Sampler::default().finish(), Err(CalibrationError::Empty));
+    }
+
+    #[test]
+    fn offsets() {
+        // 角速度的零点偏移不影响结果
+        let s = sampler([0.05, -0.1, 1.02], [1.5, -0.5, 2.0], 0.05);
+        assert_eq!(s.count(), SAMPLES);
+        let c = s.finish().unwrap();
+        assert_close(c.accel, [0.05, -0.1, 0.02]);
+
+        // 减去偏移之后为水平
+        let flat = c.accel(Accel::new(0.05, -0.1, 1.02));
+        assert_close([flat.x(), flat.y(), flat.z()], [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn moving() {
+        let accel = sampler([0.0, 0.0, 1.0], [0.0; 3], STILL_ACCEL * 1.5);
+        assert_eq!(accel.finish(), Err(CalibrationError::Moving));
+
+        // 加速度稳定但是在转动
+        let mut gyro = Sampler::default();
+        gyro.add(Accel::new(0.0, 0.0, 1.0), Gyro::new(0.0, 0.0, 0.0));
+        gyro.add(
+            Accel::new(0.0, 0.0, 1.0),
+            Gyro::new(0.0, STILL_GYRO * 1.5, 0.0),
+        );
+        assert_eq!(gyro.finish(), Err(CalibrationError::Moving));
+    }
+
+    #[test]
+    fn not_flat() {
+        let tilted = sampler([FLAT + 0.1, 0.0, 1.0], [0.0; 3], 0.0);
+        assert_eq!(tilted.finish(), Err(CalibrationError::NotFlat));
+        let upside_down = sampler([0.0, 0.0, -1.0], [0.0; 3], 0.0);
+        assert_eq!(upside_down.finish(), Err(CalibrationError::NotFlat));
+        // 刚好在范围内
+        let edge = sampler([0.0, -FLAT + 0.01, 1.0], [0.0; 3], 0.0);
+        assert!(edge.finish().is_ok());
+    }
+}
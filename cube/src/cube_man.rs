@@ -1,6 +1,9 @@
 #![doc = include_str!("../../rfcs/006_cube_man.md")]
 
-use crate::{App, Gd, RNG};
+use crate::{
+    driver::{Matrix, Motion},
+    App, Gd, BUZZER, RNG,
+};
 use alloc::{collections::VecDeque, vec::Vec};
 use cube_rand::CubeRng;
 use embassy_time::Timer;
@@ -10,6 +13,7 @@ use embedded_graphics_core::{
     prelude::WebColors,
     Pixel,
 };
+use rand_core::RngCore;
 
 /// 是方块人就下一百层
 #[derive(Debug)]
@@ -18,13 +22,29 @@ pub struct CubeManGame {
     floors: VecDeque<Option<Floor>>,
     floor_gen: FloorGen,
     depth: usize,
-    score: u8,
-    pub highest: u8,
+    /// 下过的楼梯数量
+    score: u16,
+    pub highest: u16,
+    /// 生命值,为 0 时游戏结束
+    health: u8,
+    /// 最后一个踩过的楼梯的编号,新的楼梯才计分
+    landed: usize,
     game_over: bool,
     /// ms
     waiting_time: u64,
 }
 
+/// 最大生命值,显示为最上面一行
+const MAX_HEALTH: u8 = 8;
+/// 碰到天花板的尖刺扣除的生命值
+const CEILING_DAMAGE: u8 = 3;
+/// 踩到尖刺楼梯扣除的生命值
+const SPIKE_DAMAGE: u8 = 3;
+/// 生命值的颜色
+const HEALTH_COLOR: Rgb888 = Rgb888::CSS_LIME;
+/// 天花板尖刺的颜色
+const CEILING_COLOR: Rgb888 = Rgb888::CSS_DARK_RED;
+
 impl Default for CubeManGame {
     fn default() -> Self {
         Self::new()
@@ -33,28 +53,42 @@ impl Default for CubeManGame {
 
 impl CubeManGame {
     pub fn new() -> Self {
+        Self::with_seed(unsafe { RNG.assume_init_mut().random() as u64 })
+    }
+
+    /// 相同的种子生成相同的楼梯
+    pub fn with_seed(seed: u64) -> Self {
         let floors = FloorGen::init();
 
         Self {
-            man: CubeMan::new((0, 0).into()),
+            // 最上面一行是天花板
+            man: CubeMan::new((0, 1).into()),
             floors,
-            floor_gen: FloorGen::new(),
+            floor_gen: FloorGen::new(seed),
             depth: 0,
             score: 0,
             highest: 0,
+            health: MAX_HEALTH,
+            landed: 0,
             game_over: false,
-            waiting_time: 230,
+            waiting_time: waiting_time(0),
         }
     }
 
-    pub async fn run<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<'_, T>) {
+    pub async fn run<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
         app.ledc.clear();
         app.gd = Gd::default();
 
         loop {
             if self.game_over {
-                app.ledc.draw_score(self.score);
-                Timer::after_millis(1500).await;
+                self.die(app).await;
+                // 超过两位数时滚动显示
+                if self.score >= 100 {
+                    app.ledc.scroll_number(self.score as u32, 150).await;
+                } else {
+                    app.ledc.draw_score(self.score as u8);
+                    Timer::after_millis(1500).await;
+                }
                 if self.score > self.highest {
                     self.highest = self.score;
                     app.face.break_record_animate(&mut app.ledc).await;
@@ -73,72 +107,135 @@ impl CubeManGame {
                 });
             }
             self.r#move(app).await;
-            // TODO: 移动音效,得分音效和画面效果,死亡音效
             self.draw(app);
 
-            Timer::after_millis(self.waiting_time).await;
+            if app.wait(self.waiting_time).await {
+                break;
+            }
             self.depth += 1;
+            self.waiting_time = waiting_time(self.depth);
         }
     }
 
-    async fn r#move<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<'_, T>) {
-        let np = self.man.next_pos(app);
-        if self.outside(&np) {
-            self.game_over = true;
-        } else if self.hit_wall(&np) {
-            return;
-        } else {
+    async fn r#move<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
+        let floors = self
+            .floors
+            .iter()
+            .filter_map(|f| f.clone())
+            .collect::<Vec<_>>();
+        // 倾斜越大移动越快,用力倾斜时一帧移动两格,碰到楼梯就停下
+        self.man.move_speed = 1.0 + app.tilt.strength();
+        let mut moved = false;
+        for _ in 0..(self.man.move_speed + 0.5) as i32 {
+            let np = self.man.next_pos(app);
+            // 撞墙时只是不能左右移动,仍然随楼梯上升或者下落
+            if self.hit_wall(&np) || np == self.man.pos {
+                break;
+            }
             self.man.r#move(app);
-            // 如果下面是楼梯,在停在楼梯上
-            if let Some(floor) = Self::on_floor(
-                &self
-                    .floors
-                    .iter()
-                    .filter_map(|f| f.clone())
-                    .collect::<Vec<_>>(),
-                &self.man.pos,
-            ) {
-                // 随楼梯一起向上运动
-                self.man.up();
-                self.moving_on_floor(&floor, app).await;
-            } else {
-                self.man.fall();
+            moved = true;
+            if Self::floor_at(&floors, np).is_some() {
+                break;
             }
         }
+        if moved {
+            unsafe { BUZZER.assume_init_mut().cube_man_move().await };
+        }
+        // 楼梯上升到人物所在的行,人物站在楼梯上,随楼梯一起向上运动
+        if let Some(floor) = Self::floor_at(&floors, self.man.pos) {
+            self.man.up();
+            self.land(&floor, app).await;
+            self.moving_on_floor(&floor, app).await;
+        } else if let Some(floor) = Self::floor_at(&floors, self.man.pos + Point::new(0, 1)) {
+            // 刚好落在楼梯上面,下一帧随楼梯上升
+            self.land(&floor, app).await;
+        } else {
+            self.man.fall();
+        }
+
+        if self.outside(&self.man.pos) {
+            // 掉出视野
+            self.game_over = true;
+        } else if self.man.pos.y <= 0 {
+            // 碰到天花板的尖刺,从脚下的楼梯掉下去
+            self.hurt(CEILING_DAMAGE, app).await;
+            let x = self.man.pos.x;
+            self.man.pos.y = floors
+                .iter()
+                .filter(|f| f.data.iter().any(|p| p.0.x == x && p.0.y > 0))
+                .map(|f| f.data[0].0.y + 1)
+                .min()
+                .unwrap_or(1);
+        }
     }
 
     fn calc_score(&mut self) {
-        self.score += 1;
+        self.score = self.score.saturating_add(1);
     }
 
+    /// 站到楼梯上,第一次站到这个楼梯时计分,正常的楼梯恢复生命值,尖刺楼梯扣除生命值
+    async fn land<M: Motion, L: Matrix>(&mut self, floor: &Floor, app: &mut App<M, L>) {
+        if floor.id <= self.landed {
+            return;
+        }
+        self.landed = floor.id;
+        self.calc_score();
+        match floor.r#type {
+            FloorType::Normal => {
+                self.health = (self.health + 1).min(MAX_HEALTH);
+                unsafe { BUZZER.assume_init_mut().cube_man_score().await };
+            }
+            FloorType::Spike => self.hurt(SPIKE_DAMAGE, app).await,
+            _ => unsafe { BUZZER.assume_init_mut().cube_man_score().await },
+        }
+    }
+
+    /// 扣除生命值,屏幕闪红,生命值为 0 时游戏结束
+    async fn hurt<M: Motion, L: Matrix>(&mut self, damage: u8, app: &mut App<M, L>) {
+        self.health = self.health.saturating_sub(damage);
+        unsafe { BUZZER.assume_init_mut().cube_man_hurt().await };
+        // `clear_with_color` 不刷新,整屏写入红色的像素
+        let red = Rgb888::new(0x40, 0, 0);
+        app.ledc
+            .write_pixels((0..64).map(|i| Pixel(Point::new(i % 8, i / 8), red)));
+        Timer::after_millis(80).await;
+        if self.health == 0 {
+            self.game_over = true;
+        }
+    }
+
+    /// 死亡时人物闪烁
+    async fn die<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
+        unsafe { BUZZER.assume_init_mut().cube_man_die().await };
+        let mp = self.man.pos;
+        for color in [Rgb888::BLACK, self.man.color].repeat(3) {
+            self.draw(app);
+            app.ledc.write_pixel(Pixel(mp, color));
+            Timer::after_millis(150).await;
+        }
+    }
+
+    /// 是否掉出视野,上面是天花板,不会超出
     fn outside(&self, pos: &Point) -> bool {
-        pos.y < 0 || pos.y >= 8
+        pos.y >= 8
     }
 
     fn hit_wall(&self, pos: &Point) -> bool {
         pos.x < 0 || pos.x >= 8
     }
 
-    /// 是否在楼梯上
-    fn on_floor(floors: &[Floor], pos: &Point) -> Option<Floor> {
-        let floor = floors.iter().find(|f| {
-            let min = f.data.iter().min_by(|x, y| x.cmp(y)).unwrap();
-            let max = f.data.iter().max_by(|x, y| x.cmp(y)).unwrap();
-            f.data
-                .iter()
-                .any(|p| min.0.x <= pos.x && pos.x <= max.0.x && p.0.y == pos.y + 1)
-        });
-        floor.cloned()
+    /// 占据 `pos` 的楼梯
+    fn floor_at(floors: &[Floor], pos: Point) -> Option<Floor> {
+        floors
+            .iter()
+            .find(|f| f.data.iter().any(|p| p.0 == pos))
+            .cloned()
     }
 
     /// 在楼梯上的移动
-    async fn moving_on_floor<T: esp_hal::i2c::Instance>(
-        &mut self,
-        floor: &Floor,
-        app: &mut App<'_, T>,
-    ) {
+    async fn moving_on_floor<M: Motion, L: Matrix>(&mut self, floor: &Floor, app: &mut App<M, L>) {
         match &floor.r#type {
-            FloorType::Normal => {}
+            FloorType::Normal | FloorType::Spike => {}
             FloorType::Fragile(t) => {
                 let mut fds = floor.data.clone();
                 for _ in 0..3 {
@@ -155,6 +252,12 @@ impl CubeManGame {
                 app.ledc.write_pixels(fds);
 
                 Timer::after_millis(*t).await;
+                // 碎裂,人物继续往下掉
+                for f in self.floors.iter_mut() {
+                    if f.as_ref().is_some_and(|f| f.id == floor.id) {
+                        *f = None;
+                    }
+                }
             }
             FloorType::Conveyor(cd) => {
                 if app.gd == Gd::Left || app.gd == Gd::Right {
@@ -167,7 +270,7 @@ impl CubeManGame {
                         }
                     }
                     ConveyorDir::Counterclockwise => {
-                        if self.man.pos.x - 1 < 8 {
+                        if self.man.pos.x > 0 {
                             self.man.pos.x -= 1;
                         }
                     }
@@ -179,7 +282,7 @@ impl CubeManGame {
         };
     }
 
-    pub fn draw<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<T>) {
+    pub fn draw<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
         app.ledc.clear_with_color(BinaryColor::Off.into());
         // 楼梯
         app.ledc.write_pixels(
@@ -190,6 +293,16 @@ impl CubeManGame {
                 .flat_map(|f| f.unwrap().data),
         );
 
+        // 天花板,亮起的部分为生命值,其余为尖刺
+        app.ledc.write_pixels((0..MAX_HEALTH).map(|x| {
+            let color = if x < self.health {
+                HEALTH_COLOR
+            } else {
+                CEILING_COLOR
+            };
+            Pixel(Point::new(x as i32, 0), color)
+        }));
+
         // 人物
         let mp = self.man.pos;
         app.ledc
@@ -198,8 +311,8 @@ impl CubeManGame {
 }
 
 /// 传送带旋转方向
-#[derive(Debug, Clone, Copy)]
-enum ConveyorDir {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConveyorDir {
     /// 顺时针
     Clockwise,
     /// 逆时针
@@ -207,8 +320,8 @@ enum ConveyorDir {
 }
 
 /// 楼梯类型
-#[derive(Debug, Clone, Copy)]
-enum FloorType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloorType {
     /// 正常
     Normal,
     /// 易碎(碎裂时间)
@@ -217,6 +330,8 @@ enum FloorType {
     Conveyor(ConveyorDir),
     /// 弹簧(反弹的高度)
     Spring(u8),
+    /// 尖刺
+    Spike,
 }
 
 // impl FloorType {
@@ -227,16 +342,19 @@ enum FloorType {
 
 /// 楼梯
 #[derive(Debug, Clone)]
-struct Floor {
+pub struct Floor {
+    /// 编号,从 1 开始按生成的顺序递增
+    pub id: usize,
     /// 类型
-    r#type: FloorType,
-    data: Vec<Pixel<Rgb888>>,
+    pub r#type: FloorType,
+    pub data: Vec<Pixel<Rgb888>>,
 }
 
 impl Floor {
     fn new(ft: FloorType, data: &[Point]) -> Self {
         match ft {
             FloorType::Normal => Self {
+                id: 0,
                 r#type: ft,
                 data: data
                     .iter()
@@ -244,6 +362,7 @@ impl Floor {
                     .collect::<Vec<_>>(),
             },
             FloorType::Fragile(_) => Self {
+                id: 0,
                 r#type: ft,
                 data: data
                     .iter()
@@ -251,6 +370,7 @@ impl Floor {
                     .collect::<Vec<_>>(),
             },
             FloorType::Conveyor(_) => Self {
+                id: 0,
                 r#type: ft,
                 data: data
                     .iter()
@@ -258,20 +378,59 @@ impl Floor {
                     .collect::<Vec<_>>(),
             },
             FloorType::Spring(_) => Self {
+                id: 0,
                 r#type: ft,
                 data: data
                     .iter()
                     .map(|p| Pixel((p.x, p.y).into(), RgbColor::YELLOW))
                     .collect::<Vec<_>>(),
             },
+            FloorType::Spike => Self {
+                id: 0,
+                r#type: ft,
+                data: data
+                    .iter()
+                    .map(|p| Pixel((p.x, p.y).into(), RgbColor::MAGENTA))
+                    .collect::<Vec<_>>(),
+            },
         }
     }
 }
 
+/// 楼梯之间最少空出的行数,能放下一个人物
+const MIN_GAP: usize = 1;
+/// 楼梯之间最多空出的行数
+const MAX_GAP: usize = 4;
+/// 每隔多少层楼梯间距的上限加 1
+const GAP_STEP: usize = 150;
+/// 陷阱楼梯从出现到达到最大概率经过的层数
+const RAMP: usize = 300;
+/// 开始时每一帧的等待时间,ms
+const START_WAIT: u64 = 230;
+/// 最快时每一帧的等待时间,ms
+const MIN_WAIT: u64 = 120;
+/// 每隔多少层加快一次
+const SPEED_STEP: usize = 50;
+
+/// 第 `depth` 层时每一帧的等待时间,越往下越快
+fn waiting_time(depth: usize) -> u64 {
+    START_WAIT
+        .saturating_sub((depth / SPEED_STEP) as u64 * 10)
+        .max(MIN_WAIT)
+}
+
+/// 楼梯生成器,相同的种子生成相同的楼梯
 #[derive(Debug)]
-struct FloorGen {
-    pos: Point,
-    data: VecDeque<Option<Floor>>,
+pub struct FloorGen {
+    rng: CubeRng,
+    /// 生成下一个楼梯之前还要空出的行数
+    gap: usize,
+    /// 上一个楼梯的左右两端,开始时为人物所在的列
+    last: (i32, i32),
+    /// 人物从上一个楼梯掉下来时能左右移动的格数
+    reach: i32,
+    /// 已经生成的楼梯数量
+    count: usize,
 }
 
 impl FloorGen {
@@ -281,82 +440,100 @@ impl FloorGen {
         floors
     }
 
-    fn new() -> Self {
+    pub fn new(seed: u64) -> Self {
         Self {
-            pos: (0, 0).into(),
-            data: Self::init(),
+            rng: CubeRng(seed),
+            gap: 0,
+            last: (0, 0),
+            reach: 1,
+            count: 0,
         }
     }
 
-    /// 随机生成楼梯
-    fn random(level: usize) -> Option<Floor> {
-        // 概率生成楼梯
-        let per = unsafe { CubeRng(RNG.assume_init_mut().random() as u64).random_range(1..=10) };
-        if per < 7 {
-            return None;
-        }
-
-        // 楼梯长度
-        let len = unsafe { CubeRng(RNG.assume_init_mut().random() as u64).random_range(3..=5) };
-        let mut data = Vec::<Point>::with_capacity(len);
-        for i in 0..len {
-            data.push(Point::new(i as i32, 0));
-        }
-
-        // TODO: 根据关卡等级生成楼梯
-        let floor = if level <= 5 {
-            Floor::new(FloorType::Normal, &data)
-        } else if level > 5 && level <= 150 {
-            Floor::new(FloorType::Fragile(500), &data)
-        } else {
-            Floor::new(FloorType::Conveyor(ConveyorDir::Clockwise), &data)
-            // Floor::new(FloorType::Conveyor(ConveyorDir::Counterclockwise), &data)
-            // Floor::new(FloorType::Spring(2), &data)
+    /// 各种楼梯的权重: 正常,易碎,尖刺,传送带,弹簧
+    ///
+    /// 开始时只有正常的楼梯,陷阱楼梯依次出现,经过 `RAMP` 层达到最大权重 15
+    pub fn weights(depth: usize) -> [u32; 5] {
+        let ramp = |from: usize| {
+            if depth < from {
+                0
+            } else {
+                ((depth - from).min(RAMP) as u32 * 15 / RAMP as u32).max(1)
+            }
         };
-        Some(floor)
+        let (fragile, spike, conveyor, spring) = (ramp(20), ramp(40), ramp(60), ramp(100));
+        [
+            100 - fragile - spike - conveyor - spring,
+            fragile,
+            spike,
+            conveyor,
+            spring,
+        ]
     }
 
-    /// 生成楼梯，y坐标为8
-    fn floor(&mut self, level: usize) -> Option<Floor> {
-        let mut floor = Self::random(level);
-        if let Some(ref mut floor) = floor {
-            floor.data.iter_mut().for_each(|f| f.0.y = 8);
+    /// 按权重随机选择楼梯类型
+    fn r#type(&mut self, depth: usize) -> FloorType {
+        let weights = Self::weights(depth);
+        let mut n = self.rng.random(0, weights.iter().sum::<u32>());
+        let idx = weights
+            .iter()
+            .position(|w| {
+                if n < *w {
+                    true
+                } else {
+                    n -= w;
+                    false
+                }
+            })
+            .unwrap_or(0);
+        match idx {
+            1 => FloorType::Fragile(500),
+            2 => FloorType::Spike,
+            3 if self.rng.random(0, 2) == 0 => FloorType::Conveyor(ConveyorDir::Clockwise),
+            3 => FloorType::Conveyor(ConveyorDir::Counterclockwise),
+            4 => FloorType::Spring(2),
+            _ => FloorType::Normal,
         }
-        floor
     }
 
-    fn floors(&mut self, level: usize) -> VecDeque<Option<Floor>> {
-        let mut floors = VecDeque::<Option<Floor>>::new();
-        floors.push_back(None);
-        floors.push_back(None);
-        let mut floor = Self::random(level);
-        if let Some(ref mut floor) = floor {
-            floor.data.iter_mut().for_each(|f| f.0.y = 8);
+    /// 随机生成楼梯
+    ///
+    /// 楼梯和上一个楼梯左右两端向外 `reach` 格的范围有重叠,人物掉下来时总能落到楼梯上
+    fn random(&mut self, depth: usize) -> Floor {
+        let r#type = self.r#type(depth);
+        // 传送带两端不闪烁,长度最少是 4
+        let min = match r#type {
+            FloorType::Conveyor(_) => 4,
+            _ => 3,
+        };
+        let len = self.rng.random(min as u32, 6) as i32;
+        let lo = (self.last.0 - self.reach - len + 1).max(0);
+        let hi = (self.last.1 + self.reach).min(8 - len);
+        let x = self.rng.random(lo as u32, hi as u32 + 1) as i32;
+        self.last = (x, x + len - 1);
+
+        let data = (x..x + len).map(|x| Point::new(x, 0)).collect::<Vec<_>>();
+        self.count += 1;
+        Floor {
+            id: self.count,
+            ..Floor::new(r#type, &data)
         }
-        floors.push_back(floor);
+    }
 
-        let span = unsafe { CubeRng(RNG.assume_init_mut().random() as u64).random_range(2..=6) };
-        for _ in 0..span {
-            floors.push_back(None);
-        }
-        let mut floor = Self::random(level);
-        if let Some(ref mut floor) = floor {
-            floor.data.iter_mut().for_each(|f| f.0.y += span as i32);
+    /// 生成下一行的楼梯，y坐标为8,这一行没有楼梯时为 `None`
+    pub fn floor(&mut self, depth: usize) -> Option<Floor> {
+        if self.gap > 0 {
+            self.gap -= 1;
+            return None;
         }
-        floors.push_back(floor);
-
-        floors
-
-        // (0..2)
-        //     .map(|_| {
-        //         let span = unsafe {
-        //             CubeRng(RNG.assume_init_mut().random() as u64).random_range(2..=6) as usize
-        //         };
-        //         let mut floor = Self::random(level);
-        //         floor.data.iter_mut().for_each(|f| f.0.y += span as i32);
-        //         Some(floor)
-        //     })
-        //     .collect::<VecDeque<_>>()
+        let mut floor = self.random(depth);
+        floor.data.iter_mut().for_each(|f| f.0.y = 8);
+        // 越往下楼梯之间的距离越大
+        let max = (MIN_GAP + 1 + depth / GAP_STEP).min(MAX_GAP);
+        self.gap = self.rng.random(MIN_GAP as u32, max as u32 + 1) as usize;
+        // 人物每一帧下落一格,楼梯上升一格,下落期间可以左右移动
+        self.reach = (self.gap as i32 + 1) / 2;
+        Some(floor)
     }
 }
 
@@ -383,7 +560,7 @@ impl CubeMan {
         }
     }
 
-    fn next_pos<T: esp_hal::i2c::Instance>(&self, app: &mut App<T>) -> Point {
+    fn next_pos<M: Motion, L: Matrix>(&self, app: &mut App<M, L>) -> Point {
         let mut pos = self.pos;
         match app.gd {
             Gd::Right => pos.x += 1,
@@ -393,7 +570,7 @@ impl CubeMan {
         pos
     }
 
-    fn r#move<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<T>) {
+    fn r#move<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
         self.pos = self.next_pos(app);
     }
 
@@ -407,3 +584,97 @@ impl CubeMan {
         self.pos.y -= 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        driver::fake::{FakeMatrix, FakeMotion},
+        ledc::LedControl,
+        testing,
+    };
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    /// 依次生成 `rows` 行楼梯,记录类型和左右两端
+    fn rows(seed: u64, rows: usize) -> Vec<Option<(FloorType, i32, i32)>> {
+        let mut gen = FloorGen::new(seed);
+        (0..rows)
+            .map(|depth| {
+                gen.floor(depth).map(|f| {
+                    let x = f.data.iter().map(|p| p.0.x);
+                    (f.r#type, x.clone().min().unwrap(), x.max().unwrap())
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_floors() {
+        assert_eq!(rows(1234, 500), rows(1234, 500));
+        assert_ne!(rows(1234, 500), rows(4321, 500));
+    }
+
+    #[test]
+    fn floors_reachable() {
+        for seed in 0..20 {
+            let mut last: Option<(i32, i32)> = None;
+            let mut gap = 0usize;
+            let mut types = [false; 5];
+            for (depth, row) in rows(seed, 400).into_iter().enumerate() {
+                let Some((r#type, lo, hi)) = row else {
+                    gap += 1;
+                    continue;
+                };
+                let min = if matches!(r#type, FloorType::Conveyor(_)) {
+                    4
+                } else {
+                    3
+                };
+                assert!((min..=5).contains(&(hi - lo + 1)), "{seed} {depth}");
+                assert!(lo >= 0 && hi < 8, "{seed} {depth}");
+                // 和上一个楼梯加上下落时能移动的范围有重叠
+                if let Some((plo, phi)) = last {
+                    let reach = (gap as i32 + 1) / 2;
+                    assert!(lo <= phi + reach && hi >= plo - reach, "{seed} {depth}");
+                }
+                assert!((MIN_GAP..=MAX_GAP).contains(&gap) || last.is_none());
+                types[match r#type {
+                    FloorType::Normal => 0,
+                    FloorType::Fragile(_) => 1,
+                    FloorType::Spike => 2,
+                    FloorType::Conveyor(_) => 3,
+                    FloorType::Spring(_) => 4,
+                }] = true;
+                last = Some((lo, hi));
+                gap = 0;
+            }
+            assert!(types.iter().all(|t| *t), "{seed}");
+        }
+    }
+
+    #[test]
+    fn hurt_flashes_red() {
+        testing::run(|spawner| async move {
+            let frames = Rc::new(RefCell::new(Vec::new()));
+            let sink = frames.clone();
+            let mut ledc = LedControl::with_matrix(FakeMatrix::default());
+            ledc.capture(move |frame| sink.borrow_mut().push(frame.pixels));
+            let mut app = App::new(FakeMotion::new([]), ledc, spawner);
+            let mut game = CubeManGame::with_seed(1);
+            game.hurt(1, &mut app).await;
+            let red = Rgb888::new(0x40, 0, 0);
+            assert!(frames.borrow().iter().any(|f| f.iter().all(|c| *c == red)));
+            assert_eq!(game.health, MAX_HEALTH - 1);
+            assert!(!game.game_over);
+        });
+    }
+
+    #[test]
+    fn weights_sum_to_100() {
+        for depth in [0, 20, 100, 400, 1000] {
+            assert_eq!(FloorGen::weights(depth).iter().sum::<u32>(), 100);
+        }
+        assert_eq!(FloorGen::weights(0), [100, 0, 0, 0, 0]);
+    }
+}
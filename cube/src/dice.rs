@@ -1,7 +1,9 @@
 #![doc = include_str!("../../rfcs/002_dice.md")]
 
-use crate::{App, CubeRng, BUZZER, RNG};
-use embassy_time::Timer;
+use crate::{
+    driver::{Matrix, Motion},
+    App, CubeRng, BUZZER, RNG,
+};
 
 /// 骰子
 #[derive(Debug)]
@@ -80,7 +82,7 @@ impl Dice {
         Self::dice(num)
     }
 
-    pub async fn run<T: esp_hal::i2c::Instance>(&self, app: &mut App<'_, T>) {
+    pub async fn run<M: Motion, L: Matrix>(&self, app: &mut App<M, L>) {
         app.ledc.clear();
         loop {
             let accel = app.accel();
@@ -93,9 +95,9 @@ impl Dice {
                 app.ledc.write_bytes(Self::random());
                 unsafe { BUZZER.assume_init_mut().dice().await };
             }
-            Timer::after_millis(800).await;
-
-            // TODO: 推出骰子模式
+            if app.wait(800).await {
+                break;
+            }
         }
     }
 }
@@ -1,21 +1,69 @@
 #![doc = include_str!("../../rfcs/008_dodge_cube.md")]
 
-use crate::{ledc::LedControl, player::Player, App, Gd};
-use alloc::collections::LinkedList;
+use crate::{
+    driver::{Matrix, Motion},
+    ledc::LedControl,
+    player::Player,
+    App, CubeRng, Gd, BUZZER, RNG,
+};
+use alloc::{collections::LinkedList, collections::VecDeque, vec::Vec};
 use embassy_time::Timer;
-use embedded_graphics::{geometry::Point, pixelcolor::Rgb888, Pixel};
+use embedded_graphics::{
+    geometry::Point,
+    pixelcolor::{Rgb888, WebColors},
+    Pixel,
+};
+
+/// 障碍物的形状,每个点为相对左上角的偏移
+const SHAPES: [&[(i32, i32)]; 7] = [
+    // 单点
+    &[(0, 0)],
+    // 横条
+    &[(0, 0), (1, 0)],
+    &[(0, 0), (1, 0), (2, 0)],
+    // 竖条
+    &[(0, 0), (0, 1)],
+    // 方块
+    &[(0, 0), (1, 0), (0, 1), (1, 1)],
+    // L 形
+    &[(0, 0), (0, 1), (1, 1)],
+    &[(1, 0), (0, 1), (1, 1)],
+];
+
+/// 障碍物的颜色
+const COLORS: [Rgb888; 4] = [
+    Rgb888::CSS_DEEP_SKY_BLUE,
+    Rgb888::CSS_YELLOW,
+    Rgb888::CSS_LIME,
+    Rgb888::CSS_VIOLET,
+];
+
+/// 上一波障碍物下落多少行之后生成下一波
+const WAVE_GAP: i32 = 3;
+/// 每一波最多的障碍物数量
+const WAVE_CUBES: u32 = 3;
+/// 每躲过一波减少的等待时间,ms
+const SPEED_UP: u64 = 25;
+/// 最短的等待时间,ms
+const MIN_WAITING_TIME: u64 = 200;
+
+fn random(min: u32, max: u32) -> u32 {
+    unsafe { CubeRng(RNG.assume_init_mut().random() as u64).random(min, max) }
+}
 
 #[derive(Debug)]
 pub struct DodgeCubeGame {
     width: i32,
     height: i32,
     player: Player,
+    /// 还没有完全落出屏幕的障碍物,按波次排列
+    waves: VecDeque<Vec<Cube>>,
     /// ms
     waiting_time: u64,
     /// 得分
-    score: u8,
+    score: u16,
     /// 最高分
-    pub highest: u8,
+    pub highest: u16,
     game_over: bool,
 }
 
@@ -34,6 +82,7 @@ impl DodgeCubeGame {
             width,
             height,
             player: Player::new(Point::new(3, 7)),
+            waves: VecDeque::new(),
             waiting_time: 600,
             score: 0,
             highest: 0,
@@ -41,16 +90,24 @@ impl DodgeCubeGame {
         }
     }
 
-    pub async fn run<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<'_, T>) {
+    pub async fn run<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
         app.ledc.clear();
         app.gd = Gd::default();
 
         loop {
-            Timer::after_millis(self.waiting_time).await;
+            if app.wait(self.waiting_time).await {
+                break;
+            }
 
             if self.game_over {
-                app.ledc.draw_score(self.score);
-                Timer::after_millis(1500).await;
+                unsafe { BUZZER.assume_init_mut().dodge_cube_die().await };
+                // 超过两位数时滚动显示
+                if self.score >= 100 {
+                    app.ledc.scroll_number(self.score as u32, 150).await;
+                } else {
+                    app.ledc.draw_score(self.score as u8);
+                    Timer::after_millis(1500).await;
+                }
                 if self.score > self.highest {
                     self.highest = self.score;
                     app.face.break_record_animate(&mut app.ledc).await;
@@ -60,31 +117,149 @@ impl DodgeCubeGame {
             }
             app.gravity_direction();
             self.r#move(&app.gd);
-            // TODO: 移动音效,得分音效和画面效果,死亡音效
+            if self.fall() {
+                unsafe { BUZZER.assume_init_mut().dodge_cube_score().await };
+            }
             self.draw(&mut app.ledc);
         }
     }
 
-    fn r#move(&mut self, _gd: &Gd) {}
+    /// 玩家移动,撞墙时停在原地
+    fn r#move(&mut self, gd: &Gd) {
+        let np = self.player.next_pos(*gd);
+        if !self.outside(np) {
+            self.player.r#move(*gd);
+        }
+        if self.hit() {
+            self.game_over = true;
+        }
+    }
+
+    /// 障碍物下落一行,返回是否躲过了一波
+    fn fall(&mut self) -> bool {
+        self.waves.iter_mut().flatten().for_each(Cube::fall);
+
+        let mut cleared = false;
+        while let Some(wave) = self.waves.front() {
+            if !wave.iter().all(|c| c.top() >= self.height) {
+                break;
+            }
+            self.waves.pop_front();
+            self.calc_score();
+            cleared = true;
+        }
+
+        let spawn = match self.waves.back() {
+            Some(wave) => wave.iter().map(Cube::top).min().unwrap_or(0) >= WAVE_GAP,
+            None => true,
+        };
+        if spawn {
+            self.spawn();
+        }
+
+        if self.hit() {
+            self.game_over = true;
+        }
+        cleared
+    }
 
+    /// 在屏幕上方生成一波障碍物,障碍物之间至少间隔一个像素
+    fn spawn(&mut self) {
+        let count = random(1, WAVE_CUBES + 1);
+        let mut wave: Vec<Cube> = Vec::new();
+        for _ in 0..count {
+            // 放不下时重新随机,多次失败则这一波少一个障碍物
+            for _ in 0..8 {
+                let shape = SHAPES[random(0, SHAPES.len() as u32) as usize];
+                let w = shape.iter().map(|p| p.0).max().unwrap() + 1;
+                let h = shape.iter().map(|p| p.1).max().unwrap() + 1;
+                let x = random(0, (self.width - w + 1) as u32) as i32;
+                let color = COLORS[random(0, COLORS.len() as u32) as usize];
+                let cube = Cube::new(shape, Point::new(x, -h), color);
+
+                let mut others = self.waves.iter().flatten().chain(wave.iter());
+                if others.all(|o| cube.apart(o)) {
+                    wave.push(cube);
+                    break;
+                }
+            }
+        }
+        if !wave.is_empty() {
+            self.waves.push_back(wave);
+        }
+    }
+
+    /// 玩家是否撞到了障碍物
+    fn hit(&self) -> bool {
+        self.waves
+            .iter()
+            .flatten()
+            .any(|c| c.contains(self.player.pos))
+    }
+
+    /// 每躲过一波加一分,并且加快速度
     fn calc_score(&mut self) {
-        self.score += 1;
+        self.score = self.score.saturating_add(1);
+        self.waiting_time = self
+            .waiting_time
+            .saturating_sub(SPEED_UP)
+            .max(MIN_WAITING_TIME);
     }
 
     fn outside(&self, pos: Point) -> bool {
         pos.x < 0 || pos.y < 0 || pos.x >= self.width || pos.y >= self.height
     }
 
-    pub fn draw(&mut self, ledc: &mut LedControl<'_>) {
+    pub fn draw<L: Matrix>(&mut self, ledc: &mut LedControl<L>) {
         ledc.clear();
-        // let mut pixels = self.snake.body.clone();
-        // ledc.write_pixels(pixels);
+        let mut pixels = self
+            .waves
+            .iter()
+            .flatten()
+            .flat_map(|c| c.body.iter().copied())
+            .filter(|p| !self.outside(p.0))
+            .collect::<Vec<_>>();
+        pixels.push(self.player.into());
+        ledc.write_pixels(pixels);
     }
 }
 
+/// 障碍物
 #[derive(Debug)]
 struct Cube {
     body: LinkedList<Pixel<Rgb888>>,
 }
 
-impl Cube {}
+impl Cube {
+    fn new(shape: &[(i32, i32)], pos: Point, color: Rgb888) -> Self {
+        Self {
+            body: shape
+                .iter()
+                .map(|(x, y)| Pixel(pos + Point::new(*x, *y), color))
+                .collect(),
+        }
+    }
+
+    fn fall(&mut self) {
+        self.body.iter_mut().for_each(|p| p.0.y += 1);
+    }
+
+    /// 最上面一行的 y
+    fn top(&self) -> i32 {
+        self.body.iter().map(|p| p.0.y).min().unwrap_or(i32::MAX)
+    }
+
+    fn contains(&self, pos: Point) -> bool {
+        self.body.iter().any(|p| p.0 == pos)
+    }
+
+    /// 与另一个障碍物之间是否至少间隔一个像素
+    fn apart(&self, other: &Cube) -> bool {
+        self.body.iter().all(|a| {
+            other
+                .body
+                .iter()
+                .all(|b| (a.0.x - b.0.x).abs() > 1 || (a.0.y - b.0.y).abs() > 1)
+        })
+    }
+}
@@ -0,0 +1,267 @@
+//! 硬件抽象
+//!
+//! `App` 和所有游戏只依赖这里的 trait,esp32c3 上使用真实的外设实现,
+//! 主机上使用 [`fake`] 中的内存实现,这样游戏逻辑可以脱离开发板编译和测试.
+//!
+//! 时钟直接使用 `embassy-time`,它本身就是对时钟驱动的抽象:
+//! 开发板上由 `esp-hal-embassy` 提供驱动,主机上启用 `embassy-time` 的 `std` 或 `mock-driver` 特性即可.
+
+use crate::calibration::Calibration;
+use cube_rand::CubeRng;
+use embedded_graphics::Pixel;
+use embedded_graphics_core::pixelcolor::Rgb888;
+use rand_core::RngCore;
+
+pub mod fake;
+
+/// 加速度,单位 g
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Accel {
+    x: f32,
+    y: f32,
+    z: f32,
+}
+
+impl Accel {
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn x(&self) -> f32 {
+        self.x
+    }
+
+    pub fn y(&self) -> f32 {
+        self.y
+    }
+
+    pub fn z(&self) -> f32 {
+        self.z
+    }
+}
+
+/// 角速度,单位 °/s
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Gyro {
+    x: f32,
+    y: f32,
+    z: f32,
+}
+
+impl Gyro {
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn x(&self) -> f32 {
+        self.x
+    }
+
+    pub fn y(&self) -> f32 {
+        self.y
+    }
+
+    pub fn z(&self) -> f32 {
+        self.z
+    }
+}
+
+/// 运动传感器
+pub trait Motion {
+    /// 读取加速度
+    fn accel(&mut self) -> Accel;
+
+    /// 读取角速度,没有陀螺仪时为 0
+    fn gyro(&mut self) -> Gyro {
+        Gyro::default()
+    }
+
+    /// 开机时使用的校准偏移,`stored` 为设置中保存的偏移,回放时换成录制时的偏移
+    fn calibration(&mut self, stored: Calibration) -> Calibration {
+        stored
+    }
+}
+
+/// 8*8 点阵
+pub trait Matrix {
+    /// 将像素写入缓冲区
+    fn draw<I>(&mut self, pixels: I)
+    where
+        I: IntoIterator<Item = Pixel<Rgb888>>;
+
+    /// 将缓冲区刷新到点阵
+    fn flush(&mut self);
+
+    /// 用一种颜色填满缓冲区
+    fn clear(&mut self, color: Rgb888);
+
+    /// 设置亮度
+    fn set_brightness(&mut self, brightness: u8);
+}
+
+/// 发声输出,一般是驱动无源蜂鸣器的 PWM
+pub trait ToneOutput {
+    /// 以指定的频率和占空比输出,占空比为 0 时静音
+    fn drive(&mut self, frequency: u32, duty_pct: u8);
+}
+
+/// 麦克风
+pub trait Microphone {
+    /// 读取一个采样,为 ADC 的原始读数
+    fn sample(&mut self) -> u16;
+}
+
+/// 随机数来源
+pub trait Entropy {
+    fn random(&mut self) -> u32;
+}
+
+impl Entropy for CubeRng {
+    fn random(&mut self) -> u32 {
+        self.next_u32()
+    }
+}
+
+mod mpu6050 {
+    use super::{Accel, Gyro, Motion};
+    use embedded_hal::i2c::I2c;
+    use mpu6050_dmp::{accel::AccelFullScale, gyro::GyroFullScale, sensor::Mpu6050};
+
+    impl<I: I2c> Motion for Mpu6050<I> {
+        fn accel(&mut self) -> Accel {
+            let accel = Mpu6050::accel(self).unwrap().scaled(AccelFullScale::G2);
+            Accel::new(accel.x(), accel.y(), accel.z())
+        }
+
+        /// `initialize_dmp` 把量程设置为 ±2000°/s
+        fn gyro(&mut self) -> Gyro {
+            let gyro = Mpu6050::gyro(self).unwrap().scaled(GyroFullScale::Deg2000);
+            Gyro::new(gyro.x(), gyro.y(), gyro.z())
+        }
+    }
+}
+
+mod ws2812 {
+    use super::Matrix;
+    use crate::ledc::Ws2812Matrix;
+    use embedded_graphics::{draw_target::DrawTarget, Pixel};
+    use embedded_graphics_core::pixelcolor::Rgb888;
+    use embedded_hal::spi::SpiBus;
+    use log::error;
+    use smart_leds_matrix::SmartLedMatrix;
+
+    impl<SPI: SpiBus> Matrix for Ws2812Matrix<SPI> {
+        fn draw<I>(&mut self, pixels: I)
+        where
+            I: IntoIterator<Item = Pixel<Rgb888>>,
+        {
+            if let Err(e) = self.draw_iter(pixels) {
+                error!("draw pixels error: {e:?}");
+            }
+        }
+
+        fn flush(&mut self) {
+            if let Err(e) = SmartLedMatrix::flush(self) {
+                error!("flush pixels error: {e:?}");
+            }
+        }
+
+        fn clear(&mut self, color: Rgb888) {
+            if let Err(e) = DrawTarget::clear(self, color) {
+                error!("clear error: {e:?}");
+            }
+        }
+
+        fn set_brightness(&mut self, brightness: u8) {
+            SmartLedMatrix::set_brightness(self, brightness);
+        }
+    }
+}
+
+#[cfg(feature = "esp32c3")]
+pub use esp::{EspMicrophone, EspTone};
+
+#[cfg(feature = "esp32c3")]
+mod esp {
+    use super::{Entropy, Microphone, ToneOutput};
+    use esp_hal::{
+        analog::adc::{Adc, AdcPin},
+        gpio::GpioPin,
+        ledc::{
+            channel::{self, config::PinConfig},
+            timer, Ledc, LowSpeed,
+        },
+        peripherals::ADC1,
+        prelude::{
+            _esp_hal_ledc_channel_ChannelIFace, _esp_hal_ledc_timer_TimerIFace, _fugit_RateExtU32,
+        },
+        rng::Rng,
+    };
+
+    impl Entropy for Rng {
+        fn random(&mut self) -> u32 {
+            Rng::random(self)
+        }
+    }
+
+    /// 通过 ADC1 读取接在 GPIO1 上的麦克风
+    pub struct EspMicrophone<'d> {
+        adc: Adc<'d, ADC1>,
+        pin: AdcPin<GpioPin<1>, ADC1>,
+    }
+
+    impl<'d> EspMicrophone<'d> {
+        pub fn new(adc: Adc<'d, ADC1>, pin: AdcPin<GpioPin<1>, ADC1>) -> Self {
+            Self { adc, pin }
+        }
+    }
+
+    impl<'d> Microphone for EspMicrophone<'d> {
+        fn sample(&mut self) -> u16 {
+            // 单次转换完成之前返回 WouldBlock
+            loop {
+                if let Ok(v) = self.adc.read_oneshot(&mut self.pin) {
+                    return v;
+                }
+            }
+        }
+    }
+
+    /// 通过 LEDC 输出 PWM 驱动蜂鸣器
+    pub struct EspTone<'d> {
+        pin: GpioPin<11>,
+        ledc: Ledc<'d>,
+    }
+
+    impl<'d> EspTone<'d> {
+        pub fn new(pin: GpioPin<11>, ledc: Ledc<'d>) -> Self {
+            Self { pin, ledc }
+        }
+    }
+
+    impl<'d> ToneOutput for EspTone<'d> {
+        /// FIXME: esp_hal::ledc 暂时仅支持固定频率输出，不同频率需要重新配置定时器和通道
+        fn drive(&mut self, frequency: u32, duty_pct: u8) {
+            // 定时器配置:指定 PWM 信号的频率和占空比分辨率
+            let mut lstimer0 = self.ledc.get_timer::<LowSpeed>(timer::Number::Timer0);
+            lstimer0
+                .configure(timer::config::Config {
+                    duty: timer::config::Duty::Duty13Bit,
+                    clock_source: timer::LSClockSource::APBClk,
+                    frequency: frequency.Hz(),
+                })
+                .unwrap();
+            // 通道配置:绑定定时器和输出 PWM 信号的 GPIO
+            let mut channel0 = self
+                .ledc
+                .get_channel(channel::Number::Channel0, &mut self.pin);
+            channel0
+                .configure(channel::config::Config {
+                    timer: &lstimer0,
+                    duty_pct,
+                    pin_config: PinConfig::PushPull,
+                })
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! 内存中的硬件实现,用于在主机上运行和测试游戏
+
+use super::{Accel, Matrix, Microphone, Motion, ToneOutput};
+use alloc::{collections::VecDeque, rc::Rc, vec::Vec};
+use core::cell::RefCell;
+use embedded_graphics::Pixel;
+use embedded_graphics_core::{pixelcolor::Rgb888, prelude::RgbColor};
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashError, NorFlashErrorKind,
+    ReadNorFlash,
+};
+
+/// 按顺序回放加速度样本,样本读完之后一直保持最后一个样本
+#[derive(Debug, Default, Clone)]
+pub struct FakeMotion {
+    samples: VecDeque<Accel>,
+    last: Accel,
+}
+
+impl FakeMotion {
+    pub fn new(samples: impl IntoIterator<Item = Accel>) -> Self {
+        Self {
+            samples: samples.into_iter().collect(),
+            last: Accel::default(),
+        }
+    }
+
+    /// 追加一个样本
+    pub fn push(&mut self, accel: Accel) {
+        self.samples.push_back(accel);
+    }
+
+    /// 立即改变当前的加速度,丢弃未读的样本
+    pub fn set(&mut self, accel: Accel) {
+        self.samples.clear();
+        self.last = accel;
+    }
+}
+
+impl Motion for FakeMotion {
+    fn accel(&mut self) -> Accel {
+        if let Some(accel) = self.samples.pop_front() {
+            self.last = accel;
+        }
+        self.last
+    }
+}
+
+/// 帧缓冲,`frame` 为最后一次刷新到点阵的画面,按行存储
+#[derive(Debug, Clone)]
+pub struct FakeMatrix {
+    buffer: [Rgb888; 64],
+    pub frame: [Rgb888; 64],
+    pub brightness: u8,
+    /// 刷新次数
+    pub flushes: usize,
+}
+
+impl Default for FakeMatrix {
+    fn default() -> Self {
+        Self {
+            buffer: [Rgb888::BLACK; 64],
+            frame: [Rgb888::BLACK; 64],
+            brightness: 0,
+            flushes: 0,
+        }
+    }
+}
+
+impl FakeMatrix {
+    /// 获取某个像素的颜色
+    pub fn pixel(&self, x: usize, y: usize) -> Rgb888 {
+        self.frame[y * 8 + x]
+    }
+
+    /// 以位图的形式获取画面,非黑色的像素为 1
+    pub fn bytes(&self) -> [u8; 8] {
+        let mut data = [0; 8];
+        for (i, c) in self.frame.iter().enumerate() {
+            if *c != Rgb888::BLACK {
+                data[i / 8] |= 1 << (7 - i % 8);
+            }
+        }
+        data
+    }
+}
+
+impl Matrix for FakeMatrix {
+    fn draw<I>(&mut self, pixels: I)
+    where
+        I: IntoIterator<Item = Pixel<Rgb888>>,
+    {
+        for Pixel(p, c) in pixels {
+            if (0..8).contains(&p.x) && (0..8).contains(&p.y) {
+                self.buffer[p.y as usize * 8 + p.x as usize] = c;
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        self.frame = self.buffer;
+        self.flushes += 1;
+    }
+
+    fn clear(&mut self, color: Rgb888) {
+        self.buffer = [color; 64];
+    }
+
+    fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+}
+
+/// 记录每一次输出的频率和占空比,克隆之后共享同一份记录
+#[derive(Debug, Default, Clone)]
+pub struct FakeTone(Rc<RefCell<Vec<(u32, u8)>>>);
+
+impl FakeTone {
+    /// 已经输出的 (频率, 占空比)
+    pub fn events(&self) -> Vec<(u32, u8)> {
+        self.0.borrow().clone()
+    }
+}
+
+impl ToneOutput for FakeTone {
+    fn drive(&mut self, frequency: u32, duty_pct: u8) {
+        self.0.borrow_mut().push((frequency, duty_pct));
+    }
+}
+
+/// 循环回放采样的麦克风,没有采样时保持静音
+#[derive(Debug, Default, Clone)]
+pub struct FakeMicrophone {
+    samples: Vec<u16>,
+    next: usize,
+}
+
+impl FakeMicrophone {
+    pub fn new(samples: impl IntoIterator<Item = u16>) -> Self {
+        Self {
+            samples: samples.into_iter().collect(),
+            next: 0,
+        }
+    }
+}
+
+impl Microphone for FakeMicrophone {
+    fn sample(&mut self) -> u16 {
+        let Some(v) = self.samples.get(self.next) else {
+            return 0;
+        };
+        self.next = (self.next + 1) % self.samples.len();
+        *v
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FakeFlashError {
+    /// 地址或长度没有对齐
+    NotAligned,
+    /// 读写超出了容量
+    OutOfBounds,
+    /// 写入的位置没有擦除
+    NotErased,
+    /// 模拟的断电
+    PowerLoss,
+}
+
+impl NorFlashError for FakeFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::NotAligned => NorFlashErrorKind::NotAligned,
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl From<NorFlashErrorKind> for FakeFlashError {
+    fn from(kind: NorFlashErrorKind) -> Self {
+        match kind {
+            NorFlashErrorKind::NotAligned => Self::NotAligned,
+            _ => Self::OutOfBounds,
+        }
+    }
+}
+
+/// 内存中的 NOR flash,读写粒度和扇区大小与 esp32c3 相同,初始内容为擦除后的 0xff
+///
+/// 只能写入已经擦除的位置,否则返回 [`FakeFlashError::NotErased`],
+/// 可以在写入指定字节数之后模拟断电,留下写了一半的数据.
+#[derive(Debug, Clone)]
+pub struct FakeNorFlash {
+    data: Vec<u8>,
+    /// 每个扇区的擦除次数
+    erases: Vec<u32>,
+    /// 断电前还能写入的字节数
+    power: Option<usize>,
+    /// 下一次擦除进行到一半时断电
+    half_erase: bool,
+}
+
+impl FakeNorFlash {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            data: vec![0xff; capacity],
+            erases: vec![0; capacity.div_ceil(Self::ERASE_SIZE)],
+            power: None,
+            half_erase: false,
+        }
+    }
+
+    /// flash 中的原始数据
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// 每个扇区的擦除次数
+    pub fn erases(&self) -> &[u32] {
+        &self.erases
+    }
+
+    /// 再写入 `bytes` 个字节之后断电,之后的写入和擦除都会失败
+    pub fn cut_power_after(&mut self, bytes: usize) {
+        self.power = Some(bytes);
+    }
+
+    /// 下一次擦除只擦掉前一半就断电
+    pub fn cut_power_during_erase(&mut self) {
+        self.half_erase = true;
+    }
+
+    /// 恢复供电
+    pub fn restore_power(&mut self) {
+        self.power = None;
+        self.half_erase = false;
+    }
+}
+
+impl ErrorType for FakeNorFlash {
+    type Error = FakeFlashError;
+}
+
+impl ReadNorFlash for FakeNorFlash {
+    const READ_SIZE: usize = 4;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let start = offset as usize;
+        bytes.copy_from_slice(&self.data[start..start + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for FakeNorFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = 4096;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        if self.power == Some(0) {
+            return Err(FakeFlashError::PowerLoss);
+        }
+        let (from, to) = (from as usize, to as usize);
+        if self.half_erase {
+            self.data[from..(from + to) / 2].fill(0xff);
+            self.power = Some(0);
+            return Err(FakeFlashError::PowerLoss);
+        }
+        self.data[from..to].fill(0xff);
+        for n in &mut self.erases[from / Self::ERASE_SIZE..to / Self::ERASE_SIZE] {
+            *n += 1;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        let start = offset as usize;
+        let target = &mut self.data[start..start + bytes.len()];
+        if target.iter().any(|b| *b != 0xff) {
+            return Err(FakeFlashError::NotErased);
+        }
+        match self.power.as_mut() {
+            Some(power) if *power < bytes.len() => {
+                target[..*power].copy_from_slice(&bytes[..*power]);
+                *power = 0;
+                Err(FakeFlashError::PowerLoss)
+            }
+            power => {
+                if let Some(power) = power {
+                    *power -= bytes.len();
+                }
+                target.copy_from_slice(bytes);
+                Ok(())
+            }
+        }
+    }
+}
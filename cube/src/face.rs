@@ -1,4 +1,4 @@
-use crate::{buzzer::Buzzer, ledc::LedControl, BUZZER, RNG};
+use crate::{buzzer::Buzzer, driver::Matrix, ledc::LedControl, BUZZER, RNG};
 use alloc::vec::Vec;
 use cube_rand::CubeRng;
 use embassy_time::Timer;
@@ -166,12 +166,12 @@ impl Face {
     }
 
     /// 眨眼动画
-    pub async fn blink_animate<'d>(
+    pub async fn blink_animate<L: Matrix>(
         &mut self,
         x: u8,
         y: u8,
-        ledc: &mut LedControl<'d>,
-        buzzer: &mut Buzzer<'d>,
+        ledc: &mut LedControl<L>,
+        buzzer: &mut Buzzer,
     ) {
         self.clear();
 
@@ -190,10 +190,10 @@ impl Face {
     }
 
     /// 休眠动画
-    pub async fn dormancy_animate<'d>(
+    pub async fn dormancy_animate<L: Matrix>(
         &mut self,
-        ledc: &mut LedControl<'d>,
-        buzzer: &mut Buzzer<'d>,
+        ledc: &mut LedControl<L>,
+        buzzer: &mut Buzzer,
     ) {
         self.clear();
 
@@ -271,7 +271,11 @@ impl Face {
     }
 
     /// 唤醒动画
-    pub async fn wakeup_animate<'d>(&mut self, ledc: &mut LedControl<'d>, buzzer: &mut Buzzer<'d>) {
+    pub async fn wakeup_animate<L: Matrix>(
+        &mut self,
+        ledc: &mut LedControl<L>,
+        buzzer: &mut Buzzer,
+    ) {
         let ex: u8 = 1;
         let ey: u8 = 4;
 
@@ -292,10 +296,10 @@ impl Face {
     }
 
     /// 破记录动画
-    pub async fn break_record_animate<'d>(
+    pub async fn break_record_animate<L: Matrix>(
         &mut self,
-        ledc: &mut LedControl<'d>,
-        // buzzer: &mut Buzzer<'d>,
+        ledc: &mut LedControl<L>,
+        // buzzer: &mut Buzzer,
     ) {
         let ex = 1;
         let ey = 4;
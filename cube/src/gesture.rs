@@ -0,0 +1,150 @@
+//! 所有游戏共用的手势
+//!
+//! - 屏幕朝下保持 2 秒: 退出游戏,回到菜单
+//! - 屏幕朝下不到 2 秒又翻回来: 由游戏自己处理,例如推箱子的提示
+//! - 连续敲击两下: 暂停,再敲击两下恢复
+//! - 摇晃: 由游戏自己处理,例如推箱子的撤销
+//! - 持续摇晃 2 秒: 由游戏自己处理,例如推箱子的重新开始
+//!
+//! 手势只由加速度和时间决定,游戏通过 `App::wait` 在每一帧的等待中检测手势,
+//! 需要处理摇晃和翻转的游戏使用 `App::wait_gesture`.
+//! 时间使用 `App` 的逻辑时钟,只按读取样本之间等待的时间前进,和实际经过的时间无关,
+//! 所以回放相同的样本总能识别出相同的手势.
+
+use crate::driver::Accel;
+
+/// 检测手势的间隔,ms
+pub const TICK: u64 = 20;
+/// 屏幕朝下的阈值,单位 g
+const FACE_DOWN: f32 = -0.8;
+/// 屏幕朝下需要保持的时间,ms
+const EXIT_HOLD: u64 = 2000;
+/// 翻转时屏幕朝下的最短时间,ms
+const FLIP_HOLD: u64 = 300;
+/// 翻回来之后还在晃动的时间,ms
+const SETTLE: u64 = 500;
+/// 敲击时加速度大小的阈值,单位 g
+const TAP: f32 = 1.6;
+/// 静止时加速度大小的范围,单位 g²
+const STILL: core::ops::Range<f32> = 0.8..1.2;
+/// 两次敲击的最小和最大间隔,ms
+const TAP_GAP: core::ops::RangeInclusive<u64> = 80..=400;
+/// 摇晃时加速度大小的阈值,单位 g
+const SHAKE: f32 = 1.5;
+/// 一次摇晃需要的冲击次数,敲击两下最多只有几次
+const SHAKE_HITS: u8 = 6;
+/// 冲击之间超过这个间隔就算停止摇晃,ms
+const SHAKE_GAP: u64 = 300;
+/// 持续摇晃需要的时间,ms
+const LONG_SHAKE: u64 = 2000;
+
+/// 手势
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    /// 暂停或恢复
+    Pause,
+    /// 退出
+    Exit,
+    /// 摇晃,停下之前只识别一次
+    Shake,
+    /// 摇晃之后继续摇晃
+    LongShake,
+    /// 屏幕朝下又翻回来
+    Flip,
+}
+
+/// 手势识别
+#[derive(Debug, Default, Clone)]
+pub struct Gestures {
+    /// 屏幕开始朝下的时间
+    down_since: Option<u64>,
+    /// 翻回来的时间
+    flipped_at: Option<u64>,
+    /// 上一次敲击的时间
+    last_tap: Option<u64>,
+    /// 上一个样本的加速度大小的平方
+    last_mag2: f32,
+    /// 这一次摇晃中冲击的次数
+    shake_hits: u8,
+    /// 上一次冲击的时间
+    last_hit: Option<u64>,
+    /// 识别出摇晃的时间
+    shake_since: Option<u64>,
+}
+
+impl Gestures {
+    /// 输入一个样本和当前时间(ms),识别出手势时返回
+    pub fn update(&mut self, accel: Accel, now: u64) -> Option<Gesture> {
+        if accel.z() < FACE_DOWN {
+            let since = *self.down_since.get_or_insert(now);
+            if now - since >= EXIT_HOLD {
+                self.down_since = None;
+                return Some(Gesture::Exit);
+            }
+        } else if let Some(since) = self.down_since.take() {
+            if now - since >= FLIP_HOLD {
+                self.flipped_at = Some(now);
+                return Some(Gesture::Flip);
+            }
+        }
+
+        // 静止时突然出现的冲击才算敲击,持续的摇晃不算
+        let mag2 = accel.x() * accel.x() + accel.y() * accel.y() + accel.z() * accel.z();
+        let tap = mag2 > TAP * TAP && STILL.contains(&self.last_mag2);
+        self.last_mag2 = mag2;
+        if let Some(gesture) = self.shake(mag2, now) {
+            return Some(gesture);
+        }
+        if !tap {
+            return None;
+        }
+        match self.last_tap {
+            Some(t) if TAP_GAP.contains(&(now - t)) => {
+                self.last_tap = None;
+                Some(Gesture::Pause)
+            }
+            _ => {
+                self.last_tap = Some(now);
+                None
+            }
+        }
+    }
+
+    /// 识别摇晃,停下来之后才能识别下一次摇晃
+    fn shake(&mut self, mag2: f32, now: u64) -> Option<Gesture> {
+        if mag2 <= SHAKE * SHAKE {
+            return None;
+        }
+        if self.last_hit.is_none_or(|t| now - t > SHAKE_GAP) {
+            self.shake_hits = 0;
+            self.shake_since = None;
+        }
+        self.last_hit = Some(now);
+        self.shake_hits = self.shake_hits.saturating_add(1);
+        match self.shake_since {
+            None if self.shake_hits >= SHAKE_HITS => {
+                // 摇晃中的冲击不算敲击
+                self.last_tap = None;
+                self.shake_since = Some(now);
+                Some(Gesture::Shake)
+            }
+            Some(since) if now - since >= LONG_SHAKE => {
+                self.shake_since = Some(now);
+                Some(Gesture::LongShake)
+            }
+            _ => None,
+        }
+    }
+
+    /// 是否正在做手势,这时的倾斜不应该当成操作
+    pub fn busy(&self, now: u64) -> bool {
+        self.down_since.is_some()
+            || self.flipped_at.is_some_and(|t| now - t <= SETTLE)
+            || self.last_hit.is_some_and(|t| now - t <= SHAKE_GAP)
+    }
+
+    /// 清除进行中的手势,开始或恢复游戏时调用
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
@@ -0,0 +1,299 @@
+//! 磨损均衡的环形日志
+//!
+//! 同一个 flash 扇区反复擦写很快就会损坏,日志把记录依次追加在若干个扇区中,
+//! 当前扇区写满之后才擦除下一个(也就是最旧的)扇区继续写入,每个扇区的擦除次数相同.
+//!
+//! 每条记录都是一份完整的数据,读取时只需要序号最大的有效记录:
+//!
+//! ```text
+//! | magic "JR" | 长度 u16 | 序号 u32 | 数据 | crc32 u32 | 填充 0xff |
+//! ```
+//!
+//! 记录按照 flash 的读写粒度对齐,不会跨越扇区.
+//! 写入时断电只会留下一条校验失败的记录,上一条记录仍然完整,
+//! 擦除时断电只影响最旧的扇区,最新的记录在另一个扇区中.
+//! 遇到损坏的记录后这个扇区不再写入,下一次写入会换到新的扇区.
+
+use crate::storage::crc32;
+use alloc::vec::Vec;
+use embedded_storage::nor_flash::NorFlash;
+use log::error;
+
+const MAGIC: &[u8; 2] = b"JR";
+/// 记录头长度
+const HEADER_LEN: usize = 8;
+
+/// 日志错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalError<E> {
+    /// flash 读写错误
+    Flash(E),
+    /// 记录超过了一个扇区
+    TooLarge,
+}
+
+/// 记录的位置
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    seq: u32,
+    addr: u32,
+    len: usize,
+}
+
+pub struct Journal<F: NorFlash> {
+    flash: F,
+    /// 第一个扇区的地址
+    start: u32,
+    /// 扇区数量
+    sectors: u32,
+    /// 正在写入的扇区
+    active: u32,
+    /// 下一条记录在扇区中的偏移,`None` 表示这个扇区不能再写入
+    offset: Option<u32>,
+    latest: Option<Record>,
+}
+
+impl<F: NorFlash> Journal<F> {
+    /// 读写的对齐粒度
+    const ALIGN: usize = if F::READ_SIZE > F::WRITE_SIZE {
+        F::READ_SIZE
+    } else {
+        F::WRITE_SIZE
+    };
+    const SECTOR: u32 = F::ERASE_SIZE as u32;
+
+    /// 扫描所有扇区找到最新的记录,`start` 必须按扇区对齐,至少需要两个扇区
+    pub fn mount(flash: F, start: u32, sectors: u32) -> Self {
+        assert!(start.is_multiple_of(Self::SECTOR) && sectors >= 2);
+        assert!((start + sectors * Self::SECTOR) as usize <= flash.capacity());
+        let mut journal = Self {
+            flash,
+            start,
+            sectors,
+            active: 0,
+            offset: None,
+            latest: None,
+        };
+        let mut free = vec![None; sectors as usize];
+        for sector in 0..sectors {
+            free[sector as usize] = journal.scan(sector);
+        }
+        if let Some(latest) = journal.latest {
+            journal.active = journal.sector_of(latest.addr);
+            journal.offset = free[journal.active as usize];
+        } else {
+            // 没有任何记录时从一个空的扇区开始
+            journal.active = sectors - 1;
+        }
+        journal
+    }
+
+    /// 扫描一个扇区,返回空闲位置的偏移
+    fn scan(&mut self, sector: u32) -> Option<u32> {
+        let base = self.start + sector * Self::SECTOR;
+        let mut offset = 0;
+        while offset + Self::align(HEADER_LEN) as u32 <= Self::SECTOR {
+            let header = match self.read(base + offset, HEADER_LEN) {
+                Ok(header) => header,
+                Err(e) => {
+                    error!("read journal error: {e:?}");
+                    return None;
+                }
+            };
+            if header.iter().all(|b| *b == 0xff) {
+                return Some(offset);
+            }
+            let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+            let size = Self::align(HEADER_LEN + len + 4) as u32;
+            if &header[..2] != MAGIC || offset + size > Self::SECTOR {
+                return None;
+            }
+            let bytes = match self.read(base + offset, HEADER_LEN + len + 4) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    error!("read journal error: {e:?}");
+                    return None;
+                }
+            };
+            let end = HEADER_LEN + len;
+            let crc = u32::from_le_bytes(bytes[end..end + 4].try_into().unwrap());
+            if crc != crc32(&bytes[2..end]) {
+                return None;
+            }
+            let seq = u32::from_le_bytes(header[4..8].try_into().unwrap());
+            if self.latest.is_none_or(|r| seq > r.seq) {
+                self.latest = Some(Record {
+                    seq,
+                    addr: base + offset,
+                    len,
+                });
+            }
+            offset += size;
+        }
+        None
+    }
+
+    fn sector_of(&self, addr: u32) -> u32 {
+        (addr - self.start) / Self::SECTOR
+    }
+
+    fn align(len: usize) -> usize {
+        len.div_ceil(Self::ALIGN) * Self::ALIGN
+    }
+
+    /// 读取 `len` 个字节,`addr` 必须对齐
+    fn read(&mut self, addr: u32, len: usize) -> Result<Vec<u8>, F::Error> {
+        let mut bytes = vec![0; Self::align(len)];
+        self.flash.read(addr, &mut bytes)?;
+        bytes.truncate(len);
+        Ok(bytes)
+    }
+
+    /// 最新的一条记录
+    pub fn latest(&mut self) -> Result<Option<Vec<u8>>, JournalError<F::Error>> {
+        let Some(r) = self.latest else {
+            return Ok(None);
+        };
+        let bytes = self
+            .read(r.addr + HEADER_LEN as u32, r.len)
+            .map_err(JournalError::Flash)?;
+        Ok(Some(bytes))
+    }
+
+    /// 追加一条记录,当前扇区放不下时擦除下一个扇区
+    pub fn append(&mut self, data: &[u8]) -> Result<(), JournalError<F::Error>> {
+        let size = Self::align(HEADER_LEN + data.len() + 4);
+        if size > Self::SECTOR as usize || data.len() > u16::MAX as usize {
+            return Err(JournalError::TooLarge);
+        }
+        let offset = match self.offset {
+            Some(offset) if offset as usize + size <= Self::SECTOR as usize => offset,
+            _ => {
+                let mut next = (self.active + 1) % self.sectors;
+                // 当前扇区写入失败过时最新的记录可能在下一个扇区,这时擦除当前扇区
+                if self.latest.is_some_and(|r| self.sector_of(r.addr) == next) {
+                    next = self.active;
+                }
+                let from = self.start + next * Self::SECTOR;
+                // 擦除之前先放弃当前扇区,即使擦除时断电也不会再写入旧扇区
+                self.active = next;
+                self.offset = None;
+                self.flash
+                    .erase(from, from + Self::SECTOR)
+                    .map_err(JournalError::Flash)?;
+                0
+            }
+        };
+        let seq = self.latest.map_or(0, |r| r.seq + 1);
+        let mut bytes = Vec::with_capacity(size);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&seq.to_le_bytes());
+        bytes.extend_from_slice(data);
+        let crc = crc32(&bytes[2..]);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        bytes.resize(size, 0xff);
+
+        let addr = self.start + self.active * Self::SECTOR + offset;
+        // 写入失败时这个位置的内容未知,不再使用这个扇区
+        self.offset = None;
+        self.flash
+            .write(addr, &bytes)
+            .map_err(JournalError::Flash)?;
+        self.offset = Some(offset + size as u32);
+        self.latest = Some(Record {
+            seq,
+            addr,
+            len: data.len(),
+        });
+        Ok(())
+    }
+
+    pub fn flash(&mut self) -> &mut F {
+        &mut self.flash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::fake::FakeNorFlash;
+
+    const SECTORS: u32 = 3;
+    /// 每条记录的数据长度,一个扇区放 4 条
+    const LEN: usize = 1000;
+
+    fn mount(flash: FakeNorFlash) -> Journal<FakeNorFlash> {
+        Journal::mount(flash, 0, SECTORS)
+    }
+
+    fn record(n: u32) -> Vec<u8> {
+        n.to_le_bytes().repeat(LEN / 4)
+    }
+
+    #[test]
+    fn append_and_remount() {
+        let mut journal = mount(FakeNorFlash::new(SECTORS as usize * 4096));
+        assert_eq!(journal.latest(), Ok(None));
+        for n in 0..20 {
+            journal.append(&record(n)).unwrap();
+            assert_eq!(journal.latest(), Ok(Some(record(n))));
+            assert_eq!(mount(journal.flash().clone()).latest(), Ok(Some(record(n))));
+        }
+        assert_eq!(journal.append(&[0; 4096]), Err(JournalError::TooLarge));
+    }
+
+    #[test]
+    fn sectors_wear_evenly() {
+        let mut journal = mount(FakeNorFlash::new(SECTORS as usize * 4096));
+        for n in 0..100 {
+            journal.append(&record(n)).unwrap();
+        }
+        let erases = journal.flash().erases();
+        let (min, max) = (erases.iter().min().unwrap(), erases.iter().max().unwrap());
+        assert!(max - min <= 1, "{erases:?}");
+    }
+
+    /// 在写入记录和擦除扇区的各个阶段断电,重新挂载之后最新的记录是上一条成功写入的
+    #[test]
+    fn power_loss_keeps_last_record() {
+        let size = Journal::<FakeNorFlash>::align(HEADER_LEN + LEN + 4);
+        let mut journal = mount(FakeNorFlash::new(SECTORS as usize * 4096));
+        let mut half_erased = 0;
+        for n in 0..20u32 {
+            // 0 表示在擦除或者写入的一开始断电
+            for cut in [0, 4, HEADER_LEN, size / 2, size - 4] {
+                let mut flash = journal.flash().clone();
+                flash.cut_power_after(cut);
+                let mut crashed = mount(flash);
+                assert!(crashed.append(&record(1000)).is_err());
+
+                let mut flash = crashed.flash().clone();
+                flash.restore_power();
+                let mut recovered = mount(flash);
+                let last = n.checked_sub(1).map(record);
+                assert_eq!(recovered.latest(), Ok(last));
+
+                // 断电之后还能继续写入
+                recovered.append(&record(2000)).unwrap();
+                assert_eq!(
+                    mount(recovered.flash().clone()).latest(),
+                    Ok(Some(record(2000)))
+                );
+            }
+
+            // 擦除到一半时断电,不需要擦除时正常写入
+            let mut flash = journal.flash().clone();
+            flash.cut_power_during_erase();
+            let mut crashed = mount(flash);
+            if crashed.append(&record(1000)).is_err() {
+                let mut flash = crashed.flash().clone();
+                flash.restore_power();
+                assert_eq!(mount(flash).latest(), Ok(n.checked_sub(1).map(record)));
+                half_erased += 1;
+            }
+            journal.append(&record(n)).unwrap();
+        }
+        assert!(half_erased > 0);
+    }
+}
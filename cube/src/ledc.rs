@@ -1,11 +1,9 @@
-use crate::mapping;
+use crate::{driver::Matrix, mapping};
+use alloc::boxed::Box;
+use embassy_time::{Instant, Timer};
 use embedded_graphics::{pixelcolor::*, prelude::*};
-use esp_hal::{
-    peripherals::SPI2,
-    spi::{master::Spi, FullDuplexMode},
-};
+use embedded_hal::spi::SpiBus;
 use heapless::Vec;
-use log::error;
 use smart_leds_matrix::{
     layout::{invert_axis::NoInvert, Rectangular},
     SmartLedMatrix,
@@ -15,18 +13,76 @@ use ws2812_spi::Ws2812;
 /// led 数量
 const NUM_LEDS: usize = 64;
 
-pub struct LedControl<'d> {
-    matrix: SmartLedMatrix<Ws2812<Spi<'d, SPI2, FullDuplexMode>>, Rectangular<NoInvert>, NUM_LEDS>,
+/// 由 ws2812 组成的点阵
+pub type Ws2812Matrix<SPI> = SmartLedMatrix<Ws2812<SPI>, Rectangular<NoInvert>, NUM_LEDS>;
+
+/// 刷新到点阵的一帧画面
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// 刷新的时间,单位毫秒
+    pub at: u64,
+    /// 按行存储的像素
+    pub pixels: [Rgb888; NUM_LEDS],
+}
+
+pub struct LedControl<L: Matrix> {
+    matrix: L,
+    /// 点阵当前的画面
+    pixels: [Rgb888; NUM_LEDS],
+    /// 录制画面
+    capture: Option<Box<dyn FnMut(Frame)>>,
 }
 
-impl<'d> LedControl<'d> {
-    pub fn new(spi: Spi<'d, SPI2, FullDuplexMode>) -> Self {
+impl<SPI: SpiBus> LedControl<Ws2812Matrix<SPI>> {
+    pub fn new(spi: SPI) -> Self {
         let ws = Ws2812::new(spi);
-        let mut matrix = SmartLedMatrix::<_, _, { 8 * 8 }>::new(ws, Rectangular::new(8, 8));
+        Self::with_matrix(SmartLedMatrix::<_, _, { 8 * 8 }>::new(
+            ws,
+            Rectangular::new(8, 8),
+        ))
+    }
+}
+
+impl<L: Matrix> LedControl<L> {
+    pub fn with_matrix(mut matrix: L) -> Self {
         matrix.set_brightness(1);
-        matrix.clear(Rgb888::new(0, 0, 0)).unwrap();
+        matrix.clear(Rgb888::new(0, 0, 0));
+
+        Self {
+            matrix,
+            pixels: [Rgb888::BLACK; NUM_LEDS],
+            capture: None,
+        }
+    }
+
+    /// 开始录制,之后每一帧刷新到点阵的画面都会交给 `sink`
+    pub fn capture(&mut self, sink: impl FnMut(Frame) + 'static) {
+        self.capture = Some(Box::new(sink));
+    }
+
+    /// 停止录制
+    pub fn stop_capture(&mut self) {
+        self.capture = None;
+    }
+
+    /// 点阵当前的画面,按行存储
+    pub fn snapshot(&self) -> [Rgb888; NUM_LEDS] {
+        self.pixels
+    }
 
-        Self { matrix }
+    /// 重新显示 `snapshot` 得到的画面
+    pub fn restore(&mut self, pixels: [Rgb888; NUM_LEDS]) {
+        self.write_pixels(
+            pixels
+                .into_iter()
+                .enumerate()
+                .map(|(i, c)| Pixel(Point::new(i as i32 % 8, i as i32 / 8), c)),
+        );
+    }
+
+    /// 点阵
+    pub fn matrix(&self) -> &L {
+        &self.matrix
     }
 
     pub fn off(&mut self) {
@@ -45,9 +101,8 @@ impl<'d> LedControl<'d> {
 
     /// 清屏
     pub fn clear_with_color(&mut self, color: Rgb888) {
-        if let Err(e) = self.matrix.clear(color) {
-            error!("clear_with_color error {e:?}");
-        }
+        self.pixels = [color; NUM_LEDS];
+        self.matrix.clear(color);
     }
 
     pub fn write_bytes(&mut self, data: [u8; 8]) {
@@ -70,11 +125,19 @@ impl<'d> LedControl<'d> {
     where
         I: IntoIterator<Item = Pixel<Rgb888>>,
     {
-        if let Err(e) = self.matrix.draw_iter(pixels) {
-            error!("write pixels error: {e:?}");
-        }
-        if let Err(e) = self.matrix.flush() {
-            error!("write pixels error: {e:?}");
+        let buffer = &mut self.pixels;
+        self.matrix.draw(pixels.into_iter().inspect(|Pixel(p, c)| {
+            if (0..8).contains(&p.x) && (0..8).contains(&p.y) {
+                buffer[p.y as usize * 8 + p.x as usize] = *c;
+            }
+        }));
+        self.matrix.flush();
+
+        if let Some(capture) = self.capture.as_mut() {
+            capture(Frame {
+                at: Instant::now().as_millis(),
+                pixels: self.pixels,
+            });
         }
     }
 
@@ -82,6 +145,46 @@ impl<'d> LedControl<'d> {
         self.write_pixels([pixel]);
     }
 
+    /// 从右向左滚动显示数字,每 `ms` 毫秒移动一列,用于两位数放不下的分数
+    pub async fn scroll_number(&mut self, num: u32, ms: u64) {
+        // 每个数字宽 3 列,间隔 1 列,每列用一个字节表示,最低位为第一行
+        let mut cols = Vec::<u8, 40>::new();
+        let mut digits = Vec::<u8, 10>::new();
+        let mut n = num;
+        loop {
+            digits.push((n % 10) as u8).ok();
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+        for d in digits.iter().rev() {
+            let glyph = mapping::num_map(*d);
+            for x in 0..4 {
+                let col = (0..8)
+                    .filter(|y| glyph[*y] & (0x80 >> x) != 0)
+                    .fold(0u8, |c, y| c | (1 << y));
+                cols.push(col).ok();
+            }
+        }
+        // 从屏幕右边进入,从左边完全移出
+        for offset in 0..cols.len() + 8 {
+            let mut data = [0u8; 8];
+            for x in 0..8 {
+                let Some(col) = (offset + x).checked_sub(8).and_then(|i| cols.get(i)) else {
+                    continue;
+                };
+                for (y, row) in data.iter_mut().enumerate() {
+                    if col & (1 << y) != 0 {
+                        *row |= 0x80 >> x;
+                    }
+                }
+            }
+            self.write_bytes(data);
+            Timer::after_millis(ms).await;
+        }
+    }
+
     /// 绘制分数
     pub fn draw_score(&mut self, score: u8) {
         self.clear();
@@ -1,32 +1,32 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(extract_if)]
 #![allow(unused)]
 
 use crate::{dodge_cube::DodgeCubeGame, sokoban::Sokoban};
-use alloc::vec::Vec;
+use alloc::{boxed::Box, vec::Vec};
 use bagua::BaGua;
 use buzzer::Buzzer;
+use calibration::Calibration;
 use core::mem::MaybeUninit;
 use cube_man::CubeManGame;
 use cube_rand::CubeRng;
 use dice::Dice;
+use driver::{Accel, Entropy, Matrix, Microphone, Motion};
 use embassy_executor::Spawner;
 use embassy_time::Timer;
 use embedded_graphics_core::pixelcolor::Rgb888;
-use embedded_storage::{ReadStorage, Storage};
-use esp_hal::{i2c::I2C, rng::Rng, Blocking};
-use esp_storage::FlashStorage;
+use embedded_storage::nor_flash::NorFlash;
 use face::Face;
+use gesture::{Gesture, Gestures};
 use ledc::LedControl;
-use log::info;
-use maze::Maze;
-use mpu6050_dmp::{
-    accel::{AccelF32, AccelFullScale},
-    sensor::Mpu6050,
-};
+use log::{error, info};
+use maze::{Maze, SIZES};
+use music_spectrum::MusicSpectrum;
 use snake::SnakeGame;
-use timers::Timers;
+use storage::SettingsStore;
+use tilt::{Press, TiltInput};
+use timers::{TimerMode, Timers};
 use ui::Ui;
 
 #[macro_use]
@@ -35,24 +35,33 @@ extern crate alloc;
 pub mod bagua;
 pub mod battery;
 pub mod buzzer;
+pub mod calibration;
 pub mod cube_man;
 pub mod dice;
 pub mod dodge_cube;
+pub mod driver;
 pub mod face;
+pub mod gesture;
+pub mod journal;
 pub mod ledc;
 pub mod map;
 pub mod mapping;
 pub mod maze;
+pub mod music_spectrum;
 pub mod player;
+pub mod replay;
 pub mod snake;
 pub mod sokoban;
+pub mod storage;
+#[cfg(test)]
+mod testing;
+pub mod tilt;
 pub mod timers;
 pub mod ui;
 
 pub type CubeColor = Rgb888;
-pub static mut RNG: MaybeUninit<Rng> = MaybeUninit::uninit();
+pub static mut RNG: MaybeUninit<Box<dyn Entropy>> = MaybeUninit::uninit();
 pub static mut BUZZER: MaybeUninit<Buzzer> = MaybeUninit::uninit();
-pub static mut LEDCTL: MaybeUninit<LedControl> = MaybeUninit::uninit();
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Direction {
@@ -84,6 +93,18 @@ pub enum Gd {
     Left,
 }
 
+impl Gd {
+    fn opposite(&self) -> Self {
+        match self {
+            Gd::None => Self::None,
+            Gd::Up => Self::Down,
+            Gd::Right => Self::Left,
+            Gd::Down => Self::Up,
+            Gd::Left => Self::Right,
+        }
+    }
+}
+
 impl core::fmt::Display for Gd {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
@@ -108,9 +129,10 @@ impl From<Direction> for Gd {
 }
 
 /// 小方
-pub struct App<'d, T>
+pub struct App<M, L>
 where
-    T: esp_hal::i2c::Instance,
+    M: Motion,
+    L: Matrix,
 {
     /// 界面
     uis: Vec<Ui>,
@@ -119,51 +141,45 @@ where
     /// 表情
     face: Face,
     gd: Gd,
+    /// 倾斜输入
+    tilt: TiltInput,
+    /// 游戏中的手势
+    gestures: Gestures,
 
-    mpu6050: Mpu6050<I2C<'d, T, Blocking>>,
-    ledc: LedControl<'d>,
+    motion: M,
+    ledc: LedControl<L>,
+    /// 麦克风,没有时频谱界面直接返回
+    mic: Option<Box<dyn Microphone>>,
     spawner: Spawner,
+    /// 累计的暂停时间,ms,按逻辑时钟计算
+    paused: u64,
+    /// 逻辑时钟,ms,只在检测手势的等待中按等待的时间前进,
+    /// 和读取的样本数量一一对应,回放相同的样本得到相同的手势
+    clock: u64,
+    /// 传感器校准偏移
+    calibration: Calibration,
 }
 
-impl<'d, T> App<'d, T>
+impl<M, L> App<M, L>
 where
-    T: esp_hal::i2c::Instance,
+    M: Motion,
+    L: Matrix,
 {
     fn gravity_direction(&mut self) {
-        let accel = self.accel();
-        let ax = accel.x();
-        let ay = accel.y();
-
-        let ax_abs = if ax <= 0.0 { 0.0 - ax } else { ax };
-        let ay_abs = if ay <= 0.0 { 0.0 - ay } else { ay };
-        if ax_abs > 0.5 || ay_abs > 0.5 {
-            if ax_abs > ay_abs {
-                if ax < -0.5 {
-                    self.gd = Gd::Right;
-                }
-                if ax > 0.5 {
-                    self.gd = Gd::Left;
-                }
-            }
+        self.press();
+    }
 
-            if ax_abs < ay_abs {
-                if ay < -0.5 {
-                    self.gd = Gd::Up;
-                }
-                if ay > 0.5 {
-                    self.gd = Gd::Down;
-                }
-            }
-        } else {
-            self.gd = Gd::None;
-        }
+    /// 读取倾斜并更新重力方向,按下或者自动重复时返回
+    ///
+    /// 自动重复按逻辑时钟计时,回放相同的样本得到相同的按键
+    fn press(&mut self) -> Option<Press> {
+        let accel = self.accel();
+        let press = self.tilt.update(accel, self.clock);
+        self.gd = self.tilt.gd();
+        press
     }
 
-    pub fn new(
-        mpu6050: Mpu6050<I2C<'d, T, Blocking>>,
-        mut ledc: LedControl<'d>,
-        spawner: Spawner,
-    ) -> Self {
+    pub fn new(motion: M, mut ledc: LedControl<L>, spawner: Spawner) -> Self {
         ledc.set_brightness(0x01);
 
         App {
@@ -171,81 +187,273 @@ where
             ui_current_idx: 0,
             face: Face::default(),
             gd: Gd::default(),
+            tilt: TiltInput::default(),
+            gestures: Gestures::default(),
 
-            mpu6050,
+            motion,
             ledc,
+            mic: None,
             spawner,
+            paused: 0,
+            clock: 0,
+            calibration: Calibration::default(),
         }
     }
 
-    pub fn accel(&mut self) -> AccelF32 {
-        self.mpu6050.accel().unwrap().scaled(AccelFullScale::G2)
+    /// 接入麦克风
+    pub fn with_microphone(mut self, mic: impl Microphone + 'static) -> Self {
+        self.mic = Some(Box::new(mic));
+        self
     }
 
-    pub async fn run(mut self) -> ! {
-        let flash_addr = 0x9100;
-        let mut flash = FlashStorage::new();
-        let mut flash_data = [0u8; 8];
-        flash.read(flash_addr, &mut flash_data).ok();
-        info!(
-            "Read flash data from {:x}:  {:02x?}",
-            flash_addr,
-            &flash_data[..8]
-        );
+    /// 减去校准偏移之后的加速度
+    pub fn accel(&mut self) -> Accel {
+        self.calibration.accel(self.motion.accel())
+    }
+
+    /// 使用设置中保存的校准偏移,由 `Motion` 决定实际使用的偏移
+    fn set_calibration(&mut self, stored: Calibration) {
+        self.calibration = self.motion.calibration(stored);
+    }
+
+    /// 游戏每一帧的等待,期间检测手势,返回 `true` 时游戏需要退出
+    ///
+    /// 暂停时显示暂停图,恢复后重新显示暂停前的画面,暂停的时间不计入等待时间.
+    pub async fn wait(&mut self, ms: u64) -> bool {
+        self.wait_until(ms, false).await.is_some()
+    }
 
+    /// 和 `wait` 相同,但是识别出摇晃或翻转时立即返回,需要退出时返回 `Gesture::Exit`
+    pub async fn wait_gesture(&mut self, ms: u64) -> Option<Gesture> {
+        self.wait_until(ms, true).await
+    }
+
+    /// `report` 为 `false` 时忽略游戏自己处理的手势,只会返回 `Gesture::Exit`
+    async fn wait_until(&mut self, ms: u64, report: bool) -> Option<Gesture> {
+        let mut waited = 0;
         loop {
-            Timer::after_millis(500).await;
+            let accel = self.accel();
+            match self.gestures.update(accel, self.clock) {
+                Some(Gesture::Exit) => {
+                    unsafe { BUZZER.assume_init_mut().exit().await };
+                    self.gestures.reset();
+                    return Some(Gesture::Exit);
+                }
+                Some(Gesture::Pause) => {
+                    if self.pause().await {
+                        return Some(Gesture::Exit);
+                    }
+                    waited = 0;
+                }
+                Some(g) if report => return Some(g),
+                _ => {}
+            }
 
-            self.gravity_direction();
+            if waited >= ms {
+                return None;
+            }
+            let step = (ms - waited).min(gesture::TICK);
+            Timer::after_millis(step).await;
+            waited += step;
+            self.clock += step;
+        }
+    }
 
-            if self.gd == Gd::default() {
-                self.ledc
-                    .write_bytes(self.uis[self.ui_current_idx as usize].ui());
-                continue;
+    /// 等待一次检测手势的间隔,逻辑时钟前进 `gesture::TICK`
+    async fn tick(&mut self) {
+        Timer::after_millis(gesture::TICK).await;
+        self.clock += gesture::TICK;
+    }
+
+    /// 逻辑时钟上不包括暂停的时间,ms,用于游戏计时,回放时得到相同的用时
+    pub fn play_millis(&self) -> u64 {
+        self.clock - self.paused
+    }
+
+    /// 是否正在做手势,这时的倾斜不应该当成操作
+    pub fn gesturing(&self) -> bool {
+        self.gestures.busy(self.clock)
+    }
+
+    /// 暂停,直到恢复或退出,返回 `true` 表示退出
+    async fn pause(&mut self) -> bool {
+        unsafe { BUZZER.assume_init_mut().pause().await };
+        let frame = self.ledc.snapshot();
+        self.ledc.write_bytes(mapping::PAUSE);
+        let start = self.clock;
+        loop {
+            self.tick().await;
+            let accel = self.accel();
+            match self.gestures.update(accel, self.clock) {
+                Some(Gesture::Exit) => {
+                    unsafe { BUZZER.assume_init_mut().exit().await };
+                    self.gestures.reset();
+                    self.paused += self.clock - start;
+                    return true;
+                }
+                Some(Gesture::Pause) => {
+                    unsafe { BUZZER.assume_init_mut().pause().await };
+                    self.ledc.restore(frame);
+                    self.paused += self.clock - start;
+                    return false;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// 子菜单的选择,从 `start` 开始,左右倾斜切换,按住时自动重复,向上确认,向下返回 `None`
+    ///
+    /// 需要先回到水平才会响应,避免进入菜单时的倾斜被当成确认.
+    pub async fn choose(
+        &mut self,
+        len: usize,
+        start: usize,
+        draw: impl Fn(&mut Self, usize),
+    ) -> Option<usize> {
+        let mut idx = start.min(len - 1);
+        let mut armed = false;
+        draw(self, idx);
+        loop {
+            self.tick().await;
+            let press = self.press();
+            if self.gd == Gd::None {
+                armed = true;
             }
+            let Some(Press { gd, repeat }) = press.filter(|_| armed) else {
+                continue;
+            };
 
-            match self.gd {
-                // 向上进入对应的界面
+            match gd {
+                // 确认和返回不自动重复
+                Gd::None => {}
+                Gd::Up | Gd::Down if repeat => {}
                 Gd::Up => {
                     unsafe { BUZZER.assume_init_mut().menu_confirm().await };
+                    self.gd = Gd::default();
+                    return Some(idx);
+                }
+                Gd::Down => {
+                    unsafe { BUZZER.assume_init_mut().menu_access().await };
+                    self.gd = Gd::default();
+                    return None;
+                }
+                Gd::Right => {
+                    idx = (idx + 1) % len;
+                    draw(self, idx);
+                    unsafe { BUZZER.assume_init_mut().menu_select().await };
+                }
+                Gd::Left => {
+                    idx = (idx + len - 1) % len;
+                    draw(self, idx);
+                    unsafe { BUZZER.assume_init_mut().menu_select().await };
+                }
+            }
+        }
+    }
+
+    /// 点阵
+    pub fn ledc(&self) -> &LedControl<L> {
+        &self.ledc
+    }
+
+    pub async fn run<F: NorFlash>(mut self, flash: F) -> ! {
+        let mut store = SettingsStore::new(flash);
+        let mut settings = store.load();
+        let mut saved = settings;
+        info!("Load settings: {settings:?}");
+
+        self.ledc.set_brightness(settings.brightness);
+        self.set_calibration(settings.calibration);
+        unsafe { BUZZER.assume_init_mut().open = settings.sound };
+        if (settings.last_menu as usize) < self.uis.len() {
+            self.ui_current_idx = settings.last_menu as i8;
+        }
+
+        self.ledc
+            .write_bytes(self.uis[self.ui_current_idx as usize].ui());
+        loop {
+            self.tick().await;
+
+            let Some(Press { gd, repeat }) = self.press() else {
+                continue;
+            };
+
+            match gd {
+                // 向上进入对应的界面,按住不放不会再次进入
+                Gd::Up if !repeat => {
+                    unsafe { BUZZER.assume_init_mut().menu_confirm().await };
+                    self.gestures.reset();
+                    settings.last_menu = self.ui_current_idx as u8;
                     match self.uis[self.ui_current_idx as usize] {
-                        Ui::Timer => Timers::default().run(&mut self).await,
-                        Ui::MusicSpectrum => {
-                            // 麦克风采集信息，通过fft转换
+                        Ui::Timer => {
+                            if let Some((mode, minutes)) = Timers::menu(&mut self).await {
+                                let mut timers = Timers::new(minutes);
+                                match mode {
+                                    TimerMode::Drop => timers.run(&mut self).await,
+                                    TimerMode::Sand => timers.run_sand(&mut self).await,
+                                }
+                            }
                         }
+                        Ui::MusicSpectrum => MusicSpectrum::default().run(&mut self).await,
                         Ui::Dice => Dice.run(&mut self).await,
                         Ui::Snake => {
-                            let mut snake = SnakeGame::new();
-                            // 最高分从flash中获取
-                            snake.highest = flash_data[0x00];
-                            snake.run(&mut self).await;
-                            // 游戏结束将最高分再次写入flash
-                            flash_data[0x00] = snake.highest;
-                            flash.write(flash_addr, &flash_data).ok();
+                            if let Some(mode) = SnakeGame::menu(&mut self).await {
+                                let mut snake = SnakeGame::with_mode(mode);
+                                snake.highest = settings.highest.snake[mode as usize];
+                                snake.run(&mut self).await;
+                                settings.highest.snake[mode as usize] = snake.highest;
+                            }
                         }
                         Ui::BaGua => BaGua::run(&mut self).await,
                         Ui::Maze => {
-                            let mut cr = unsafe {
-                                CubeRng(RNG.assume_init_mut().random() as u64).random_range(19..=33)
-                            };
-                            if cr % 2 == 0 {
-                                cr += 1;
+                            if let Some((mode, size)) = Maze::menu(&mut self).await {
+                                let mut maze = Maze::new(SIZES[size], SIZES[size]);
+                                maze.mode = mode;
+                                maze.best = settings.maze[size];
+                                maze.run(&mut self).await;
+                                settings.maze[size] = maze.best;
                             }
-                            Maze::new(cr, cr).run(&mut self).await;
                         }
                         Ui::CubeMan => {
                             let mut cm = CubeManGame::new();
-                            // 最高分从flash中获取
-                            cm.highest = flash_data[0x01];
+                            cm.highest = settings.highest.cube_man;
                             cm.run(&mut self).await;
-                            // 游戏结束将最高分再次写入flash
-                            flash_data[0x01] = cm.highest;
-                            flash.write(flash_addr, &flash_data).ok();
+                            settings.highest.cube_man = cm.highest;
+                        }
+                        Ui::Sokoban => {
+                            let mut sokoban = Sokoban::new();
+                            sokoban.unlocked = settings.sokoban;
+                            sokoban.run(&mut self).await;
+                            settings.sokoban = sokoban.unlocked;
+                        }
+                        Ui::DodgeCube => {
+                            let mut dc = DodgeCubeGame::new();
+                            dc.highest = settings.highest.dodge_cube;
+                            dc.run(&mut self).await;
+                            settings.highest.dodge_cube = dc.highest;
                         }
-                        Ui::Sokoban => Sokoban::new().run(&mut self).await,
-                        Ui::DodgeCube => DodgeCubeGame::new().run(&mut self).await,
-                        Ui::Sound => unsafe { BUZZER.assume_init_mut().change() },
+                        Ui::Sound => {
+                            let buzzer = unsafe { BUZZER.assume_init_mut() };
+                            buzzer.change();
+                            settings.sound = buzzer.open;
+                        }
+                        Ui::Calibration => {
+                            if let Some(calibration) = Calibration::run(&mut self).await {
+                                self.calibration = calibration;
+                                settings.calibration = calibration;
+                            }
+                        }
+                    }
+                    // 设置有变化时才写入flash
+                    if settings != saved {
+                        if let Err(e) = store.save(&settings) {
+                            error!("save settings error: {e:?}");
+                        }
+                        saved = settings;
                     }
+                    self.ledc
+                        .write_bytes(self.uis[self.ui_current_idx as usize].ui());
                 }
                 Gd::Right => {
                     self.ui_current_idx += 1;
@@ -265,10 +473,7 @@ where
                         .write_bytes(self.uis[self.ui_current_idx as usize].ui());
                     unsafe { BUZZER.assume_init_mut().menu_select().await };
                 }
-                _ => {
-                    self.ledc
-                        .write_bytes(self.uis[self.ui_current_idx as usize].ui());
-                }
+                _ => {}
             }
         }
     }
@@ -1,23 +1,18 @@
 #![no_std]
 #![no_main]
-#![feature(core_intrinsics)]
 #![allow(unused)]
 
-use alloc::vec::Vec;
-use core::f32::consts::PI;
-use core::intrinsics::roundf32;
 use core::mem::MaybeUninit;
 use cube::buzzer::Buzzer;
+use cube::driver::{EspMicrophone, EspTone};
 use cube::ledc::LedControl;
 use embassy_executor::Spawner;
-use embassy_time::Timer;
 use esp_backtrace as _;
 use esp_hal::analog::adc::{Adc, AdcConfig, Attenuation};
 use esp_hal::clock::Clocks;
 use esp_hal::delay::Delay;
 use esp_hal::gpio::Io;
 use esp_hal::ledc::{LSGlobalClkSource, Ledc};
-use esp_hal::peripherals::ADC1;
 use esp_hal::spi::master::Spi;
 use esp_hal::spi::SpiMode;
 use esp_hal::system::SystemControl;
@@ -26,9 +21,6 @@ use esp_hal::{clock::ClockControl, i2c::I2C, peripherals::Peripherals, prelude::
 use log::info;
 use mpu6050_dmp::address::Address;
 use mpu6050_dmp::sensor::Mpu6050;
-use spectrum_analyzer::scaling::{self, divide_by_N_sqrt};
-use spectrum_analyzer::windows::{hamming_window, hann_window};
-use spectrum_analyzer::{samples_fft_to_spectrum, FrequencyLimit};
 
 extern crate alloc;
 
@@ -76,7 +68,7 @@ async fn main(spawner: Spawner) {
 
     let mut ledc = Ledc::new(peripherals.LEDC, clocks);
     ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
-    let buzzer = Buzzer::new(io.pins.gpio11, ledc, spawner);
+    let buzzer = Buzzer::new(EspTone::new(io.pins.gpio11, ledc), spawner);
     unsafe { cube::BUZZER.write(buzzer) };
 
     let i2c = I2C::new(
@@ -95,82 +87,15 @@ async fn main(spawner: Spawner) {
     let ledc = LedControl::new(spi);
 
     let rng = esp_hal::rng::Rng::new(peripherals.RNG);
-    unsafe { cube::RNG.write(rng) };
+    unsafe { cube::RNG.write(alloc::boxed::Box::new(rng)) };
 
     let mut adc1_config = AdcConfig::new();
-    let mut adc1_pin = adc1_config.enable_pin(io.pins.gpio1, Attenuation::Attenuation11dB);
-    let mut adc1 = Adc::new(peripherals.ADC1, adc1_config);
-
-    let mut samples = alloc::vec![0.0;64];
-    loop {
-        for sample in samples.iter_mut().take(64) {
-            let data = nb::block!(adc1.read_oneshot(&mut adc1_pin)).unwrap();
-            // *sample = data as f32 * 1.1 / 4095.;
-            *sample = data as f32;
-            Timer::after_micros(250).await;
-        }
-        // let hann_window = hamming_window(&samples);
-        let hann_window = hann_window(&samples);
-        let spectrum_hann_window = samples_fft_to_spectrum(
-            &hann_window,
-            8000,
-            // FrequencyLimit::Min(80.),
-            FrequencyLimit::All,
-            Some(&scaling::divide_by_N_sqrt),
-        )
-        .unwrap();
-
-        info!("ADC reading: {samples:?}");
-
-        // 频率（Hz），频谱中的频率值（幅度）
-        for (fr, fr_val) in spectrum_hann_window.data().iter() {
-            let maped = map_range(fr.val(), 80., 4000., 0., 8.);
-            info!("{fr}Hz => {fr_val}, maped: {maped},round: {:?}", unsafe {
-                roundf32(maped)
-            });
-        }
-        Timer::after_millis(1000).await;
-    }
-
-    // cube::App::new(mpu, ledc, spawner).run().await;
+    let adc1_pin = adc1_config.enable_pin(io.pins.gpio1, Attenuation::Attenuation11dB);
+    let adc1 = Adc::new(peripherals.ADC1, adc1_config);
+    let mic = EspMicrophone::new(adc1, adc1_pin);
+
+    cube::App::new(mpu, ledc, spawner)
+        .with_microphone(mic)
+        .run(esp_storage::FlashStorage::new())
+        .await;
 }
-
-fn map_range(x: f32, in_min: f32, in_max: f32, out_min: f32, out_max: f32) -> f32 {
-    (x - in_min) * (out_max - out_min) / (in_max - in_min) + out_min
-}
-
-// void displayUpdate(){
-//   color = 0;
-//   for(int i = 0; i < xres; i++){
-//     for(int j = 0; j < yres; j++){
-//       if(j <= Intensity[i]){                                // Light everything within the intensity range
-// //        if(j%2 == 0){
-// //          leds[(xres*(j+1))-i-1] = CHSV(color, 255, BRIGHTNESS);
-// //        }
-// //        else{
-// //          leds[(xres*j)+i] = CHSV(color, 255, BRIGHTNESS);
-// //        }
-//         if(j>freq_block[i]){
-//           freq_block[i] = min(j+1,8);
-//         }
-// leds[(xres*j)+i] = CHSV(color, 255, BRIGHTNESS);
-//       }
-//       else{                                                  // Everything outside the range goes dark
-// //        if(j%2 == 0){
-// //          leds[(xres*(j+1))-i-1] = CHSV(color, 255, 0);
-// //        }
-// //        else{
-// //          leds[(xres*j)+i] = CHSV(color, 255, 0);
-// //        }
-//         if(j == freq_block[i]){
-//           leds[(xres*j)+i] = CHSV(color, 0, BRIGHTNESS);//白色坠落点
-//         }else{
-//           leds[(xres*j)+i] = CHSV(color, 255, 0);
-//         }
-//
-//       }
-//     }
-//     color += 255/xres;             // Increment the Hue to get the Rainbow
-//
-//   }
-// }
@@ -1,6 +1,5 @@
 use core::fmt::Display;
 
-use crate::Gd;
 use alloc::vec::Vec;
 use embedded_graphics::{geometry::Point, pixelcolor::Rgb888, Pixel};
 
@@ -27,6 +26,133 @@ impl<T> Map<T> {
     }
 }
 
+/// 跟随玩家的镜头,`W`*`H` 为视野大小
+///
+/// 玩家在视野中间的死区内移动时镜头不动,走出死区时镜头跟着移动,
+/// 镜头不会超出地图,地图比视野小时镜头停在原点.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Camera<const W: usize, const H: usize> {
+    /// 视野左上角坐标
+    pub pos: Point,
+    /// 镜头要移动到的位置
+    target: Point,
+    /// 地图宽度
+    width: usize,
+    /// 地图长度
+    height: usize,
+    /// 死区的宽和高
+    dead_zone: (usize, usize),
+    /// 每帧最多移动的格数,`None` 时直接移动到目标位置
+    speed: Option<u32>,
+}
+
+impl<const W: usize, const H: usize> Camera<W, H> {
+    /// 默认的死区为视野的四分之一,不插值
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            pos: Point::zero(),
+            target: Point::zero(),
+            width,
+            height,
+            dead_zone: ((W / 4).max(1), (H / 4).max(1)),
+            speed: None,
+        }
+    }
+
+    /// 设置死区的大小,范围为 1 到视野大小
+    pub fn with_dead_zone(mut self, width: usize, height: usize) -> Self {
+        self.dead_zone = (width.clamp(1, W), height.clamp(1, H));
+        self
+    }
+
+    /// 每帧最多移动 `cells` 格,逐帧移动到目标位置
+    pub fn with_speed(mut self, cells: u32) -> Self {
+        self.speed = Some(cells.max(1));
+        self
+    }
+
+    /// 死区左上角在视野中的坐标
+    fn margin(&self) -> Point {
+        Point::new(
+            ((W - self.dead_zone.0) / 2) as i32,
+            ((H - self.dead_zone.1) / 2) as i32,
+        )
+    }
+
+    /// 镜头直接对准玩家,玩家在死区的左上角
+    pub fn look_at(&mut self, player: Point) {
+        self.pos = player - self.margin();
+        self.follow(player);
+        self.pos = self.target;
+    }
+
+    /// 根据玩家的位置计算镜头的目标位置,需要调用 `step` 移动镜头
+    pub fn follow(&mut self, player: Point) {
+        let margin = self.margin();
+        self.target = Point::new(
+            axis(
+                self.pos.x,
+                player.x,
+                margin.x,
+                self.dead_zone.0,
+                W,
+                self.width,
+            ),
+            axis(
+                self.pos.y,
+                player.y,
+                margin.y,
+                self.dead_zone.1,
+                H,
+                self.height,
+            ),
+        );
+    }
+
+    /// 向目标位置移动一帧,镜头移动时返回 `true`
+    pub fn step(&mut self) -> bool {
+        let old = self.pos;
+        self.pos = match self.speed {
+            None => self.target,
+            Some(speed) => {
+                let d = self.target - self.pos;
+                let speed = speed as i32;
+                self.pos + Point::new(d.x.clamp(-speed, speed), d.y.clamp(-speed, speed))
+            }
+        };
+        self.pos != old
+    }
+
+    /// 镜头是否已经到达目标位置
+    pub fn settled(&self) -> bool {
+        self.pos == self.target
+    }
+
+    /// 地图上的点是否在视野内
+    pub fn contains(&self, p: Point) -> bool {
+        let Point { x, y } = p - self.pos;
+        (0..W as i32).contains(&x) && (0..H as i32).contains(&y)
+    }
+}
+
+/// 一个方向上镜头的目标位置
+///
+/// 玩家在 `[pos + margin, pos + margin + dead)` 之外时移动镜头,结果限制在 `[0, map - view]` 之内
+fn axis(pos: i32, player: i32, margin: i32, dead: usize, view: usize, map: usize) -> i32 {
+    if map <= view {
+        return 0;
+    }
+    let far = margin + dead as i32 - 1;
+    let pos = if player - pos < margin {
+        player - margin
+    } else if player - pos > far {
+        player - far
+    } else {
+        pos
+    };
+    pos.clamp(0, (map - view) as i32)
+}
+
 /// 视野
 #[derive(Debug)]
 pub struct Vision<const W: usize, const H: usize, T> {
@@ -34,6 +160,8 @@ pub struct Vision<const W: usize, const H: usize, T> {
     pub pos: Point,
     /// 视野数据
     pub data: Vec<MapCell<T>>,
+    /// 跟随玩家的镜头
+    camera: Camera<W, H>,
 }
 
 impl<const W: usize, const H: usize, T> Display for Vision<W, H, T> {
@@ -50,99 +178,166 @@ impl<const W: usize, const H: usize, T> Display for Vision<W, H, T> {
 }
 
 impl<const W: usize, const H: usize, T: Clone> Vision<W, H, T> {
-    /// 初始化视野
+    /// 初始化视野,使用默认的镜头
     pub fn new(width: usize, height: usize, player: Point) -> Self {
+        Self::with_camera(Camera::new(width, height), player)
+    }
+
+    /// 使用指定的镜头初始化视野,镜头直接对准玩家
+    pub fn with_camera(mut camera: Camera<W, H>, player: Point) -> Self {
+        camera.look_at(player);
         Self {
-            pos: {
-                let x = {
-                    if player.x - 3 <= 0 || width < W {
-                        0
-                    } else if player.x + 5 >= width as i32 {
-                        player.x - W as i32 + width as i32 - player.x
-                    } else {
-                        player.x - 3
-                    }
-                };
-                let y = {
-                    if player.y - 3 <= 0 || height < H {
-                        0
-                    } else if player.y + 5 >= height as i32 {
-                        player.y - H as i32 + height as i32 - player.y
-                    } else {
-                        player.y - 3
-                    }
-                };
-                Point::new(x, y)
-            },
+            pos: camera.pos,
             data: Vec::new(),
+            camera,
         }
     }
 
-    /// 视野下一个位置
-    pub fn next_pos(&self, gd: Gd) -> Point {
-        let mut pos = self.pos;
-        match gd {
-            Gd::None => {}
-            Gd::Up => pos.y -= 1,
-            Gd::Right => pos.x += 1,
-            Gd::Down => pos.y += 1,
-            Gd::Left => pos.x -= 1,
-        };
-        pos
+    /// 根据视野位置更新视野数据
+    pub fn update_data(&mut self, map: &Map<T>) {
+        let Point { x, y } = self.pos;
+        self.data = map
+            .data
+            .iter()
+            .filter(|d| {
+                d.0 .0.x >= x && d.0 .0.x < x + W as i32 && d.0 .0.y >= y && d.0 .0.y < y + H as i32
+            })
+            .cloned()
+            .collect::<Vec<_>>();
     }
 
-    /// 移动视野
-    pub fn r#move(&mut self, gd: Gd) {
-        self.pos = self.next_pos(gd);
+    /// 根据玩家的位置设置镜头的目标位置,玩家移动之后调用
+    pub fn follow(&mut self, player: Point) {
+        self.camera.follow(player);
     }
 
-    /// 根据视野位置更新视野数据
-    pub fn update_data(&mut self, map: &Map<T>) {
-        let Point { x, y } = self.pos;
-        let data = map.data.iter();
-        if map.width < W && map.height < H {
-            self.data.clone_from(&map.data);
-        } else if map.width < W {
-            self.data = data
-                .filter(|d| d.0 .0.y >= y && d.0 .0.y < y + H as i32)
-                .cloned()
-                .collect::<Vec<_>>();
-        } else if map.height < H {
-            self.data = data
-                .filter(|d| d.0 .0.x >= x && d.0 .0.x < x + W as i32)
-                .cloned()
-                .collect::<Vec<_>>();
-        } else {
-            self.data = data
-                .filter(|d| {
-                    d.0 .0.x >= x
-                        && d.0 .0.x < x + W as i32
-                        && d.0 .0.y >= y
-                        && d.0 .0.y < y + H as i32
-                })
-                .cloned()
-                .collect::<Vec<_>>();
+    /// 镜头向目标位置移动一帧,视野位置改变时更新视野数据并返回 `true`
+    ///
+    /// 每帧调用,镜头设置了速度时逐帧移动到目标位置
+    pub fn step(&mut self, map: &Map<T>) -> bool {
+        if !self.camera.step() {
+            return false;
+        }
+        self.pos = self.camera.pos;
+        self.update_data(map);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::pixelcolor::RgbColor;
+
+    type View = Camera<8, 8>;
+
+    /// 跟随玩家并移动到目标位置
+    fn walk(camera: &mut View, player: Point) -> Point {
+        camera.follow(player);
+        while camera.step() {}
+        camera.pos
+    }
+
+    #[test]
+    fn map_smaller_than_view() {
+        let mut camera = View::new(5, 5);
+        camera.look_at(Point::new(4, 4));
+        assert_eq!(camera.pos, Point::zero());
+        for p in [(0, 0), (4, 0), (0, 4), (4, 4), (2, 2)] {
+            assert_eq!(walk(&mut camera, p.into()), Point::zero());
         }
+        // 只有一个方向比视野小
+        let mut camera = View::new(20, 8);
+        camera.look_at(Point::new(19, 7));
+        assert_eq!(camera.pos, Point::new(12, 0));
     }
 
-    /// 改变视野位置
-    pub fn update(&mut self, gd: Gd, map: &Map<T>) {
-        let Point { x, y } = self.next_pos(gd);
-        let overlapping = {
-            if map.width < W && map.height < H {
-                true
-            } else if map.width < W {
-                x < 0 || y < 0 || x > map.width as i32 - 7 || y >= map.height as i32 - 7
-            } else if map.height < H {
-                x < 0 || y < 0 || x >= map.width as i32 - 7 || y > map.height as i32 - 7
-            } else {
-                x < 0 || y < 0 || x >= map.width as i32 - 7 || y >= map.height as i32 - 7
+    #[test]
+    fn player_at_edges() {
+        let mut camera = View::new(20, 20);
+        camera.look_at(Point::zero());
+        assert_eq!(camera.pos, Point::zero());
+        camera.look_at(Point::new(19, 19));
+        assert_eq!(camera.pos, Point::new(12, 12));
+        // 从一边走到另一边,玩家一直在视野内,镜头不超出地图
+        camera.look_at(Point::new(0, 10));
+        for x in 0..20 {
+            let pos = walk(&mut camera, Point::new(x, 10));
+            assert!((0..=12).contains(&pos.x), "{x}: {pos:?}");
+            assert!(camera.contains(Point::new(x, 10)));
+        }
+        assert_eq!(camera.pos.x, 12);
+        for x in (0..20).rev() {
+            walk(&mut camera, Point::new(x, 10));
+            assert!(camera.contains(Point::new(x, 10)));
+        }
+        assert_eq!(camera.pos.x, 0);
+    }
+
+    #[test]
+    fn dead_zone_boundaries() {
+        // 默认死区为 2*2,左上角在视野的 (3, 3)
+        let mut camera = View::new(20, 20);
+        camera.look_at(Point::new(10, 10));
+        assert_eq!(camera.pos, Point::new(7, 7));
+        // 死区内不动
+        assert_eq!(walk(&mut camera, Point::new(11, 11)), Point::new(7, 7));
+        // 走出死区时镜头跟着移动一格
+        assert_eq!(walk(&mut camera, Point::new(12, 11)), Point::new(8, 7));
+        assert_eq!(walk(&mut camera, Point::new(11, 12)), Point::new(8, 8));
+        // 回到死区的边缘不动
+        assert_eq!(walk(&mut camera, Point::new(11, 11)), Point::new(8, 8));
+        assert_eq!(walk(&mut camera, Point::new(10, 11)), Point::new(7, 8));
+
+        // 死区和视野一样大时只在走出视野时移动
+        let mut camera = View::new(20, 20).with_dead_zone(100, 100);
+        camera.look_at(Point::new(5, 5));
+        assert_eq!(camera.pos, Point::new(5, 5));
+        assert_eq!(walk(&mut camera, Point::new(12, 12)), Point::new(5, 5));
+        assert_eq!(walk(&mut camera, Point::new(13, 5)), Point::new(6, 5));
+        assert_eq!(walk(&mut camera, Point::new(5, 4)), Point::new(5, 4));
+
+        // 死区至少为一格
+        let mut camera = View::new(20, 20).with_dead_zone(0, 0);
+        camera.look_at(Point::new(10, 10));
+        assert_eq!(walk(&mut camera, Point::new(11, 9)), Point::new(8, 6));
+    }
+
+    #[test]
+    fn speed_moves_every_frame() {
+        let mut camera = View::new(40, 40).with_speed(2);
+        camera.look_at(Point::new(3, 3));
+        assert_eq!(camera.pos, Point::zero());
+        camera.follow(Point::new(20, 3));
+        // 目标为 20 - 4,每帧两格
+        let mut frames = 0;
+        while camera.step() {
+            frames += 1;
+            assert!(camera.pos.x <= 16);
+        }
+        assert_eq!(frames, 8);
+        assert!(camera.settled());
+        assert_eq!(camera.pos, Point::new(16, 0));
+    }
+
+    #[test]
+    fn vision_steps_data() {
+        let mut map = Map::new(20, 20);
+        for y in 0..20 {
+            for x in 0..20 {
+                map.data.push((Pixel(Point::new(x, y), Rgb888::WHITE), ()));
             }
-        };
-        if overlapping {
-            return;
         }
-        self.r#move(gd);
-        self.update_data(map);
+        let camera = Camera::new(20, 20).with_speed(1);
+        let mut vision = Vision::<8, 8, ()>::with_camera(camera, Point::new(3, 3));
+        vision.update_data(&map);
+        assert_eq!(vision.pos, Point::zero());
+        vision.follow(Point::new(6, 3));
+        assert!(vision.step(&map));
+        assert!(vision.step(&map));
+        assert!(!vision.step(&map));
+        assert_eq!(vision.pos, Point::new(2, 0));
+        assert_eq!(vision.data.len(), 64);
+        assert!(vision.data.iter().all(|c| c.0 .0.x >= 2 && c.0 .0.x < 10));
     }
 }
@@ -141,6 +141,19 @@ pub const ALL_ON: [u8; 8] = [
     0b11111111,
 ];
 
+/// 暂停图
+#[rustfmt::skip]
+pub const PAUSE: [u8; 8] = [
+    0b00000000,
+    0b01100110,
+    0b01100110,
+    0b01100110,
+    0b01100110,
+    0b01100110,
+    0b01100110,
+    0b00000000,
+];
+
 pub const fn num_map(num: u8) -> [u8; 8] {
     match num {
         1 => NUM_1,
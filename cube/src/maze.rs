@@ -1,7 +1,9 @@
 #![doc = include_str!("../../rfcs/005_maze.md")]
 
 use crate::{
-    map::{Map, Vision},
+    driver::{Matrix, Motion},
+    gesture::Gesture,
+    map::{Camera, Map, Vision},
     player::Player,
     App, CubeRng, Gd, BUZZER, RNG,
 };
@@ -13,6 +15,7 @@ use embedded_graphics_core::{
     prelude::{RgbColor, WebColors},
     Pixel,
 };
+use maze::Algorithm;
 
 /// 迷宫
 /// 左上角为坐标原点,所有的坐标都为全局坐标
@@ -25,55 +28,155 @@ pub struct Maze {
     /// ms
     waiting_time: u64,
     game_over: bool,
+    pub mode: MazeMode,
+    /// 见过的格子,按行保存,只在迷雾模式中使用
+    seen: Vec<bool>,
+    /// 开始的时间,ms,不包括暂停
+    start: u64,
+    /// 这一局的用时,秒
+    seconds: u16,
+    /// 这个大小的最短用时,秒,0 表示没有记录
+    pub best: u16,
 }
 
+/// 迷宫模式
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MazeMode {
+    /// 视野中的迷宫都可见
+    #[default]
+    Open,
+    /// 只能看到玩家附近的格子,见过的墙变暗
+    Fog,
+}
+
+impl MazeMode {
+    #[rustfmt::skip]
+    fn ui(&self) -> [u8; 8] {
+        match self {
+            MazeMode::Open => [
+                0b00000000,
+                0b01010110,
+                0b01011010,
+                0b01000010,
+                0b00111010,
+                0b00100010,
+                0b01101110,
+                0b00000000,
+            ],
+            MazeMode::Fog => [
+                0b00000000,
+                0b00010000,
+                0b00011000,
+                0b01000010,
+                0b00111010,
+                0b00100000,
+                0b00001000,
+                0b00000000,
+            ],
+        }
+    }
+}
+
+/// 可以选择的迷宫大小,宽高相同
+pub const SIZES: [usize; 8] = [19, 21, 23, 25, 27, 29, 31, 33];
+/// 提示时显示的步数
+const HINT_STEPS: usize = 5;
+/// 打通死路的最大比例,太多时迷宫过于简单
+const MAX_BRAID: u32 = 50;
+/// 迷雾中玩家四周能看到的半径
+const FOG_RADIUS: i32 = 2;
+/// 迷雾中见过但是现在看不到的墙的颜色
+const FOG_COLOR: Rgb888 = Rgb888::new(0x20, 0x20, 0x20);
+
 impl Maze {
+    /// 先选择模式,再选择大小,返回模式和 `SIZES` 中的索引,向下返回 `None`
+    pub async fn menu<M: Motion, L: Matrix>(app: &mut App<M, L>) -> Option<(MazeMode, usize)> {
+        let modes = [MazeMode::Open, MazeMode::Fog];
+        loop {
+            let mode = app
+                .choose(modes.len(), 0, |app, i| {
+                    app.ledc.write_bytes(modes[i].ui());
+                })
+                .await?;
+            let Some(size) = app
+                .choose(SIZES.len(), 0, |app, i| {
+                    app.ledc.draw_score(SIZES[i] as u8);
+                })
+                .await
+            else {
+                continue;
+            };
+            return Some((modes[mode], size));
+        }
+    }
+
+    /// 起点和终点在最长的最短路径的两端
     pub fn new(width: usize, height: usize) -> Self {
         let map = MazeMap::new(width, height);
-        // FIXME: 随机玩家坐标,这里可能导致起始位置较近，后续使用路径算法生成
-        let pp = loop {
-            let pp = unsafe {
-                Point {
-                    x: CubeRng(RNG.assume_init_mut().random() as u64).random_range(1..width) as i32,
-                    y: CubeRng(RNG.assume_init_mut().random() as u64).random_range(1..height)
-                        as i32,
-                }
+        // 生成的迷宫至少有一格路
+        let (a, b) = map.maze.farthest_pair().unwrap();
+        // 随机选择从哪一端出发
+        let (spos, epos) =
+            if unsafe { CubeRng(RNG.assume_init_mut().random() as u64).random(0, 2) } == 0 {
+                (a, b)
+            } else {
+                (b, a)
             };
-            let md = map
-                .map
-                .data
-                .iter()
-                .any(|c| c.0 .0.x == pp.x && c.0 .0.y == pp.y);
-            if !md {
-                break pp;
-            }
-        };
-        let player = Player::new(pp);
-        let mut vision = Vision::new(width, height, player.pos);
+        Self::with_map(map, to_point(spos), to_point(epos))
+    }
+
+    fn with_map(mut map: MazeMap, spos: Point, epos: Point) -> Self {
+        map.spos = spos;
+        map.epos = epos;
+        let player = Player::new(spos);
+        // 大迷宫中镜头每帧最多移动一格
+        let camera = Camera::new(map.map.width, map.map.height).with_speed(1);
+        let mut vision = Vision::with_camera(camera, player.pos);
         vision.update_data(&map.map);
-        let mut maze = Maze {
+        let seen = vec![false; map.map.width * map.map.height];
+        Maze {
             map,
             player,
             vision,
             waiting_time: 300,
             game_over: false,
-        };
-        maze.map.spos = player.pos;
-        maze.map.cal_epos();
-        maze
+            mode: MazeMode::default(),
+            seen,
+            start: 0,
+            seconds: 0,
+            best: 0,
+        }
     }
 
-    pub async fn run<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<'_, T>) {
+    pub async fn run<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
         app.ledc.clear();
         app.gd = Gd::default();
+        self.draw(app);
+        self.start = app.play_millis();
 
         loop {
-            Timer::after_millis(self.waiting_time).await;
+            // 倾斜越大移动越快,最快只等一半的时间
+            let wait = self.waiting_time as f32 * (1.0 - app.tilt.strength() / 2.0);
+            match app.wait_gesture(wait as u64).await {
+                Some(Gesture::Exit) => break,
+                // 翻转提示接下来的几步
+                Some(Gesture::Flip) => {
+                    self.hint(app).await;
+                    continue;
+                }
+                // 做手势时的倾斜不移动
+                _ if app.gesturing() => continue,
+                _ => {}
+            }
 
             if self.game_over {
-                // TODO: 结束动画
                 unsafe { BUZZER.assume_init_mut().maze_over().await };
-                Timer::after_millis(3000).await;
+                self.show_time(app).await;
+                if self.best == 0 || self.seconds < self.best {
+                    self.best = self.seconds;
+                    app.face.break_record_animate(&mut app.ledc).await;
+                }
+                Timer::after_millis(500).await;
                 break;
             }
             app.gravity_direction();
@@ -82,12 +185,15 @@ impl Maze {
                 let moved = self.player.r#move(app.gd);
                 if moved {
                     unsafe { BUZZER.assume_init_mut().maze_move().await };
-                    // 玩家移动之后视野数据改变
-                    self.vision.update(app.gd, &self.map.map);
+                    // 玩家移动之后镜头跟随
+                    self.vision.follow(self.player.pos);
                     // 游戏结束
                     if self.player.pos.x == self.map.epos.x && self.player.pos.y == self.map.epos.y
                     {
                         self.game_over = true;
+                        // 不到 1 秒也记为 1 秒,0 表示没有记录
+                        let seconds = (app.play_millis() - self.start) / 1000;
+                        self.seconds = seconds.clamp(1, u16::MAX as u64) as u16;
                     }
                 }
             }
@@ -95,21 +201,77 @@ impl Maze {
         }
     }
 
-    fn draw<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<T>) {
-        app.ledc.clear_with_color(BinaryColor::Off.into());
-        let vp = self.vision.pos;
-        let mut pixels = self
-            .map
+    /// 依次显示用时的分钟和秒
+    async fn show_time<M: Motion, L: Matrix>(&self, app: &mut App<M, L>) {
+        log::info!("maze finished in {}s, best {}s", self.seconds, self.best);
+        // 超过两位数的分钟滚动显示
+        let minutes = self.seconds / 60;
+        if minutes >= 100 {
+            app.ledc.scroll_number(minutes as u32, 150).await;
+        } else {
+            app.ledc.draw_score(minutes as u8);
+            Timer::after_millis(1000).await;
+        }
+        app.ledc.draw_score((self.seconds % 60) as u8);
+        Timer::after_millis(1500).await;
+    }
+
+    /// 从玩家的位置沿着最短路径逐格点亮接下来的几步
+    async fn hint<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
+        let Some(path) = self
             .map
-            .data
+            .maze
+            .shortest_path(to_cell(self.player.pos), to_cell(self.map.epos))
+        else {
+            return;
+        };
+        let steps = path
             .iter()
-            .map(|m| m.0)
-            .clone()
+            .skip(1)
+            .take(HINT_STEPS)
+            .map(|c| to_point(*c))
             .collect::<Vec<_>>();
-        // 将全局坐标转换为led坐标
-        for d in pixels.iter_mut() {
-            d.0.x -= vp.x;
-            d.0.y -= vp.y;
+        for i in 1..=steps.len() {
+            self.draw(app);
+            let vp = self.vision.pos;
+            app.ledc.write_pixels(
+                steps[..i]
+                    .iter()
+                    .map(|p| Pixel(*p - vp, Rgb888::CSS_MAGENTA)),
+            );
+            Timer::after_millis(150).await;
+        }
+        Timer::after_millis(300).await;
+        self.draw(app);
+    }
+
+    /// 画一帧,镜头先向目标位置移动一帧
+    fn draw<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
+        self.vision.step(&self.map.map);
+        app.ledc.clear_with_color(BinaryColor::Off.into());
+        let vp = self.vision.pos;
+        let mut pixels = Vec::new();
+        for (Pixel(p, color), _) in self.map.map.data.iter() {
+            let color = match self.mode {
+                MazeMode::Open => *color,
+                MazeMode::Fog => {
+                    let Point { x, y } = *p - vp;
+                    if !(0..8).contains(&x) || !(0..8).contains(&y) {
+                        continue;
+                    }
+                    let idx = p.y as usize * self.map.map.width + p.x as usize;
+                    if visible(&self.map.maze, self.player.pos, *p) {
+                        self.seen[idx] = true;
+                        *color
+                    } else if self.seen[idx] {
+                        FOG_COLOR
+                    } else {
+                        continue;
+                    }
+                }
+            };
+            // 将全局坐标转换为led坐标
+            pixels.push(Pixel(*p - vp, color));
         }
         // 终点
         let pp = {
@@ -129,7 +291,7 @@ impl Maze {
     }
 
     /// 检测是否撞墙
-    fn hit_wall<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<T>) -> bool {
+    fn hit_wall<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) -> bool {
         let Point { x, y } = self.player.next_pos(app.gd);
         let overlapping = x <= 0
             || y <= 0
@@ -151,6 +313,8 @@ impl Maze {
 #[derive(Debug)]
 struct MazeMap {
     map: Map<()>,
+    /// 生成的迷宫,用于寻路
+    maze: maze::Maze,
     /// 地图颜色
     color: Rgb888,
     /// 起点
@@ -163,15 +327,19 @@ struct MazeMap {
 
 impl MazeMap {
     fn new(width: usize, height: usize) -> Self {
-        // 使用地图生成算法生成地图 TODO: 迷宫大小,使用的算法都随机
+        // 随机选择生成算法,再随机打通一部分死路
+        let mut rng = unsafe { CubeRng(RNG.assume_init_mut().random() as u64) };
+        let algorithm = pick_algorithm(&mut rng);
+        let braid = rng.random(0, MAX_BRAID + 1) as u8;
         let maze = maze::Maze::new(width, height)
             .unwrap()
-            .generate(&mut unsafe { CubeRng(RNG.assume_init_mut().random() as u64) });
-        log::info!("\n{maze}\n");
+            .generate_with(algorithm, &mut rng)
+            .braid(braid, &mut rng);
+        log::info!("{algorithm:?}, braid {braid}%\n{maze}\n");
         let mut map = Map::new(width, height);
         for y in 0..height {
             for x in 0..width {
-                if maze[y][x] == 1 {
+                if maze.is_wall(x, y) {
                     map.data
                         .push((Pixel((x as i32, y as i32).into(), Rgb888::CSS_WHITE), ()));
                 }
@@ -179,32 +347,60 @@ impl MazeMap {
         }
         Self {
             map,
+            maze,
             color: Rgb888::WHITE,
             spos: Point::default(),
             epos: Point::default(),
             color_epos: Rgb888::CSS_GREEN,
         }
     }
+}
+
+/// 随机选择一种生成算法
+fn pick_algorithm(rng: &mut CubeRng) -> Algorithm {
+    Algorithm::ALL[rng.random_range(0..Algorithm::ALL.len())]
+}
+
+/// 迷雾中玩家能否看到 `p`: 在半径之内,或者和玩家在同一行或同一列并且中间没有墙
+fn visible(maze: &maze::Maze, player: Point, p: Point) -> bool {
+    let d = p - player;
+    if d.x * d.x + d.y * d.y <= FOG_RADIUS * FOG_RADIUS {
+        return true;
+    }
+    if d.x != 0 && d.y != 0 {
+        return false;
+    }
+    let step = Point::new(d.x.signum(), d.y.signum());
+    let mut c = player + step;
+    while c != p {
+        if maze.is_wall(c.x as usize, c.y as usize) {
+            return false;
+        }
+        c += step;
+    }
+    true
+}
 
-    /// 计算结束位置
-    fn cal_epos(&mut self) {
-        let pos = loop {
-            let x = unsafe {
-                CubeRng(RNG.assume_init_mut().random() as u64).random_range(0..self.map.width - 1)
-            } as i32;
+fn to_cell(p: Point) -> maze::Cell {
+    (p.x as usize, p.y as usize)
+}
 
-            let y = unsafe {
-                CubeRng(RNG.assume_init_mut().random() as u64).random_range(0..self.map.height - 1)
-            } as i32;
+fn to_point((x, y): maze::Cell) -> Point {
+    Point::new(x as i32, y as i32)
+}
 
-            if self.map.data.iter().any(|c| c.0 .0.x == x && c.0 .0.y == y)
-                || (self.spos.x == x && self.spos.y == y)
-            {
-                continue;
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            break (x, y);
-        };
-        self.epos = pos.into();
+    #[test]
+    fn every_algorithm_picked() {
+        let mut rng = CubeRng(1);
+        let mut seen = [false; Algorithm::ALL.len()];
+        for _ in 0..500 {
+            let algorithm = pick_algorithm(&mut rng);
+            seen[Algorithm::ALL.iter().position(|a| *a == algorithm).unwrap()] = true;
+        }
+        assert!(seen.iter().all(|s| *s), "{seen:?}");
     }
 }
@@ -0,0 +1,276 @@
+#![doc = include_str!("../../rfcs/009_music_spectrum.md")]
+
+use crate::{
+    driver::{Matrix, Microphone, Motion},
+    App,
+};
+use alloc::vec::Vec;
+use embassy_time::{Duration, Ticker};
+use embedded_graphics::{geometry::Point, pixelcolor::Rgb888, Pixel};
+use spectrum_analyzer::{
+    samples_fft_to_spectrum, scaling::divide_by_N_sqrt, windows::hann_window, FrequencyLimit,
+};
+
+/// 采样率,Hz
+pub const SAMPLE_RATE: u32 = 4000;
+/// 每帧的采样数量,必须是 2 的幂
+pub const SAMPLES: usize = 256;
+/// 频带数量,每一列一个
+pub const BANDS: usize = 8;
+/// 频带的边界,Hz,从 100Hz 到奈奎斯特频率按对数等分: `100 * 20^(i/8)`
+pub const BAND_EDGES: [f32; BANDS + 1] = [
+    100.0, 145.4, 211.5, 307.5, 447.2, 650.3, 945.7, 1375.2, 2000.0,
+];
+/// 每帧之间检测手势的时间,ms
+const FRAME: u64 = 20;
+/// 峰值点停留的帧数
+const PEAK_HOLD: u8 = 6;
+
+/// 把频谱按频率合并到各个频带,每个频带取最大的幅度
+///
+/// `spectrum` 为 (频率 Hz, 幅度),超出 `BAND_EDGES` 范围的频率被忽略.
+pub fn bands(spectrum: &[(f32, f32)]) -> [f32; BANDS] {
+    let mut bands = [0.0; BANDS];
+    for (fr, val) in spectrum {
+        let Some(i) = BAND_EDGES
+            .windows(2)
+            .position(|e| e[0] <= *fr && *fr < e[1])
+        else {
+            continue;
+        };
+        if *val > bands[i] {
+            bands[i] = *val;
+        }
+    }
+    bands
+}
+
+/// 自动增益: 以最近的最大幅度作为满格,安静时缓慢提高增益
+#[derive(Debug, Clone, Copy)]
+pub struct Agc {
+    /// 满格对应的幅度
+    level: f32,
+}
+
+impl Default for Agc {
+    fn default() -> Self {
+        Self {
+            level: Self::MIN_LEVEL,
+        }
+    }
+}
+
+impl Agc {
+    /// 最小的满格幅度,避免把噪声放大到满格
+    pub const MIN_LEVEL: f32 = 50.0;
+    /// 每帧的衰减
+    const DECAY: f32 = 0.95;
+
+    /// 把各个频带的幅度换算成 0 到 8 的高度
+    pub fn apply(&mut self, bands: [f32; BANDS]) -> [u8; BANDS] {
+        let max = bands.iter().fold(0.0f32, |m, v| m.max(*v));
+        self.level = (self.level * Self::DECAY).max(max).max(Self::MIN_LEVEL);
+        bands.map(|v| ((v / self.level * 8.0 + 0.5) as u8).min(8))
+    }
+}
+
+/// 下落的峰值点
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Peaks {
+    /// 峰值点所在的高度
+    pub heights: [u8; BANDS],
+    /// 峰值点还要停留的帧数
+    hold: [u8; BANDS],
+}
+
+impl Peaks {
+    /// 柱子超过峰值点时把峰值点顶上去,否则停留一会儿之后每帧下落一格
+    pub fn update(&mut self, heights: [u8; BANDS]) {
+        for (i, h) in heights.into_iter().enumerate() {
+            if h >= self.heights[i] {
+                self.heights[i] = h;
+                self.hold[i] = PEAK_HOLD;
+            } else if self.hold[i] > 0 {
+                self.hold[i] -= 1;
+            } else {
+                self.heights[i] -= 1;
+            }
+        }
+    }
+}
+
+/// 色环上的颜色,`hue` 从 0 到 255 依次为红,绿,蓝
+pub fn hue(hue: u8) -> Rgb888 {
+    let h = hue as u16 * 3;
+    let (section, offset) = ((h >> 8) as u8, (h & 0xff) as u8);
+    let (rise, fall) = (offset, 255 - offset);
+    match section {
+        0 => Rgb888::new(fall, rise, 0),
+        1 => Rgb888::new(0, fall, rise),
+        _ => Rgb888::new(rise, 0, fall),
+    }
+}
+
+/// 绘制彩虹色的柱子和白色的峰值点,柱子从底部向上
+pub fn render(heights: [u8; BANDS], peaks: &Peaks) -> Vec<Pixel<Rgb888>> {
+    let mut pixels = Vec::with_capacity(64);
+    for x in 0..BANDS {
+        let color = hue((x * 255 / BANDS) as u8);
+        // 峰值点在柱子的上方一格,满格时在最上面一格,为 0 时不显示
+        let peak = (peaks.heights[x] > 0).then(|| peaks.heights[x].min(7));
+        for level in 0..8 {
+            let p = Point::new(x as i32, 7 - level as i32);
+            let c = if level < heights[x] {
+                color
+            } else if Some(level) == peak {
+                Rgb888::new(0xff, 0xff, 0xff)
+            } else {
+                Rgb888::new(0, 0, 0)
+            };
+            pixels.push(Pixel(p, c));
+        }
+    }
+    pixels
+}
+
+/// 去掉直流分量,加窗之后做 fft,返回 (频率 Hz, 幅度)
+pub fn spectrum(samples: &[f32]) -> Vec<(f32, f32)> {
+    let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+    let samples = samples.iter().map(|v| v - mean).collect::<Vec<_>>();
+    let Ok(spectrum) = samples_fft_to_spectrum(
+        &hann_window(&samples),
+        SAMPLE_RATE,
+        FrequencyLimit::Range(BAND_EDGES[0], BAND_EDGES[BANDS]),
+        Some(&divide_by_N_sqrt),
+    ) else {
+        return Vec::new();
+    };
+    spectrum
+        .data()
+        .iter()
+        .map(|(fr, val)| (fr.val(), val.val()))
+        .collect()
+}
+
+/// 音乐频谱
+#[derive(Debug, Default)]
+pub struct MusicSpectrum {
+    agc: Agc,
+    peaks: Peaks,
+}
+
+impl MusicSpectrum {
+    pub async fn run<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
+        app.ledc.clear();
+        let mut samples = [0.0; SAMPLES];
+        // 没有麦克风时直接返回菜单
+        while let Some(mic) = app.mic.as_mut() {
+            let mut ticker = Ticker::every(Duration::from_hz(SAMPLE_RATE as u64));
+            for sample in samples.iter_mut() {
+                *sample = mic.sample() as f32;
+                ticker.next().await;
+            }
+
+            let heights = self.agc.apply(bands(&spectrum(&samples)));
+            self.peaks.update(heights);
+            app.ledc.write_pixels(render(heights, &self.peaks));
+
+            if app.wait(FRAME).await {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::PI;
+
+    const WHITE: Rgb888 = Rgb888::new(0xff, 0xff, 0xff);
+
+    /// 频率为 `freq` Hz 的正弦波,叠加在麦克风的直流偏置上
+    fn sine(freq: f32, amp: f32) -> [f32; SAMPLES] {
+        core::array::from_fn(|i| {
+            2048.0 + amp * (2.0 * PI * freq * i as f32 / SAMPLE_RATE as f32).sin()
+        })
+    }
+
+    fn loudest(bands: [f32; BANDS]) -> usize {
+        (0..BANDS)
+            .max_by(|a, b| bands[*a].total_cmp(&bands[*b]))
+            .unwrap()
+    }
+
+    /// 第 `x` 列从下往上第 `level` 格的颜色
+    fn color(pixels: &[Pixel<Rgb888>], x: i32, level: i32) -> Rgb888 {
+        let p = Point::new(x, 7 - level);
+        pixels.iter().find(|Pixel(q, _)| *q == p).unwrap().1
+    }
+
+    #[test]
+    fn sine_in_its_band() {
+        for (freq, band) in [
+            (130.0, 0),
+            (250.0, 2),
+            (400.0, 3),
+            (800.0, 5),
+            (1000.0, 6),
+            (1700.0, 7),
+        ] {
+            let b = bands(&spectrum(&sine(freq, 500.0)));
+            assert_eq!(loudest(b), band, "{freq} Hz");
+        }
+    }
+
+    #[test]
+    fn loud_sine_fills_column() {
+        let mut agc = Agc::default();
+        let heights = agc.apply(bands(&spectrum(&sine(1000.0, 500.0))));
+        assert_eq!(heights[6], 8);
+        assert!(heights.iter().enumerate().all(|(i, h)| i == 6 || *h < 8));
+    }
+
+    #[test]
+    fn quiet_sine_not_amplified() {
+        // 低于 `Agc::MIN_LEVEL` 的声音不会放大到满格
+        let mut agc = Agc::default();
+        let heights = agc.apply(bands(&spectrum(&sine(1000.0, 5.0))));
+        assert!(heights[6] < 8);
+    }
+
+    #[test]
+    fn silence_is_dark() {
+        let mut agc = Agc::default();
+        let mut peaks = Peaks::default();
+        let heights = agc.apply(bands(&spectrum(&[2048.0; SAMPLES])));
+        assert_eq!(heights, [0; BANDS]);
+        peaks.update(heights);
+        let pixels = render(heights, &peaks);
+        assert!(pixels.iter().all(|Pixel(_, c)| *c == Rgb888::new(0, 0, 0)));
+    }
+
+    #[test]
+    fn peak_holds_then_falls() {
+        let mut peaks = Peaks::default();
+        let mut heights = [0; BANDS];
+        heights[0] = 5;
+        heights[1] = 8;
+        peaks.update(heights);
+        let pixels = render([0, 3, 0, 0, 0, 0, 0, 0], &peaks);
+        assert_eq!(color(&pixels, 0, 5), WHITE);
+        assert_eq!(color(&pixels, 1, 7), WHITE);
+        assert_eq!(color(&pixels, 1, 2), hue(255 / BANDS as u8));
+
+        for _ in 0..PEAK_HOLD {
+            peaks.update([0; BANDS]);
+        }
+        assert_eq!(peaks.heights[0], 5);
+        for _ in 0..5 {
+            peaks.update([0; BANDS]);
+        }
+        assert_eq!(peaks.heights[0], 0);
+        let pixels = render([0; BANDS], &peaks);
+        assert_eq!(color(&pixels, 0, 0), Rgb888::new(0, 0, 0));
+    }
+}
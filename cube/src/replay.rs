@@ -0,0 +1,514 @@
+//! 输入录制与回放
+//!
+//! 游戏的随机性只来自 `RNG`,输入只来自 [`Motion`],
+//! 所以记录下随机数种子和游戏读取到的每一个加速度和角速度样本,就能让游戏重新走出完全相同的画面.
+//! `Gd` 由加速度计算得到,回放加速度即可得到相同的 `Gd`.
+//!
+//! 样本在记录时就被量化,游戏读到的是量化后的值,因此回放和录制时的输入完全一致.
+//! 相同的样本连续出现时只记录一次和次数,平放不动时日志几乎不增长.
+//!
+//! 记录的是传感器的原始读数,`App` 读取时再减去校准偏移,所以开机时的加速度偏移也记录在日志中,
+//! 回放时使用录制时的偏移.之后重新校准时读取的加速度和角速度都在日志中,
+//! 回放时静止检查的结果和偏移都和录制时相同.
+
+use crate::{
+    calibration::Calibration,
+    driver::{Accel, Gyro, Motion},
+    RNG,
+};
+use alloc::{boxed::Box, vec::Vec};
+use cube_rand::CubeRng;
+
+/// 日志文件头
+const MAGIC: &[u8; 4] = b"CUBE";
+/// 日志格式版本
+const VERSION: u8 = 1;
+/// 文件头长度: 文件头,版本,种子,加速度偏移,加速度的段数
+const HEADER_LEN: usize = 4 + 1 + 8 + 12 + 4;
+/// 量化精度,每 g 的刻度数,i8 可以表示 ±2g
+const SCALE: f32 = 64.0;
+/// 角速度的量化精度,每 °/s 的刻度数,i16 可以表示 ±2048°/s
+const GYRO_SCALE: f32 = 16.0;
+
+/// 日志解析错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    /// 文件头不正确
+    Magic,
+    /// 不支持的版本
+    Version(u8),
+    /// 数据不完整
+    Truncated,
+}
+
+/// 量化后的加速度样本
+type Sample = [i8; 3];
+/// 量化后的角速度样本
+type GyroSample = [i16; 3];
+
+fn quantize(accel: Accel) -> Sample {
+    let q = |v: f32| (v * SCALE).clamp(i8::MIN as f32, i8::MAX as f32) as i8;
+    [q(accel.x()), q(accel.y()), q(accel.z())]
+}
+
+fn dequantize(sample: Sample) -> Accel {
+    let d = |v: i8| v as f32 / SCALE;
+    Accel::new(d(sample[0]), d(sample[1]), d(sample[2]))
+}
+
+fn quantize_gyro(gyro: Gyro) -> GyroSample {
+    let q = |v: f32| (v * GYRO_SCALE).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+    [q(gyro.x()), q(gyro.y()), q(gyro.z())]
+}
+
+fn dequantize_gyro(sample: GyroSample) -> Gyro {
+    let d = |v: i16| v as f32 / GYRO_SCALE;
+    Gyro::new(d(sample[0]), d(sample[1]), d(sample[2]))
+}
+
+/// 追加一个样本,和上一段相同时只增加次数
+fn push_run<T: PartialEq>(runs: &mut Vec<(u8, T)>, sample: T) {
+    match runs.last_mut() {
+        Some((n, s)) if *s == sample && *n < u8::MAX => *n += 1,
+        _ => runs.push((1, sample)),
+    }
+}
+
+/// 按顺序展开所有样本
+fn expand<T: Copy>(runs: &[(u8, T)]) -> impl Iterator<Item = T> + '_ {
+    runs.iter()
+        .flat_map(|(n, s)| core::iter::repeat_n(*s, *n as usize))
+}
+
+/// 输入日志
+///
+/// 加速度和角速度分别按读取的顺序记录,游戏只读取加速度,校准时还会读取角速度
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputLog {
+    /// 随机数种子
+    pub seed: u64,
+    /// 开机时的加速度偏移,单位 g
+    pub offset: [f32; 3],
+    /// 加速度,(连续次数, 样本)
+    runs: Vec<(u8, Sample)>,
+    /// 角速度,(连续次数, 样本)
+    gyro: Vec<(u8, GyroSample)>,
+}
+
+impl InputLog {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            offset: [0.0; 3],
+            runs: Vec::new(),
+            gyro: Vec::new(),
+        }
+    }
+
+    /// 记录一个样本,返回量化后的值,游戏应该使用返回值
+    pub fn record(&mut self, accel: Accel) -> Accel {
+        let sample = quantize(accel);
+        push_run(&mut self.runs, sample);
+        dequantize(sample)
+    }
+
+    /// 记录一个角速度样本,返回量化后的值
+    pub fn record_gyro(&mut self, gyro: Gyro) -> Gyro {
+        let sample = quantize_gyro(gyro);
+        push_run(&mut self.gyro, sample);
+        dequantize_gyro(sample)
+    }
+
+    /// 加速度样本数量
+    pub fn len(&self) -> usize {
+        self.runs.iter().map(|(n, _)| *n as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty() && self.gyro.is_empty()
+    }
+
+    /// 按顺序遍历所有加速度样本
+    pub fn samples(&self) -> impl Iterator<Item = Accel> + '_ {
+        expand(&self.runs).map(dequantize)
+    }
+
+    /// 按顺序遍历所有角速度样本
+    pub fn gyro_samples(&self) -> impl Iterator<Item = Gyro> + '_ {
+        expand(&self.gyro).map(dequantize_gyro)
+    }
+
+    /// 序列化,整数和浮点数均为小端:
+    ///
+    /// 文件头,版本,种子,3 个 f32 的加速度偏移,加速度的段数 u32,
+    /// 之后每 4 个字节为一段加速度: 次数,x,y,z,最后每 7 个字节为一段角速度: 次数,x,y,z(i16)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.runs.len() * 4 + self.gyro.len() * 7);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        for v in self.offset {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.runs.len() as u32).to_le_bytes());
+        for (n, s) in self.runs.iter() {
+            bytes.push(*n);
+            bytes.extend(s.iter().map(|v| *v as u8));
+        }
+        for (n, s) in self.gyro.iter() {
+            bytes.push(*n);
+            for v in s {
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReplayError> {
+        if bytes.len() >= 4 && &bytes[..4] != MAGIC {
+            return Err(ReplayError::Magic);
+        }
+        if bytes.len() < HEADER_LEN {
+            return Err(ReplayError::Truncated);
+        }
+        if bytes[4] != VERSION {
+            return Err(ReplayError::Version(bytes[4]));
+        }
+        let seed = u64::from_le_bytes(bytes[5..13].try_into().unwrap());
+        let mut offset = [0.0; 3];
+        for (v, b) in offset.iter_mut().zip(bytes[13..25].chunks(4)) {
+            *v = f32::from_le_bytes(b.try_into().unwrap());
+        }
+        let count = u32::from_le_bytes(bytes[25..HEADER_LEN].try_into().unwrap()) as usize;
+        let body = &bytes[HEADER_LEN..];
+        let accel_len = count.checked_mul(4).filter(|len| *len <= body.len());
+        let Some(accel_len) = accel_len else {
+            return Err(ReplayError::Truncated);
+        };
+        let (accel, gyro) = body.split_at(accel_len);
+        if gyro.len() % 7 != 0 {
+            return Err(ReplayError::Truncated);
+        }
+        let runs = accel
+            .chunks(4)
+            .filter(|c| c[0] > 0)
+            .map(|c| (c[0], [c[1] as i8, c[2] as i8, c[3] as i8]))
+            .collect();
+        let i16 = |b: &[u8]| i16::from_le_bytes([b[0], b[1]]);
+        let gyro = gyro
+            .chunks(7)
+            .filter(|c| c[0] > 0)
+            .map(|c| (c[0], [i16(&c[1..]), i16(&c[3..]), i16(&c[5..])]))
+            .collect();
+        Ok(Self {
+            seed,
+            offset,
+            runs,
+            gyro,
+        })
+    }
+}
+
+/// 一边读取传感器一边录制
+pub struct Recorder<M: Motion> {
+    motion: M,
+    log: InputLog,
+}
+
+impl<M: Motion> Recorder<M> {
+    pub fn new(motion: M, seed: u64) -> Self {
+        Self {
+            motion,
+            log: InputLog::new(seed),
+        }
+    }
+
+    /// 已经录制的日志
+    pub fn log(&self) -> &InputLog {
+        &self.log
+    }
+}
+
+impl<M: Motion> Motion for Recorder<M> {
+    fn accel(&mut self) -> Accel {
+        let accel = self.motion.accel();
+        self.log.record(accel)
+    }
+
+    fn gyro(&mut self) -> Gyro {
+        let gyro = self.motion.gyro();
+        self.log.record_gyro(gyro)
+    }
+
+    /// 记录开机时的加速度偏移
+    fn calibration(&mut self, stored: Calibration) -> Calibration {
+        let calibration = self.motion.calibration(stored);
+        self.log.offset = calibration.accel;
+        calibration
+    }
+}
+
+/// 按日志回放输入,日志回放完之后保持平放静止
+#[derive(Debug, Clone)]
+pub struct Replayer {
+    samples: Vec<Accel>,
+    next: usize,
+    gyro: Vec<Gyro>,
+    next_gyro: usize,
+    /// 录制时的加速度偏移
+    offset: [f32; 3],
+}
+
+impl Replayer {
+    pub fn new(log: &InputLog) -> Self {
+        Self {
+            samples: log.samples().collect(),
+            next: 0,
+            gyro: log.gyro_samples().collect(),
+            next_gyro: 0,
+            offset: log.offset,
+        }
+    }
+
+    /// 加速度是否已经回放完
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.samples.len()
+    }
+}
+
+impl Motion for Replayer {
+    fn accel(&mut self) -> Accel {
+        let Some(accel) = self.samples.get(self.next) else {
+            return Accel::new(0.0, 0.0, 1.0);
+        };
+        self.next += 1;
+        *accel
+    }
+
+    fn gyro(&mut self) -> Gyro {
+        let Some(gyro) = self.gyro.get(self.next_gyro) else {
+            return Gyro::default();
+        };
+        self.next_gyro += 1;
+        *gyro
+    }
+
+    /// 使用录制时的加速度偏移,和设置中保存的偏移无关
+    fn calibration(&mut self, _stored: Calibration) -> Calibration {
+        Calibration { accel: self.offset }
+    }
+}
+
+/// 用种子初始化全局随机数,录制和回放前都需要调用
+pub fn seed_rng(seed: u64) {
+    unsafe { RNG.write(Box::new(CubeRng(seed))) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        calibration::SAMPLES,
+        driver::fake::{FakeMatrix, FakeMotion},
+        ledc::LedControl,
+        mapping,
+        snake::{SnakeGame, SnakeMode},
+        testing, App,
+    };
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+    use embassy_executor::Spawner;
+    use embedded_graphics_core::pixelcolor::Rgb888;
+
+    const FLAT: Accel = Accel::new(0.0, 0.0, 1.0);
+    const TAP: Accel = Accel::new(0.0, 0.0, 2.0);
+    const RIGHT: Accel = Accel::new(-1.0, 0.0, 0.0);
+
+    /// 敲击两下
+    fn double_tap() -> impl Iterator<Item = Accel> {
+        [TAP].into_iter().chain([FLAT; 8]).chain([TAP, FLAT])
+    }
+
+    /// 用 `motion` 玩一局贪吃蛇,`stored` 为设置中的校准偏移,返回录制的日志和每一帧的画面
+    async fn play<M: Motion>(
+        motion: M,
+        seed: u64,
+        stored: Calibration,
+        spawner: Spawner,
+    ) -> (InputLog, Vec<[Rgb888; 64]>) {
+        seed_rng(seed);
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let sink = frames.clone();
+        let mut ledc = LedControl::with_matrix(FakeMatrix::default());
+        ledc.capture(move |frame| sink.borrow_mut().push(frame.pixels));
+        let mut app = App::new(Recorder::new(motion, seed), ledc, spawner);
+        app.set_calibration(stored);
+        SnakeGame::with_mode(SnakeMode::Classic).run(&mut app).await;
+        let log = app.motion.log().clone();
+        drop(app);
+        (log, frames.take())
+    }
+
+    #[test]
+    fn replay_gives_same_frames() {
+        testing::run(|spawner| async move {
+            // 暂停再恢复,然后一直向右直到撞墙
+            let samples = [FLAT; 10]
+                .into_iter()
+                .chain(double_tap())
+                .chain([FLAT; 20])
+                .chain(double_tap())
+                .chain([RIGHT]);
+            let (log, frames) = play(
+                FakeMotion::new(samples),
+                42,
+                Calibration::default(),
+                spawner,
+            )
+            .await;
+            let paused = frames.iter().any(|f| {
+                let on = |i: usize| f[i] != Rgb888::new(0, 0, 0);
+                (0..8).all(|y| {
+                    (0..8).all(|x| on(y * 8 + x) == (mapping::PAUSE[y] >> (7 - x) & 1 == 1))
+                })
+            });
+            assert!(paused);
+
+            let bytes = log.to_bytes();
+            let log = InputLog::from_bytes(&bytes).unwrap();
+            let (replayed, replay_frames) = play(
+                Replayer::new(&log),
+                log.seed,
+                Calibration::default(),
+                spawner,
+            )
+            .await;
+            assert_eq!(replayed, log);
+            assert_eq!(replay_frames, frames);
+        });
+    }
+
+    #[test]
+    fn replay_uses_recorded_offset() {
+        testing::run(|spawner| async move {
+            // 偏移让平放的样本向左倾斜,蛇向左撞墙
+            let recorded = Calibration {
+                accel: [-0.75, 0.0, 0.0],
+            };
+            let (log, frames) = play(FakeMotion::new([FLAT; 40]), 5, recorded, spawner).await;
+            assert_eq!(log.offset, recorded.accel);
+
+            let log = InputLog::from_bytes(&log.to_bytes()).unwrap();
+            let mut replayer = Replayer::new(&log);
+            let stored = Calibration {
+                accel: [0.1, 0.1, 0.1],
+            };
+            assert_eq!(replayer.calibration(stored), recorded);
+            let (replayed, replay_frames) = play(replayer, log.seed, stored, spawner).await;
+            assert_eq!(replayed, log);
+            assert_eq!(replay_frames, frames);
+
+            // 设置中没有偏移时蛇向上走,画面不同
+            let (_, flat_frames) = play(
+                FakeMotion::new([FLAT; 40]),
+                5,
+                Calibration::default(),
+                spawner,
+            )
+            .await;
+            assert_ne!(flat_frames, frames);
+        });
+    }
+
+    /// 平放不动,`turning` 时绕 z 轴来回转动
+    struct Still {
+        turning: bool,
+        reads: u32,
+    }
+
+    impl Motion for Still {
+        fn accel(&mut self) -> Accel {
+            Accel::new(0.05, -0.05, 1.0)
+        }
+
+        fn gyro(&mut self) -> Gyro {
+            self.reads += 1;
+            let z = if self.turning && self.reads % 2 == 0 {
+                30.0
+            } else {
+                1.0
+            };
+            Gyro::new(0.5, -0.5, z)
+        }
+    }
+
+    #[test]
+    fn calibration_replays() {
+        testing::run(|spawner| async move {
+            for turning in [false, true] {
+                let motion = Recorder::new(Still { turning, reads: 0 }, 1);
+                let ledc = LedControl::with_matrix(FakeMatrix::default());
+                let mut app = App::new(motion, ledc, spawner);
+                let live = Calibration::run(&mut app).await;
+                // 转动时静止检查失败
+                assert_eq!(live.is_none(), turning);
+                let log = InputLog::from_bytes(&app.motion.log().to_bytes()).unwrap();
+                assert_eq!(log.gyro_samples().count(), SAMPLES as usize);
+
+                let ledc = LedControl::with_matrix(FakeMatrix::default());
+                let mut app = App::new(Replayer::new(&log), ledc, spawner);
+                assert_eq!(Calibration::run(&mut app).await, live);
+            }
+        });
+    }
+
+    #[test]
+    fn play_time_skips_pause() {
+        testing::run(|spawner| async move {
+            // 暂停 40 个样本之后恢复
+            let samples = [FLAT; 10]
+                .into_iter()
+                .chain(double_tap())
+                .chain([FLAT; 40])
+                .chain(double_tap());
+            let ledc = LedControl::with_matrix(FakeMatrix::default());
+            let mut app = App::new(FakeMotion::new(samples), ledc, spawner);
+            assert!(!app.wait(2000).await);
+            assert!(app.paused >= 40 * crate::gesture::TICK);
+            assert_eq!(app.play_millis() + app.paused, app.clock);
+            // 暂停之前等待的时间也计入
+            assert!(app.play_millis() > 2000);
+        });
+    }
+
+    #[test]
+    fn log_round_trip() {
+        let mut log = InputLog::new(7);
+        for _ in 0..300 {
+            log.record(FLAT);
+        }
+        log.record(RIGHT);
+        assert_eq!(log.len(), 301);
+        for z in [0.0, 0.0, -1999.5, 3.25] {
+            log.record_gyro(Gyro::new(1.0, -2.0, z));
+        }
+        assert_eq!(log.gyro_samples().count(), 4);
+        log.offset = [0.25, -0.5, 0.125];
+        assert_eq!(InputLog::from_bytes(&log.to_bytes()), Ok(log.clone()));
+        assert_eq!(InputLog::from_bytes(b"CUBE"), Err(ReplayError::Truncated));
+        let mut bytes = log.to_bytes();
+        bytes[4] = 9;
+        assert_eq!(InputLog::from_bytes(&bytes), Err(ReplayError::Version(9)));
+        bytes[4] = VERSION;
+        bytes.pop();
+        assert_eq!(InputLog::from_bytes(&bytes), Err(ReplayError::Truncated));
+        bytes.truncate(HEADER_LEN - 1);
+        assert_eq!(InputLog::from_bytes(&bytes), Err(ReplayError::Truncated));
+        // 加速度的段数超出数据
+        let mut bytes = log.to_bytes();
+        bytes[HEADER_LEN - 1] = 0xff;
+        assert_eq!(InputLog::from_bytes(&bytes), Err(ReplayError::Truncated));
+        bytes[0] = b'X';
+        assert_eq!(InputLog::from_bytes(&bytes), Err(ReplayError::Magic));
+    }
+}
@@ -1,7 +1,10 @@
 #![doc = include_str!("../../rfcs/003_snake.md")]
 
-use crate::{App, Direction, Gd, BUZZER, RNG};
-use alloc::collections::LinkedList;
+use crate::{
+    driver::{Matrix, Motion},
+    App, Direction, Gd, BUZZER, RNG,
+};
+use alloc::{collections::LinkedList, vec::Vec};
 use cube_rand::CubeRng;
 use embassy_time::Timer;
 use embedded_graphics::{
@@ -10,17 +13,117 @@ use embedded_graphics::{
     Pixel,
 };
 
+/// 贪吃蛇模式
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SnakeMode {
+    /// 经典: 撞墙结束
+    #[default]
+    Classic,
+    /// 穿墙: 从一边出去,从另一边进来
+    Wrap,
+    /// 障碍: 随机选择一种障碍物布局
+    Maze,
+    /// 加速: 蛇越长移动越快
+    Speed,
+}
+
+impl SnakeMode {
+    #[rustfmt::skip]
+    fn ui(&self) -> [u8; 8] {
+        match self {
+            SnakeMode::Classic => [
+                0b00000000,
+                0b01110100,
+                0b01000000,
+                0b01111110,
+                0b00000010,
+                0b00011110,
+                0b00010000,
+                0b00000000,
+            ],
+            SnakeMode::Wrap => [
+                0b00000000,
+                0b00100100,
+                0b01000010,
+                0b11111111,
+                0b01000010,
+                0b00100100,
+                0b00000000,
+                0b00000000,
+            ],
+            SnakeMode::Maze => [
+                0b10000001,
+                0b00000000,
+                0b00111100,
+                0b00000000,
+                0b00000000,
+                0b00111100,
+                0b00000000,
+                0b10000001,
+            ],
+            SnakeMode::Speed => [
+                0b00000000,
+                0b10010000,
+                0b01001000,
+                0b00100100,
+                0b01001000,
+                0b10010000,
+                0b00000000,
+                0b00000000,
+            ],
+        }
+    }
+}
+
+/// 障碍物布局,避开蛇开始的位置和向上的路线
+const LAYOUTS: [&[(i32, i32)]; 3] = [
+    // 四个角
+    &[(1, 1), (6, 1), (1, 6), (6, 6)],
+    // 中间一道竖墙
+    &[(2, 2), (2, 3), (2, 4), (2, 5)],
+    // 两道横墙
+    &[
+        (0, 2),
+        (1, 2),
+        (2, 2),
+        (3, 2),
+        (0, 6),
+        (1, 6),
+        (2, 6),
+        (3, 6),
+    ],
+];
+/// 开始时每一步的等待时间,ms
+const START_WAIT: u64 = 600;
+/// 加速模式中最快的等待时间,ms
+const MIN_WAIT: u64 = 200;
+/// 加速模式中蛇每长一格减少的等待时间,ms
+const SPEED_STEP: u64 = 40;
+/// 吃到食物后出现奖励食物的概率为 1 / BONUS_CHANCE
+const BONUS_CHANCE: u32 = 4;
+/// 奖励食物存在的步数
+const BONUS_STEPS: u8 = 15;
+/// 奖励食物最后几步闪烁
+const BONUS_BLINK: u8 = 5;
+/// 奖励食物的分数
+const BONUS_SCORE: u8 = 3;
+
 #[derive(Debug)]
 pub struct SnakeGame {
     width: i32,
     height: i32,
+    mode: SnakeMode,
     snake: Snake,
     food: Food,
+    /// 奖励食物和剩余的步数
+    bonus: Option<(Food, u8)>,
+    /// 障碍物
+    obstacles: &'static [(i32, i32)],
     /// ms
     waiting_time: u64,
     /// 得分
     score: u8,
-    /// 最高分
+    /// 这个模式的最高分
     pub highest: u8,
     game_over: bool,
 }
@@ -33,32 +136,76 @@ impl Default for SnakeGame {
 
 impl SnakeGame {
     pub fn new() -> Self {
+        Self::with_mode(SnakeMode::Classic)
+    }
+
+    pub fn with_mode(mode: SnakeMode) -> Self {
         let width = 8;
         let height = 8;
+        let obstacles = match mode {
+            SnakeMode::Maze => {
+                LAYOUTS[unsafe {
+                    CubeRng(RNG.assume_init_mut().random() as u64).random(0, LAYOUTS.len() as u32)
+                } as usize]
+            }
+            _ => &[],
+        };
 
-        Self {
+        let mut game = Self {
             width,
             height,
+            mode,
             snake: Snake::new(Point::new(5, 5)),
-            food: Food::random(width, height),
-            waiting_time: 600,
+            // 先放在屏幕外,下面在空的位置生成
+            food: Food {
+                pos: Point::new(-1, -1),
+                color: Rgb888::CSS_RED,
+            },
+            bonus: None,
+            obstacles,
+            waiting_time: START_WAIT,
             score: 0,
             highest: 0,
             game_over: false,
-        }
+        };
+        game.food = game.create_food(Rgb888::CSS_RED).unwrap();
+        game
     }
 
-    pub async fn run<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<'_, T>) {
+    /// 选择模式,向下返回 `None`
+    pub async fn menu<M: Motion, L: Matrix>(app: &mut App<M, L>) -> Option<SnakeMode> {
+        let modes = [
+            SnakeMode::Classic,
+            SnakeMode::Wrap,
+            SnakeMode::Maze,
+            SnakeMode::Speed,
+        ];
+        let mode = app
+            .choose(modes.len(), 0, |app, i| {
+                app.ledc.write_bytes(modes[i].ui());
+            })
+            .await?;
+        Some(modes[mode])
+    }
+
+    pub async fn run<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
         app.ledc.clear();
         app.gd = Gd::default();
 
         loop {
-            Timer::after_millis(self.waiting_time).await;
+            if app.wait(self.waiting_time).await {
+                break;
+            }
 
             if self.game_over {
                 unsafe { BUZZER.assume_init_mut().snake_die().await };
-                app.ledc.draw_score(self.score);
-                Timer::after_millis(1500).await;
+                // 超过两位数时滚动显示
+                if self.score >= 100 {
+                    app.ledc.scroll_number(self.score as u32, 150).await;
+                } else {
+                    app.ledc.draw_score(self.score);
+                    Timer::after_millis(1500).await;
+                }
                 if self.score > self.highest {
                     self.highest = self.score;
                     app.face.break_record_animate(&mut app.ledc).await;
@@ -68,14 +215,14 @@ impl SnakeGame {
             }
             app.gravity_direction();
 
-            self.r#move(&app.gd).await;
+            self.r#move(app).await;
 
             self.draw(app);
         }
     }
 
-    async fn r#move(&mut self, gd: &Gd) {
-        match gd {
+    async fn r#move<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
+        match app.gd {
             Gd::None => {}
             Gd::Up => self.snake.set_direction(Direction::Up),
             Gd::Right => self.snake.set_direction(Direction::Right),
@@ -83,26 +230,93 @@ impl SnakeGame {
             Gd::Left => self.snake.set_direction(Direction::Left),
         };
 
-        let next_head = self.snake.next_head_pos();
-        if self.food.pos.eq(&next_head) {
-            unsafe { BUZZER.assume_init_mut().snake_score().await };
-            // TODO: 得分画面效果
+        // 奖励食物过期
+        if let Some((_, steps)) = self.bonus.as_mut() {
+            *steps -= 1;
+            if *steps == 0 {
+                self.bonus = None;
+            }
+        }
 
-            self.snake.grow(self.food.clone());
-            self.food
-                .create_food(self.width, self.height, &self.snake.body);
-            self.calc_score();
-            unsafe { BUZZER.assume_init_mut().snake_move().await };
-        } else if self.outside(next_head) || self.snake.overlapping() {
+        let next_head = self.next_head();
+        if self.food.pos.eq(&next_head) {
+            let food = self.food.clone();
+            self.snake.grow(food.clone());
+            self.calc_score(1);
+            self.score_flash(app, food.color).await;
+            // 蛇占满了所有空位,游戏结束
+            let Some(next) = self.create_food(Rgb888::CSS_RED) else {
+                self.game_over = true;
+                return;
+            };
+            self.food = next;
+            // 一定概率出现奖励食物
+            let lucky = unsafe {
+                CubeRng(RNG.assume_init_mut().random() as u64).random(0, BONUS_CHANCE) == 0
+            };
+            if self.bonus.is_none() && lucky {
+                self.bonus = self
+                    .create_food(Rgb888::CSS_GOLD)
+                    .map(|bonus| (bonus, BONUS_STEPS));
+            }
+            self.speed_up();
+        } else if let Some((bonus, _)) = self.bonus.take_if(|(b, _)| b.pos.eq(&next_head)) {
+            self.snake.grow(bonus.clone());
+            self.calc_score(BONUS_SCORE);
+            self.score_flash(app, bonus.color).await;
+            self.speed_up();
+        } else if self.hit(next_head) {
             self.game_over = true;
         } else {
-            self.snake.r#move();
+            self.snake.r#move(next_head);
             unsafe { BUZZER.assume_init_mut().snake_move().await };
         }
     }
 
-    fn calc_score(&mut self) {
-        self.score += 1;
+    fn calc_score(&mut self, score: u8) {
+        self.score = self.score.saturating_add(score);
+    }
+
+    /// 得分时播放音效,蛇身闪烁成食物的颜色
+    async fn score_flash<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>, color: Rgb888) {
+        unsafe { BUZZER.assume_init_mut().snake_score().await };
+        self.draw(app);
+        app.ledc
+            .write_pixels(self.snake.body.iter().map(|p| Pixel(p.0, color)));
+        Timer::after_millis(150).await;
+    }
+
+    /// 加速模式中蛇越长移动越快
+    fn speed_up(&mut self) {
+        if self.mode == SnakeMode::Speed {
+            let grown = self.snake.body.len() as u64 - 2;
+            self.waiting_time = START_WAIT.saturating_sub(grown * SPEED_STEP).max(MIN_WAIT);
+        }
+    }
+
+    /// 蛇头的下一个位置,穿墙模式中从另一边进来
+    fn next_head(&self) -> Point {
+        let pos = self.snake.next_head_pos();
+        match self.mode {
+            SnakeMode::Wrap => {
+                Point::new(pos.x.rem_euclid(self.width), pos.y.rem_euclid(self.height))
+            }
+            _ => pos,
+        }
+    }
+
+    /// 蛇头移动到 `pos` 时是否撞到墙,障碍物或者蛇身,蛇尾会移开所以不算
+    fn hit(&self, pos: Point) -> bool {
+        let len = self.snake.body.len();
+        self.outside(pos)
+            || self.is_obstacle(pos)
+            || self.snake.body.iter().take(len - 1).any(|p| p.0 == pos)
+    }
+
+    fn is_obstacle(&self, pos: Point) -> bool {
+        self.obstacles
+            .iter()
+            .any(|(x, y)| pos == Point::new(*x, *y))
     }
 
     fn outside(&self, next_head: Point) -> bool {
@@ -112,13 +326,51 @@ impl SnakeGame {
             || next_head.y >= self.height
     }
 
-    pub fn draw<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<T>) {
+    /// 在随机一个空的位置生成食物,没有空位时返回 `None`
+    fn create_food(&self, color: Rgb888) -> Option<Food> {
+        let free = self.free_cells();
+        if free.is_empty() {
+            return None;
+        }
+        let i = unsafe {
+            CubeRng(RNG.assume_init_mut().random() as u64).random(0, free.len() as u32) as usize
+        };
+        Some(Food {
+            pos: free[i],
+            color,
+        })
+    }
+
+    /// 没有蛇身,障碍物和食物的位置
+    fn free_cells(&self) -> Vec<Point> {
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| Point::new(x, y)))
+            .filter(|&pos| {
+                !(self.snake.body.iter().any(|s| s.0 == pos)
+                    || self.is_obstacle(pos)
+                    || self.food.pos == pos
+                    || self.bonus.as_ref().is_some_and(|(b, _)| b.pos == pos))
+            })
+            .collect()
+    }
+
+    pub fn draw<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
         let ledc = &mut app.ledc;
         ledc.clear();
         // 蛇身
         let mut pixels = self.snake.body.clone();
+        // 障碍物
+        for (x, y) in self.obstacles {
+            pixels.push_back(Pixel(Point::new(*x, *y), Rgb888::CSS_BLUE));
+        }
         // 食物
         pixels.push_back(self.food.clone().into());
+        // 奖励食物,快要消失时闪烁
+        if let Some((bonus, steps)) = &self.bonus {
+            if *steps > BONUS_BLINK || steps % 2 == 0 {
+                pixels.push_back(bonus.clone().into());
+            }
+        }
         ledc.write_pixels(pixels);
     }
 }
@@ -136,31 +388,6 @@ impl From<Food> for Pixel<Rgb888> {
     }
 }
 
-impl Food {
-    fn random(width: i32, height: i32) -> Self {
-        let food = unsafe {
-            let x = CubeRng(RNG.assume_init_mut().random() as u64).random(0, width as u32) as i32;
-            let y = CubeRng(RNG.assume_init_mut().random() as u64).random(0, height as u32) as i32;
-            (x, y)
-        };
-        Self {
-            pos: food.into(),
-            color: Rgb888::CSS_RED,
-        }
-    }
-
-    fn create_food(&self, width: i32, height: i32, snake_body: &LinkedList<Pixel<Rgb888>>) -> Self {
-        loop {
-            let food = Food::random(width, height);
-            if snake_body.iter().any(|s| s.0.eq(&food.pos)) {
-                continue;
-            } else {
-                break food;
-            }
-        }
-    }
-}
-
 /// 贪吃蛇
 #[derive(Debug)]
 struct Snake {
@@ -196,8 +423,7 @@ impl Snake {
         self.body.push_front(food.into());
     }
 
-    fn r#move(&mut self) {
-        let nh = self.next_head_pos();
+    fn r#move(&mut self, nh: Point) {
         self.body.push_front(Pixel(nh, Rgb888::CSS_WHITE));
         self.body.pop_back();
         self.head = nh;
@@ -216,8 +442,121 @@ impl Snake {
         }
         pos
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 蛇身从蛇头开始,食物放在角落里
+    fn game(mode: SnakeMode, body: &[(i32, i32)], direction: Direction) -> SnakeGame {
+        let body = body
+            .iter()
+            .map(|p| Pixel(Point::from(*p), Rgb888::CSS_WHITE))
+            .collect::<LinkedList<_>>();
+        SnakeGame {
+            width: 8,
+            height: 8,
+            mode,
+            snake: Snake {
+                direction,
+                head: body.front().unwrap().0,
+                body,
+            },
+            food: Food {
+                pos: Point::new(7, 7),
+                color: Rgb888::CSS_RED,
+            },
+            bonus: None,
+            obstacles: if mode == SnakeMode::Maze {
+                LAYOUTS[0]
+            } else {
+                &[]
+            },
+            waiting_time: START_WAIT,
+            score: 0,
+            highest: 0,
+            game_over: false,
+        }
+    }
+
+    #[test]
+    fn next_head_wraps_only_in_wrap_mode() {
+        let cases = [
+            ((0, 3), Direction::Left, (-1, 3), (7, 3)),
+            ((7, 3), Direction::Right, (8, 3), (0, 3)),
+            ((3, 0), Direction::Up, (3, -1), (3, 7)),
+            ((3, 7), Direction::Down, (3, 8), (3, 0)),
+            ((3, 3), Direction::Up, (3, 2), (3, 2)),
+        ];
+        for (head, direction, classic, wrap) in cases {
+            let g = game(SnakeMode::Classic, &[head], direction);
+            assert_eq!(g.next_head(), Point::from(classic));
+            assert!(g.hit(g.next_head()) == (classic != wrap));
+            let g = game(SnakeMode::Wrap, &[head], direction);
+            assert_eq!(g.next_head(), Point::from(wrap));
+            assert!(!g.hit(g.next_head()));
+        }
+    }
+
+    #[test]
+    fn hit_body_but_not_tail() {
+        // 蛇头在 (2, 2),向下是蛇身,向左是蛇尾
+        let body = [(2, 2), (2, 3), (1, 3), (1, 2)];
+        let g = game(SnakeMode::Classic, &body, Direction::Up);
+        assert!(g.hit(Point::new(2, 3)));
+        assert!(g.hit(Point::new(1, 3)));
+        assert!(!g.hit(Point::new(1, 2)));
+        assert!(!g.hit(Point::new(3, 2)));
+        assert!(g.hit(Point::new(-1, 2)));
+        assert!(g.hit(Point::new(2, 8)));
+    }
+
+    #[test]
+    fn hit_obstacles() {
+        let g = game(SnakeMode::Maze, &[(5, 5), (5, 6)], Direction::Up);
+        for (x, y) in LAYOUTS[0] {
+            assert!(g.hit(Point::new(*x, *y)));
+        }
+        let g = game(SnakeMode::Classic, &[(5, 5), (5, 6)], Direction::Up);
+        let (x, y) = LAYOUTS[0][0];
+        assert!(!g.hit(Point::new(x, y)));
+    }
+
+    #[test]
+    fn speed_up_only_in_speed_mode() {
+        let mut body = vec![(0, 0), (0, 1)];
+        let mut g = game(SnakeMode::Classic, &body, Direction::Up);
+        body.extend([(0, 2), (0, 3)]);
+        g.snake = game(SnakeMode::Classic, &body, Direction::Up).snake;
+        g.speed_up();
+        assert_eq!(g.waiting_time, START_WAIT);
+
+        let mut g = game(SnakeMode::Speed, &body, Direction::Up);
+        g.speed_up();
+        assert_eq!(g.waiting_time, START_WAIT - 2 * SPEED_STEP);
+        // 最快不超过 `MIN_WAIT`
+        let body = (0..8)
+            .flat_map(|y| (0..8).map(move |x| (x, y)))
+            .collect::<Vec<_>>();
+        g.snake = game(SnakeMode::Speed, &body, Direction::Up).snake;
+        g.speed_up();
+        assert_eq!(g.waiting_time, MIN_WAIT);
+    }
 
-    fn overlapping(&self) -> bool {
-        self.body.iter().skip(1).any(|pos| pos.0.eq(&self.head))
+    #[test]
+    fn food_only_on_free_cells() {
+        // 除了右下角的食物和左上角以外都是蛇身
+        let body = (0..8)
+            .flat_map(|y| (0..8).map(move |x| (x, y)))
+            .filter(|&p| p != (0, 0) && p != (7, 7))
+            .collect::<Vec<_>>();
+        let mut g = game(SnakeMode::Classic, &body, Direction::Up);
+        assert_eq!(g.free_cells(), [Point::new(0, 0)]);
+        // 吃掉食物之后没有空位
+        g.snake.grow(g.food.clone());
+        g.food.pos = Point::new(0, 0);
+        assert!(g.free_cells().is_empty());
+        assert_eq!(g.create_food(Rgb888::CSS_RED), None);
     }
 }
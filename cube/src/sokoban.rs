@@ -1,11 +1,13 @@
 #![doc = include_str!("../../rfcs/007_sokoban.md")]
 
 use crate::{
+    driver::{Matrix, Motion},
+    gesture::Gesture,
     map::{Map, MapCell, Vision},
     player::Player,
     App, Gd, BUZZER,
 };
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 use embassy_time::Timer;
 use embedded_graphics::geometry::Point;
 use embedded_graphics_core::{
@@ -13,6 +15,22 @@ use embedded_graphics_core::{
     prelude::WebColors,
     Pixel,
 };
+use generator::Difficulty;
+use levels::LEVELS;
+use log::{info, warn};
+use solver::{Solution, SolveError, Solver};
+use xsb::{Level, XsbError};
+
+pub mod generator;
+pub mod levels;
+pub mod solver;
+pub mod xsb;
+
+/// 关卡总数,内置关卡之后的关卡由生成器用关卡序号作为种子生成,显示最多两位数
+pub const LEVEL_COUNT: usize = 99;
+
+/// 提示时搜索最多使用的堆,字节,超过时不给提示
+const HINT_MEMORY: usize = 12 * 1024;
 
 /// 推箱子
 /// 左上角为坐标原点,所有的坐标都为全局坐标
@@ -23,6 +41,15 @@ pub struct Sokoban {
     map: SokobanMap,
     player: Player,
     vision: Vision<8, 8, TargetType>,
+    /// 当前关卡,从 0 开始
+    level: usize,
+    /// 已解锁的最高关卡,从 0 开始
+    pub unlocked: u8,
+    /// 走过的步骤,LURD 格式
+    history: String,
+    solver: Solver,
+    /// 卡死的箱子
+    stuck: Option<Point>,
     /// ms
     waiting_time: u64,
     game_over: bool,
@@ -36,76 +63,251 @@ impl Default for Sokoban {
 
 impl Sokoban {
     pub fn new() -> Self {
-        let xsb = "
-########
-#--#---#
-#-$----#
-#--*.*-#
-#-$@*--#
-###$*--#
--#--*--#
--#-#.--#
--#--.--#
--#######
-";
-        let map = SokobanMap::from_xsb(xsb);
+        Self::with_level(0)
+    }
+
+    /// 第 `level` 关,从 0 开始
+    pub fn with_level(level: usize) -> Self {
+        // 内置的关卡都是合法的,生成失败时使用最后一个内置关卡
+        let map = match LEVELS.get(level) {
+            Some(xsb) => SokobanMap::from_xsb(xsb).unwrap(),
+            None => SokobanMap::new(level as u64, Difficulty::new((level - LEVELS.len()) as u8))
+                .unwrap_or_else(|| {
+                    warn!("sokoban level {} generation failed", level + 1);
+                    SokobanMap::from_xsb(LEVELS[LEVELS.len() - 1]).unwrap()
+                }),
+        };
+        Self::with_map(map, level)
+    }
+
+    /// 从 XSB 文件中读取的关卡,用于在主机上求解
+    pub fn with_xsb(level: &Level) -> Self {
+        Self::with_map(SokobanMap::from_level(level), 0)
+    }
+
+    fn with_map(map: SokobanMap, level: usize) -> Self {
         let width = map.map.width;
         let height = map.map.height;
         let player = Player::new(map.player.0 .0);
         let mut vision = Vision::new(width, height, player.pos);
         vision.update_data(&map.map);
+        let solver = Solver::new(&map);
         Sokoban {
+            solver,
+            stuck: None,
             map,
             player,
             vision,
+            level,
+            unlocked: 0,
+            history: String::new(),
             waiting_time: 300,
             game_over: false,
         }
     }
 
-    pub async fn run<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<'_, T>) {
-        app.ledc.clear();
+    /// 切换到第 `level` 关,保留解锁进度
+    fn load(&mut self, level: usize) {
+        let unlocked = self.unlocked;
+        *self = Self::with_level(level);
+        self.unlocked = unlocked;
+    }
+
+    pub async fn run<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
         app.gd = Gd::default();
+        // 选择关卡,默认为已解锁的最高关卡
+        let last = (self.unlocked as usize).min(LEVEL_COUNT - 1);
+        let Some(level) = app
+            .choose(last + 1, last, |app, i| app.ledc.draw_score(i as u8 + 1))
+            .await
+        else {
+            return;
+        };
+        self.load(level);
+        app.ledc.clear();
 
         loop {
-            Timer::after_millis(self.waiting_time).await;
+            match app.wait_gesture(self.waiting_time).await {
+                Some(Gesture::Exit) => break,
+                // 摇晃撤销一步
+                Some(Gesture::Shake) => {
+                    if self.undo() {
+                        unsafe { BUZZER.assume_init_mut().sokoban_undo().await };
+                    }
+                    self.draw(app);
+                    continue;
+                }
+                // 持续摇晃重新开始这一关
+                Some(Gesture::LongShake) => {
+                    self.load(self.level);
+                    unsafe { BUZZER.assume_init_mut().sokoban_restart().await };
+                    self.draw(app);
+                    continue;
+                }
+                // 翻转提示下一次推动
+                Some(Gesture::Flip) => {
+                    self.hint(app).await;
+                    continue;
+                }
+                // 做手势时的倾斜不移动
+                _ if app.gesturing() => continue,
+                _ => {}
+            }
 
             if self.game_over {
-                // TODO: 结束进入下一关
+                info!("sokoban level {} solved: {}", self.level + 1, self.history);
                 Timer::after_millis(1500).await;
                 app.face.break_record_animate(&mut app.ledc).await;
                 Timer::after_millis(500).await;
-                break;
+                // 解锁并进入下一关,最后一关完成后返回菜单
+                let next = self.level + 1;
+                if next >= LEVEL_COUNT {
+                    break;
+                }
+                self.unlocked = self.unlocked.max(next as u8);
+                self.load(next);
+                app.gd = Gd::default();
+                continue;
             }
             app.gravity_direction();
 
-            if !self.hit_wall(app) {
-                // 不撞墙，是否在推动箱子，能否推动箱子，能一起移动
-                let can_push = self.push_box(app);
-                if can_push {
-                    let moved = self.player.r#move(app.gd);
-                    if moved {
-                        unsafe { BUZZER.assume_init_mut().sokoban_move().await };
-                    }
-                    // 玩家移动之后视野数据改变
-                    self.vision.update(app.gd, &self.map.map);
-                    self.game_over();
+            if let Some(push) = self.walk(app.gd) {
+                unsafe { BUZZER.assume_init_mut().sokoban_move().await };
+                // 箱子卡死时闪烁,提示撤销
+                if push && self.check_stuck() {
+                    let stuck = self.stuck.into_iter().collect::<Vec<_>>();
+                    self.flash(app, &stuck, Rgb888::CSS_RED).await;
                 }
             }
             self.draw(app);
         }
     }
 
+    /// 走过的步骤,LURD 格式,可以用 [`Sokoban::replay`] 重放
+    pub fn history(&self) -> &str {
+        &self.history
+    }
+
+    /// 所有箱子都在目标点上
+    pub fn solved(&self) -> bool {
+        self.game_over
+    }
+
+    /// 向 `gd` 走一步,撞墙或者推不动箱子时返回 `None`,否则返回是否推动了箱子
+    fn walk(&mut self, gd: Gd) -> Option<bool> {
+        if self.hit_wall(gd) {
+            return None;
+        }
+        let next = self.player.next_pos(gd);
+        let push = self.map.boxs.iter().any(|b| b.0 .0 == next);
+        if !self.push_box(gd) || !self.player.r#move(gd) {
+            return None;
+        }
+        self.history.extend(step(gd, push));
+        // 玩家移动之后镜头跟随
+        self.vision.follow(self.player.pos);
+        self.game_over();
+        Some(push)
+    }
+
+    /// 按 LURD 重放步骤,和倾斜移动一样检查墙和箱子,步骤记录到历史中
+    ///
+    /// 遇到走不通,不是 LURD 字符或者推动箱子的标记不对的步骤时停止,返回这一步的位置.
+    pub fn replay(&mut self, lurd: &str) -> Result<(), usize> {
+        for (i, c) in lurd.char_indices() {
+            let Some((gd, push)) = parse_step(c) else {
+                return Err(i);
+            };
+            let next = self.player.next_pos(gd);
+            if self.map.boxs.iter().any(|b| b.0 .0 == next) != push || self.walk(gd).is_none() {
+                return Err(i);
+            }
+        }
+        self.check_stuck();
+        Ok(())
+    }
+
+    /// 撤销最后一步,推动过的箱子也退回原处,没有可以撤销的步骤时返回 `false`
+    fn undo(&mut self) -> bool {
+        let Some((gd, push)) = self.history.pop().and_then(parse_step) else {
+            return false;
+        };
+        let pos = self.player.pos;
+        if push {
+            let from = forward(pos, gd);
+            if let Some(b) = self.map.boxs.iter_mut().find(|b| b.0 .0 == from) {
+                b.0 .0 = pos;
+            }
+        }
+        self.player.r#move(gd.opposite());
+        self.vision.follow(self.player.pos);
+        self.game_over = false;
+        self.check_stuck();
+        true
+    }
+
+    /// 检查是否有卡死的箱子,新出现卡死的箱子时返回 `true`
+    fn check_stuck(&mut self) -> bool {
+        let boxs = self.map.boxs.iter().map(|b| b.0 .0).collect::<Vec<_>>();
+        let stuck = boxs
+            .iter()
+            .find(|b| self.solver.deadlocked(&boxs, **b))
+            .copied();
+        let new = stuck.is_some() && stuck != self.stuck;
+        self.stuck = stuck;
+        new
+    }
+
+    /// 从当前的位置求推动次数最少的解,展开的状态超过 `limit` 时放弃
+    pub fn solve(&self, limit: usize) -> Result<Solution, SolveError> {
+        let boxs = self.map.boxs.iter().map(|b| b.0 .0).collect::<Vec<_>>();
+        self.solver.solve(self.player.pos, &boxs, limit)
+    }
+
+    /// 闪烁显示下一次推动的箱子和推到的位置,无解或者搜索不完时发出提示音
+    async fn hint<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
+        match self.solve(Solver::limit(HINT_MEMORY)) {
+            Ok(Solution { pushes, .. }) if !pushes.is_empty() => {
+                let push = pushes[0];
+                let cells = [push.from, forward(push.from, push.gd)];
+                self.flash(app, &cells, Rgb888::CSS_MAGENTA).await;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                info!("sokoban hint: {e:?}");
+                unsafe { BUZZER.assume_init_mut().menu_access().await };
+            }
+        }
+    }
+
+    /// 在当前画面上闪烁几个格子
+    async fn flash<M: Motion, L: Matrix>(
+        &mut self,
+        app: &mut App<M, L>,
+        cells: &[Point],
+        color: Rgb888,
+    ) {
+        for _ in 0..3 {
+            self.draw(app);
+            let vp = self.vision.pos;
+            app.ledc
+                .write_pixels(cells.iter().map(|c| Pixel(*c - vp, color)));
+            Timer::after_millis(150).await;
+            self.draw(app);
+            Timer::after_millis(150).await;
+        }
+    }
+
     /// 推动箱子
-    fn push_box<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<T>) -> bool {
-        let Point { x, y } = self.player.next_pos(app.gd);
+    fn push_box(&mut self, gd: Gd) -> bool {
+        let Point { x, y } = self.player.next_pos(gd);
         let boxs = self.map.boxs.clone();
         for (cp, ct) in self.map.boxs.iter_mut() {
             // 下一个位置是箱子且能推动则推箱子
             if TargetType::Box.eq(ct) && cp.0.x == x && cp.0.y == y {
                 // 再下一个位置
                 let mut boxp = cp.0;
-                match app.gd {
+                match gd {
                     Gd::None => {}
                     Gd::Up => boxp.y -= 1,
                     Gd::Right => boxp.x += 1,
@@ -123,7 +325,7 @@ impl Sokoban {
                 }
 
                 // 推动箱子
-                match app.gd {
+                match gd {
                     Gd::None => {}
                     Gd::Up => cp.0.y -= 1,
                     Gd::Right => cp.0.x += 1,
@@ -142,7 +344,9 @@ impl Sokoban {
         self.game_over = all;
     }
 
-    fn draw<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<T>) {
+    /// 画一帧,镜头先向目标位置移动一帧
+    fn draw<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
+        self.vision.step(&self.map.map);
         app.ledc.clear_with_color(BinaryColor::Off.into());
         let vp = self.vision.pos;
         let mut pixels = self
@@ -161,8 +365,10 @@ impl Sokoban {
         // 箱子
         let goals = self.map.goals.iter().map(|m| m.0 .0).collect::<Vec<_>>();
         for b in self.map.boxs.iter().map(|b| b.0) {
-            // 青色表示箱子在目标点上
-            let color = if goals.contains(&b.0) {
+            // 青色表示箱子在目标点上,暗红色表示箱子卡死
+            let color = if self.stuck == Some(b.0) {
+                Rgb888::CSS_DARK_RED
+            } else if goals.contains(&b.0) {
                 Rgb888::CSS_CYAN
             } else {
                 b.1
@@ -186,8 +392,8 @@ impl Sokoban {
     }
 
     /// 检测是否撞墙
-    fn hit_wall<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<T>) -> bool {
-        let Point { x, y } = self.player.next_pos(app.gd);
+    fn hit_wall(&self, gd: Gd) -> bool {
+        let Point { x, y } = self.player.next_pos(gd);
         let overlapping = x <= 0
             || y <= 0
             || x >= self.map.map.width as i32 - 1
@@ -204,6 +410,35 @@ impl Sokoban {
     }
 }
 
+/// 沿着 `gd` 方向走一步的位置
+fn forward(p: Point, gd: Gd) -> Point {
+    Player::new(p).next_pos(gd)
+}
+
+/// LURD 中的一步,移动为小写,推动箱子为大写
+fn step(gd: Gd, push: bool) -> Option<char> {
+    let c = match gd {
+        Gd::None => return None,
+        Gd::Up => 'u',
+        Gd::Right => 'r',
+        Gd::Down => 'd',
+        Gd::Left => 'l',
+    };
+    Some(if push { c.to_ascii_uppercase() } else { c })
+}
+
+/// LURD 中一步的方向和是否推动箱子,不是 LURD 字符时返回 `None`
+fn parse_step(c: char) -> Option<(Gd, bool)> {
+    let gd = match c.to_ascii_lowercase() {
+        'u' => Gd::Up,
+        'r' => Gd::Right,
+        'd' => Gd::Down,
+        'l' => Gd::Left,
+        _ => return None,
+    };
+    Some((gd, c.is_ascii_uppercase()))
+}
+
 /// 标记地图中的类型,表示墙,人还是目标点
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
 enum TargetType {
@@ -233,67 +468,98 @@ struct SokobanMap<T = TargetType> {
 }
 
 impl SokobanMap {
-    /// TODO: 使用算法生成
-    fn new() -> Self {
-        unimplemented!()
+    /// 根据种子生成地图,见 [`generator`],生成失败时返回 `None`
+    fn new(seed: u64, difficulty: Difficulty) -> Option<Self> {
+        Self::from_xsb(&generator::generate(seed, difficulty)?).ok()
     }
 
-    /// 根据XSB生成地图
-    fn from_xsb(xsb: &str) -> Self {
+    /// 根据XSB生成地图,只使用文本中的第一个关卡
+    fn from_xsb(xsb: &str) -> Result<Self, XsbError> {
+        Ok(Self::from_level(&xsb.parse()?))
+    }
+
+    fn from_level(level: &Level) -> Self {
         let mut map = Self::default();
-        for (y, line) in xsb.trim().lines().enumerate() {
-            let y = y as i32;
-            for (x, char) in line.chars().enumerate() {
-                let x = x as i32;
-                match char {
-                    '@' => {
-                        map.player = (Pixel((x, y).into(), Rgb888::CSS_RED), TargetType::Man);
-                    }
-                    '+' => {
-                        map.player = (Pixel((x, y).into(), Rgb888::CSS_RED), TargetType::Man);
-                        let goal = (Pixel((x, y).into(), Rgb888::CSS_GREEN), TargetType::Goal);
-                        map.goals.push(goal);
-                    }
-                    '$' => {
-                        map.boxs
-                            .push((Pixel((x, y).into(), Rgb888::CSS_BLUE), TargetType::Box));
-                    }
-                    '*' => {
-                        map.boxs
-                            .push((Pixel((x, y).into(), Rgb888::CSS_BLUE), TargetType::Box));
-                        let goal = (Pixel((x, y).into(), Rgb888::CSS_GREEN), TargetType::Goal);
-                        map.goals.push(goal);
-                    }
-                    '#' => {
-                        let wall = (Pixel((x, y).into(), Rgb888::CSS_WHITE), TargetType::Wall);
-                        map.map.data.push(wall);
-                    }
-                    '.' => {
-                        let goal = (Pixel((x, y).into(), Rgb888::CSS_GREEN), TargetType::Goal);
-                        map.goals.push(goal);
-                        map.map.data.push(goal);
-                    }
-                    _ => {}
-                }
+        map.map.width = level.width;
+        map.map.height = level.height;
+        for (x, y, char) in level.cells() {
+            let p = Point::new(x as i32, y as i32);
+            if matches!(char, '@' | '+') {
+                map.player = (Pixel(p, Rgb888::CSS_RED), TargetType::Man);
+            }
+            if matches!(char, '$' | '*') {
+                map.boxs.push((Pixel(p, Rgb888::CSS_BLUE), TargetType::Box));
+            }
+            if matches!(char, '.' | '*' | '+') {
+                let goal = (Pixel(p, Rgb888::CSS_GREEN), TargetType::Goal);
+                map.goals.push(goal);
+                map.map.data.push(goal);
+            }
+            if char == '#' {
+                let wall = (Pixel(p, Rgb888::CSS_WHITE), TargetType::Wall);
+                map.map.data.push(wall);
             }
         }
-        map.map.height = map
-            .map
-            .data
-            .iter()
-            .max_by(|c1, c2| c1.0 .0.y.cmp(&c2.0 .0.y))
-            .map_or(0, |c| (c.0 .0.y + 1) as usize);
-        map.map.width = map
-            .map
-            .data
-            .iter()
-            .max_by(|c1, c2| c1.0 .0.x.cmp(&c2.0 .0.x))
-            .map_or(0, |c| (c.0 .0.x + 1) as usize);
         map
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    /// TODO: 根据LURD生成地图
-    fn from_lurd(_lurd: &str) -> Self {
-        unimplemented!()
+    fn boxes(sokoban: &Sokoban) -> Vec<Point> {
+        sokoban.map.boxs.iter().map(|b| b.0 .0).collect()
+    }
+
+    #[test]
+    fn replay_solutions() {
+        for level in 0..LEVELS.len() {
+            let solution = Sokoban::with_level(level).solve(usize::MAX).unwrap();
+            let mut sokoban = Sokoban::with_level(level);
+            assert_eq!(sokoban.replay(&solution.lurd), Ok(()), "level {level}");
+            assert!(sokoban.solved(), "level {level}");
+            assert_eq!(sokoban.history(), solution.lurd);
+        }
+    }
+
+    #[test]
+    fn replay_history_round_trip() {
+        let mut played = Sokoban::with_level(1);
+        // 最后一步推不动,不记录
+        for gd in [
+            Gd::Down,
+            Gd::Right,
+            Gd::Right,
+            Gd::Down,
+            Gd::Down,
+            Gd::Left,
+            Gd::Up,
+            Gd::Up,
+        ] {
+            played.walk(gd);
+        }
+        assert_eq!(played.history(), "drrddLU");
+
+        let mut replayed = Sokoban::with_level(1);
+        assert_eq!(replayed.replay(played.history()), Ok(()));
+        assert_eq!(replayed.history(), played.history());
+        assert_eq!(replayed.player.pos, played.player.pos);
+        assert_eq!(boxes(&replayed), boxes(&played));
+
+        // 撤销全部步骤之后回到初始状态
+        while replayed.undo() {}
+        assert_eq!(boxes(&replayed), boxes(&Sokoban::with_level(1)));
+    }
+
+    #[test]
+    fn replay_stops_at_invalid_step() {
+        // #@$-.#
+        let cases = [("L", 0), ("rR", 0), ("R?", 1), ("RRR", 2)];
+        for (lurd, at) in cases {
+            let mut sokoban = Sokoban::with_level(0);
+            assert_eq!(sokoban.replay(lurd), Err(at), "{lurd}");
+            assert_eq!(sokoban.history(), &lurd[..at]);
+        }
     }
 }
@@ -0,0 +1,305 @@
+//! 推箱子关卡生成
+//!
+//! 1. 在房间里随机放几块墙,只保留最大的一块连通的地板
+//! 2. 把箱子放在目标点上,也就是已经完成的状态
+//! 3. 从完成的状态反向拉箱子,每一次拉倒过来都是一次推动,所以生成的关卡一定有解
+//! 4. 用求解器算出最少的推动次数,在几个候选中选择最接近目标难度的
+//!
+//! 求解时限制使用的堆,搜索不完的关卡认为已经达到目标难度.
+//!
+//! 同一个种子和难度总是生成同一个关卡,关卡可以通过种子分享.
+
+use super::solver::{SolveError, Solver};
+use super::SokobanMap;
+use alloc::{string::String, vec::Vec};
+use cube_rand::CubeRng;
+use rand_core::RngCore;
+
+/// 每个难度生成的候选关卡数量
+const CANDIDATES: usize = 6;
+/// 计算难度时搜索最多使用的堆,字节,每次求解完就释放
+const SOLVE_MEMORY: usize = 12 * 1024;
+/// 目标推动次数的上限,更难的关卡生成太慢
+const MAX_PUSHES: usize = 16;
+/// 生成房间的最大尝试次数
+const ROOM_TRIES: usize = 20;
+
+const WALL: u8 = b'#';
+const FLOOR: u8 = b'-';
+const DIRECTIONS: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+/// 生成的参数,由难度决定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Difficulty {
+    /// 房间内部的大小,不包括四周的墙
+    pub size: usize,
+    pub boxes: usize,
+    /// 目标的最少推动次数
+    pub pushes: usize,
+}
+
+impl Difficulty {
+    /// 难度从 0 开始,箱子,房间和推动次数逐渐增加
+    pub fn new(level: u8) -> Self {
+        let level = level as usize;
+        Self {
+            size: if level < 6 { 6 } else { 7 },
+            boxes: (2 + level / 4).min(4),
+            pushes: (4 + level).min(MAX_PUSHES),
+        }
+    }
+}
+
+/// 正在生成的关卡,地图按行存储 XSB 字符
+struct Room {
+    width: usize,
+    height: usize,
+    cells: Vec<u8>,
+    goals: Vec<usize>,
+    boxes: Vec<usize>,
+    player: usize,
+}
+
+impl Room {
+    /// 四周是墙的空房间,内部随机放墙,只保留最大的连通区域
+    fn random(rng: &mut CubeRng, size: usize, boxes: usize) -> Self {
+        let (width, height) = (size + 2, size + 2);
+        let mut room = Self {
+            width,
+            height,
+            cells: Vec::new(),
+            goals: Vec::new(),
+            boxes: Vec::new(),
+            player: 0,
+        };
+        for _ in 0..ROOM_TRIES {
+            room.cells = vec![WALL; width * height];
+            for y in 1..height - 1 {
+                for x in 1..width - 1 {
+                    room.cells[y * width + x] = FLOOR;
+                }
+            }
+            for _ in 0..size * size / 5 {
+                let i = rng.random(1, size as u32 + 1) as usize * width
+                    + rng.random(1, size as u32 + 1) as usize;
+                room.cells[i] = WALL;
+            }
+            room.keep_largest();
+            // 地板太少时箱子推不开
+            let floor = room.cells.iter().filter(|c| **c == FLOOR).count();
+            if floor >= boxes * 3 + 6 {
+                break;
+            }
+        }
+        room
+    }
+
+    fn neighbour(&self, i: usize, (dx, dy): (i32, i32)) -> Option<usize> {
+        let x = (i % self.width) as i32 + dx;
+        let y = (i / self.width) as i32 + dy;
+        let inside = x >= 0 && y >= 0 && x < self.width as i32 && y < self.height as i32;
+        let n = y as usize * self.width + x as usize;
+        (inside && self.cells[n] != WALL).then_some(n)
+    }
+
+    /// 从 `from` 出发能走到的格子,`blocked` 中的格子不能经过
+    fn reach(&self, from: usize, blocked: &[usize]) -> Vec<bool> {
+        let mut seen = vec![false; self.cells.len()];
+        seen[from] = true;
+        let mut stack = vec![from];
+        while let Some(i) = stack.pop() {
+            for d in DIRECTIONS {
+                if let Some(n) = self.neighbour(i, d) {
+                    if !seen[n] && !blocked.contains(&n) {
+                        seen[n] = true;
+                        stack.push(n);
+                    }
+                }
+            }
+        }
+        seen
+    }
+
+    /// 把不和最大区域连通的地板变成墙
+    fn keep_largest(&mut self) {
+        let mut best = Vec::new();
+        let mut count = 0;
+        for i in 0..self.cells.len() {
+            if self.cells[i] != FLOOR || best.get(i) == Some(&true) {
+                continue;
+            }
+            let area = self.reach(i, &[]);
+            let n = area.iter().filter(|r| **r).count();
+            if n > count {
+                count = n;
+                best = area;
+            }
+        }
+        for (i, c) in self.cells.iter_mut().enumerate() {
+            if !best.get(i).copied().unwrap_or(false) {
+                *c = WALL;
+            }
+        }
+    }
+
+    fn floor(&self) -> Vec<usize> {
+        (0..self.cells.len())
+            .filter(|i| self.cells[*i] == FLOOR)
+            .collect()
+    }
+
+    /// 把箱子放在随机的目标点上,玩家放在剩下的格子,地板不够时返回 `false`
+    fn place(&mut self, rng: &mut CubeRng, boxes: usize) -> bool {
+        let mut floor = self.floor();
+        if boxes == 0 || floor.len() <= boxes {
+            return false;
+        }
+        self.goals.clear();
+        for _ in 0..boxes {
+            self.goals
+                .push(floor.swap_remove(rng.random(0, floor.len() as u32) as usize));
+        }
+        self.boxes = self.goals.clone();
+        self.player = floor[rng.random(0, floor.len() as u32) as usize];
+        true
+    }
+
+    /// 反向拉一次箱子: 玩家站在箱子旁边,往后退一步,箱子跟着移动一格
+    fn pull(&mut self, rng: &mut CubeRng) -> bool {
+        let reach = self.reach(self.player, &self.boxes);
+        let mut pulls = Vec::new();
+        for (k, b) in self.boxes.iter().enumerate() {
+            for d in DIRECTIONS {
+                let Some(stand) = self.neighbour(*b, d) else {
+                    continue;
+                };
+                let Some(back) = self.neighbour(stand, d) else {
+                    continue;
+                };
+                if reach[stand] && !self.boxes.contains(&back) {
+                    pulls.push((k, stand, back));
+                }
+            }
+        }
+        if pulls.is_empty() {
+            return false;
+        }
+        let (k, stand, back) = pulls[rng.random(0, pulls.len() as u32) as usize];
+        self.boxes[k] = stand;
+        self.player = back;
+        true
+    }
+
+    fn xsb(&self) -> String {
+        let mut xsb = String::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = y * self.width + x;
+                let goal = self.goals.contains(&i);
+                let c = if self.boxes.contains(&i) {
+                    if goal {
+                        '*'
+                    } else {
+                        '$'
+                    }
+                } else if self.player == i {
+                    if goal {
+                        '+'
+                    } else {
+                        '@'
+                    }
+                } else if goal {
+                    '.'
+                } else {
+                    self.cells[i] as char
+                };
+                xsb.push(c);
+            }
+            xsb.push('\n');
+        }
+        xsb
+    }
+}
+
+/// 生成一个关卡,返回 XSB 格式,所有候选都不能用时返回 `None`
+pub fn generate(seed: u64, difficulty: Difficulty) -> Option<String> {
+    let mut rng = CubeRng(seed);
+    let mut best: Option<(usize, String)> = None;
+    for _ in 0..CANDIDATES {
+        let mut room = Room::random(&mut rng, difficulty.size, difficulty.boxes);
+        if !room.place(&mut rng, difficulty.boxes) {
+            continue;
+        }
+        for _ in 0..difficulty.pushes * 4 {
+            if !room.pull(&mut rng) {
+                break;
+            }
+        }
+        let xsb = room.xsb();
+        let Ok(map) = SokobanMap::from_xsb(&xsb) else {
+            continue;
+        };
+        let boxs = map.boxs.iter().map(|b| b.0 .0).collect::<Vec<_>>();
+        let limit = Solver::limit(SOLVE_MEMORY);
+        let pushes = match Solver::new(&map).solve(map.player.0 .0, &boxs, limit) {
+            Ok(s) => s.pushes.len(),
+            Err(SolveError::TooManyStates) => difficulty.pushes,
+            Err(_) => continue,
+        };
+        // 已经完成的关卡不算
+        if pushes == 0 {
+            continue;
+        }
+        let distance = pushes.abs_diff(difficulty.pushes);
+        if best.as_ref().is_none_or(|b| distance < b.0) {
+            best = Some((distance, xsb));
+        }
+        if pushes >= difficulty.pushes {
+            break;
+        }
+    }
+    best.map(|b| b.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sokoban::{levels::LEVELS, LEVEL_COUNT};
+
+    fn room(cells: &str) -> Room {
+        Room {
+            width: cells.len(),
+            height: 1,
+            cells: cells.bytes().collect(),
+            goals: Vec::new(),
+            boxes: Vec::new(),
+            player: 0,
+        }
+    }
+
+    #[test]
+    fn generated_levels_solvable() {
+        for level in LEVELS.len()..LEVEL_COUNT {
+            let difficulty = Difficulty::new((level - LEVELS.len()) as u8);
+            let xsb = generate(level as u64, difficulty).unwrap();
+            let map = SokobanMap::from_xsb(&xsb).unwrap();
+            let boxs = map.boxs.iter().map(|b| b.0 .0).collect::<Vec<_>>();
+            let solution = Solver::new(&map).solve(map.player.0 .0, &boxs, usize::MAX);
+            assert!(
+                solution.is_ok_and(|s| !s.pushes.is_empty()),
+                "level {level}\n{xsb}"
+            );
+        }
+    }
+
+    #[test]
+    fn place_needs_floor() {
+        let mut rng = CubeRng(1);
+        assert!(!room("#####").place(&mut rng, 2));
+        assert!(!room("#--#").place(&mut rng, 2));
+        let mut r = room("#---#");
+        assert!(r.place(&mut rng, 2));
+        assert_eq!(r.boxes, r.goals);
+        assert!(!r.goals.contains(&r.player));
+    }
+}
@@ -0,0 +1,134 @@
+//! 内置的关卡,按难度从低到高排列
+//!
+//! 每一关都是一个 XSB 字符串,`-` 表示地板,关卡尽量不超过 8*8,
+//! 超出的部分通过视野滚动显示.
+//!
+//! 第一关用来熟悉操作.之后的关卡在随机的房间里放好目标点,从完成的状态开始广度优先搜索所有反向拉箱子的走法,
+//! 离完成最远的状态作为关卡的开始,最少推动次数从 11 逐渐增加到 39.
+
+/// 所有关卡
+pub const LEVELS: [&str; 13] = [
+    "
+######
+#@$-.#
+######
+",
+    "
+#######
+#@#-#.#
+#-----#
+##$---#
+#-$-#-#
+#----.#
+#######
+",
+    "
+#######
+#--.--#
+#-----#
+#-.#-##
+#-$@$##
+###---#
+--#####
+",
+    "
+--######
+-##----#
+-#@#---#
+##-$-$##
+#-#----#
+#---##-#
+##-.-.-#
+-#######
+",
+    "
+-####
+-#--####
+##$--.-#
+#--#---#
+#---#-.#
+#-$---##
+##@#--#
+-######
+",
+    "
+########
+#@--.--#
+####-*-#
+#---#-##
+#---$--#
+#-#----#
+#---##-#
+########
+",
+    "
+--#####
+###@#.#
+#-#$-.#
+#----.#
+##$---#
+#-$-#-#
+#-----#
+#######
+",
+    "
+########
+#.-----#
+#----$##
+#-#-#@-#
+#--##$-#
+#----$.#
+#---##.#
+########
+",
+    "
+#######
+#.----#
+###-$-#
+#-----#
+#-#$###
+##-$--#
+#+--.-#
+#######
+",
+    "
+######
+#@#--###
+#-*-.--#
+#-#--#-#
+#-$-#--#
+##*----#
+#------#
+########
+",
+    "
+########
+#--#@-.#
+#-#*$#$#
+#---$--#
+#--.-#-#
+#------#
+#.---#-#
+########
+",
+    "
+########
+#@-#..-#
+#$$#---#
+#-$----#
+#-#-#-##
+#-#$---#
+#.----.#
+########
+",
+    "
+########
+#--#@-.#
+##--$$-#
+#.--#--#
+##-##$-#
+#.---$-#
+#-.-#--#
+########
+",
+];
@@ -0,0 +1,472 @@
+//! 推箱子求解器和死锁检测
+//!
+//! 以箱子的位置为状态做 A* 搜索,每推动一次箱子算一步,
+//! 估价为每个箱子到最近目标点的曼哈顿距离之和,得到的是推动次数最少的解.
+//! 玩家在两次推动之间可以自由走动,状态中只记录玩家能到达的区域里最小的格子.
+//!
+//! 每个状态记录目前最少的推动次数,找到更少的推动次数时重新加入待展开的队列,
+//! 从队列中取出时才关闭,取出的是目标状态时就是最优解.
+//!
+//! 搜索时剪掉两种死锁:
+//! - 死格: 从目标点反向拉箱子到不了的格子,箱子推进去之后再也到不了任何目标点
+//! - 冻结: 箱子在水平和竖直方向都被墙,死格或者同样冻结的箱子挡住,并且不在目标点上
+
+use super::{forward, step, SokobanMap, TargetType};
+use crate::Gd;
+use alloc::{
+    collections::{BTreeMap, BinaryHeap, VecDeque},
+    string::String,
+    vec::Vec,
+};
+use core::{cmp::Ordering, mem::size_of};
+use embedded_graphics::geometry::Point;
+
+/// 最多支持的箱子数量
+pub const MAX_BOXES: usize = 15;
+/// 最多支持的格子数量,格子的编号用 u8 表示
+const MAX_CELLS: usize = 256;
+const DIRECTIONS: [Gd; 4] = [Gd::Up, Gd::Right, Gd::Down, Gd::Left];
+
+/// 求解错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolveError {
+    /// 地图或者箱子数量超出了求解器的范围
+    TooLarge,
+    /// 搜索的状态超过了限制
+    TooManyStates,
+    /// 无解
+    Unsolvable,
+}
+
+/// 一次推动
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Push {
+    /// 推动之前箱子的位置
+    pub from: Point,
+    /// 推动的方向
+    pub gd: Gd,
+}
+
+/// 推动次数最少的解
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solution {
+    pub pushes: Vec<Push>,
+    /// 包括玩家走动的完整步骤,LURD 格式
+    pub lurd: String,
+}
+
+/// 玩家能到达的区域中最小的格子和箱子的位置,箱子按编号排序
+type State = heapless::Vec<u8, { MAX_BOXES + 1 }>;
+
+/// 搜索树中的节点
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    parent: u32,
+    /// 推动之前箱子的格子和推动的方向
+    push: (u8, u8),
+}
+
+/// 待展开的状态,按照估价从小到大
+///
+/// 估价相同时优先展开推动次数多的,更接近目标;估价不会高估,取出时才检查目标,不影响最优.
+#[derive(Debug, PartialEq, Eq)]
+struct Open {
+    f: u16,
+    g: u16,
+    node: u32,
+    state: State,
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.f.cmp(&self.f).then(self.g.cmp(&other.g))
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// 搜索过的状态
+#[derive(Debug, Clone, Copy)]
+struct Seen {
+    /// 目前最少的推动次数
+    g: u16,
+    /// 已经展开过
+    closed: bool,
+}
+
+/// 地图中不变的部分: 墙,目标点和死格
+#[derive(Debug, Clone)]
+pub struct Solver {
+    width: usize,
+    height: usize,
+    walls: Vec<bool>,
+    goals: Vec<bool>,
+    dead: Vec<bool>,
+}
+
+impl Solver {
+    /// 每个搜索过的状态大约占用的堆: 搜索树,待展开的队列和搜索过的状态,按扩容之后的两倍计算
+    pub const STATE_BYTES: usize =
+        2 * (size_of::<Node>() + size_of::<Open>() + size_of::<(State, Seen)>());
+
+    /// 用 `bytes` 字节的堆能搜索的状态数量,作为 [`Solver::solve`] 的 `limit`
+    pub const fn limit(bytes: usize) -> usize {
+        bytes / Self::STATE_BYTES
+    }
+
+    pub(super) fn new(map: &SokobanMap) -> Self {
+        let (width, height) = (map.map.width, map.map.height);
+        let mut solver = Self {
+            width,
+            height,
+            walls: vec![false; width * height],
+            goals: vec![false; width * height],
+            dead: Vec::new(),
+        };
+        for (p, t) in map.map.data.iter() {
+            if let Some(i) = solver.index(p.0) {
+                match t {
+                    TargetType::Wall => solver.walls[i] = true,
+                    TargetType::Goal => solver.goals[i] = true,
+                    _ => {}
+                }
+            }
+        }
+        solver.dead = solver.dead_cells();
+        solver
+    }
+
+    fn index(&self, p: Point) -> Option<usize> {
+        let inside = p.x >= 0 && p.y >= 0 && p.x < self.width as i32 && p.y < self.height as i32;
+        inside.then_some(p.y as usize * self.width + p.x as usize)
+    }
+
+    fn point(&self, i: usize) -> Point {
+        Point::new((i % self.width) as i32, (i / self.width) as i32)
+    }
+
+    /// 相邻的格子,地图外面当成墙,返回 `None`
+    fn next(&self, i: usize, gd: Gd) -> Option<usize> {
+        self.index(forward(self.point(i), gd))
+            .filter(|n| !self.walls[*n])
+    }
+
+    /// 从每个目标点反向拉箱子,拉不到的格子是死格
+    fn dead_cells(&self) -> Vec<bool> {
+        let mut live = self.goals.clone();
+        let mut queue = (0..live.len())
+            .filter(|i| live[*i])
+            .collect::<VecDeque<_>>();
+        while let Some(i) = queue.pop_front() {
+            for gd in DIRECTIONS {
+                // 箱子拉到 `to`,拉完之后玩家站在 `to` 的前面
+                let Some(to) = self.next(i, gd) else {
+                    continue;
+                };
+                if self.next(to, gd).is_some() && !live[to] {
+                    live[to] = true;
+                    queue.push_back(to);
+                }
+            }
+        }
+        live.iter().map(|l| !l).collect()
+    }
+
+    /// 箱子在 `cell` 是否冻结并且不在目标点上
+    pub fn deadlocked(&self, boxes: &[Point], cell: Point) -> bool {
+        let boxes = boxes
+            .iter()
+            .filter_map(|b| self.index(*b))
+            .collect::<Vec<_>>();
+        self.index(cell)
+            .is_some_and(|i| self.dead[i] || self.frozen_off_goal(&boxes, i))
+    }
+
+    fn frozen_off_goal(&self, boxes: &[usize], i: usize) -> bool {
+        let mut frozen = Vec::new();
+        self.frozen(boxes, i, &mut frozen) && frozen.iter().any(|b| !self.goals[*b])
+    }
+
+    /// 箱子在两个方向上都不能移动,`frozen` 中是一起被冻结的箱子
+    fn frozen(&self, boxes: &[usize], i: usize, frozen: &mut Vec<usize>) -> bool {
+        let len = frozen.len();
+        frozen.push(i);
+        let ok = self.blocked(boxes, i, Gd::Left, frozen) && self.blocked(boxes, i, Gd::Up, frozen);
+        if !ok {
+            frozen.truncate(len);
+        }
+        ok
+    }
+
+    /// 箱子沿着 `gd` 所在的轴不能移动
+    fn blocked(&self, boxes: &[usize], i: usize, gd: Gd, frozen: &mut Vec<usize>) -> bool {
+        let (a, b) = (self.next(i, gd), self.next(i, gd.opposite()));
+        let (Some(a), Some(b)) = (a, b) else {
+            return true;
+        };
+        if self.dead[a] && self.dead[b] {
+            return true;
+        }
+        // 正在检查的箱子当成墙
+        [a, b]
+            .into_iter()
+            .any(|n| boxes.contains(&n) && (frozen.contains(&n) || self.frozen(boxes, n, frozen)))
+    }
+
+    /// 玩家从 `from` 出发能到达的格子
+    fn reach(&self, from: usize, boxes: &[u8]) -> Vec<bool> {
+        let mut seen = vec![false; self.walls.len()];
+        seen[from] = true;
+        let mut stack = vec![from];
+        while let Some(i) = stack.pop() {
+            for gd in DIRECTIONS {
+                if let Some(n) = self.next(i, gd) {
+                    if !seen[n] && !boxes.contains(&(n as u8)) {
+                        seen[n] = true;
+                        stack.push(n);
+                    }
+                }
+            }
+        }
+        seen
+    }
+
+    /// 每个箱子到最近的目标点的曼哈顿距离之和
+    fn estimate(&self, boxes: &[u8]) -> u16 {
+        let goals = (0..self.goals.len())
+            .filter(|i| self.goals[*i])
+            .map(|i| self.point(i))
+            .collect::<Vec<_>>();
+        boxes
+            .iter()
+            .map(|b| {
+                let p = self.point(*b as usize);
+                goals
+                    .iter()
+                    .map(|g| ((g.x - p.x).abs() + (g.y - p.y).abs()) as u16)
+                    .min()
+                    .unwrap_or(0)
+            })
+            .sum()
+    }
+
+    fn state(&self, player: usize, boxes: &[u8]) -> State {
+        let reach = self.reach(player, boxes);
+        let mut state = State::new();
+        let _ = state.push(reach.iter().position(|r| *r).unwrap_or(player) as u8);
+        let _ = state.extend_from_slice(boxes);
+        state
+    }
+
+    /// 求推动次数最少的解,展开的状态超过 `limit` 时放弃
+    pub fn solve(
+        &self,
+        player: Point,
+        boxes: &[Point],
+        limit: usize,
+    ) -> Result<Solution, SolveError> {
+        if self.walls.len() > MAX_CELLS || boxes.len() > MAX_BOXES {
+            return Err(SolveError::TooLarge);
+        }
+        let player = self.index(player).ok_or(SolveError::Unsolvable)?;
+        let mut start = boxes
+            .iter()
+            .map(|b| self.index(*b).map(|i| i as u8))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(SolveError::Unsolvable)?;
+        start.sort_unstable();
+
+        let mut nodes = vec![Node {
+            parent: u32::MAX,
+            push: (0, 0),
+        }];
+        let mut seen = BTreeMap::new();
+        let mut open = BinaryHeap::new();
+        let state = self.state(player, &start);
+        seen.insert(
+            state.clone(),
+            Seen {
+                g: 0,
+                closed: false,
+            },
+        );
+        open.push(Open {
+            f: self.estimate(&start),
+            g: 0,
+            node: 0,
+            state,
+        });
+
+        while let Some(Open { g, node, state, .. }) = open.pop() {
+            // 已经用更少的推动次数展开过
+            match seen.get_mut(&state) {
+                Some(s) if !s.closed && s.g == g => s.closed = true,
+                _ => continue,
+            }
+            let boxes = &state[1..];
+            if boxes.iter().all(|b| self.goals[*b as usize]) {
+                return Ok(self.solution(player, start, &nodes, node));
+            }
+            let reach = self.reach(state[0] as usize, boxes);
+            for (k, b) in boxes.iter().enumerate() {
+                let b = *b as usize;
+                for (d, gd) in DIRECTIONS.into_iter().enumerate() {
+                    let (Some(stand), Some(to)) = (self.next(b, gd.opposite()), self.next(b, gd))
+                    else {
+                        continue;
+                    };
+                    if !reach[stand] || self.dead[to] || boxes.contains(&(to as u8)) {
+                        continue;
+                    }
+                    let mut next = Vec::from(boxes);
+                    next[k] = to as u8;
+                    next.sort_unstable();
+                    let cells = next.iter().map(|c| *c as usize).collect::<Vec<_>>();
+                    if self.frozen_off_goal(&cells, to) {
+                        continue;
+                    }
+                    let state = self.state(b, &next);
+                    if seen.get(&state).is_some_and(|s| s.closed || s.g <= g + 1) {
+                        continue;
+                    }
+                    if nodes.len() >= limit {
+                        return Err(SolveError::TooManyStates);
+                    }
+                    nodes.push(Node {
+                        parent: node,
+                        push: (b as u8, d as u8),
+                    });
+                    seen.insert(
+                        state.clone(),
+                        Seen {
+                            g: g + 1,
+                            closed: false,
+                        },
+                    );
+                    open.push(Open {
+                        f: g + 1 + self.estimate(&next),
+                        g: g + 1,
+                        node: nodes.len() as u32 - 1,
+                        state,
+                    });
+                }
+            }
+        }
+        Err(SolveError::Unsolvable)
+    }
+
+    /// 从搜索树还原推动的顺序,补上玩家在两次推动之间的走动
+    fn solution(
+        &self,
+        player: usize,
+        mut boxes: Vec<u8>,
+        nodes: &[Node],
+        mut node: u32,
+    ) -> Solution {
+        let mut pushes = Vec::new();
+        while node != 0 {
+            let n = nodes[node as usize];
+            pushes.push(n.push);
+            node = n.parent;
+        }
+        pushes.reverse();
+
+        let mut lurd = String::new();
+        let mut player = player;
+        for (b, d) in pushes.iter() {
+            let (b, gd) = (*b as usize, DIRECTIONS[*d as usize]);
+            let stand = self.next(b, gd.opposite()).unwrap_or(b);
+            lurd.extend(self.walk(player, stand, &boxes));
+            lurd.extend(step(gd, true));
+            let to = self.next(b, gd).unwrap_or(b);
+            if let Some(k) = boxes.iter().position(|c| *c as usize == b) {
+                boxes[k] = to as u8;
+            }
+            player = b;
+        }
+        Solution {
+            pushes: pushes
+                .iter()
+                .map(|(b, d)| Push {
+                    from: self.point(*b as usize),
+                    gd: DIRECTIONS[*d as usize],
+                })
+                .collect(),
+            lurd,
+        }
+    }
+
+    /// 玩家从 `from` 走到 `to` 的最短路径,LURD 格式
+    fn walk(&self, from: usize, to: usize, boxes: &[u8]) -> Vec<char> {
+        let mut prev = vec![None; self.walls.len()];
+        let mut queue = VecDeque::from([from]);
+        prev[from] = Some((from, Gd::None));
+        while let Some(i) = queue.pop_front() {
+            if i == to {
+                break;
+            }
+            for gd in DIRECTIONS {
+                if let Some(n) = self.next(i, gd) {
+                    if prev[n].is_none() && !boxes.contains(&(n as u8)) {
+                        prev[n] = Some((i, gd));
+                        queue.push_back(n);
+                    }
+                }
+            }
+        }
+        let mut path = Vec::new();
+        let mut i = to;
+        while let Some((p, gd)) = prev[i] {
+            if i == from {
+                break;
+            }
+            path.extend(step(gd, false));
+            i = p;
+        }
+        path.reverse();
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sokoban::levels::LEVELS;
+
+    /// 内置关卡最少的推动次数,用不剪枝的广度优先搜索验证过
+    const MIN_PUSHES: [usize; LEVELS.len()] = [2, 11, 13, 16, 18, 19, 19, 21, 22, 24, 26, 30, 39];
+
+    fn solve(xsb: &str, limit: usize) -> Result<Solution, SolveError> {
+        let map = SokobanMap::from_xsb(xsb).unwrap();
+        let boxes = map.boxs.iter().map(|b| b.0 .0).collect::<Vec<_>>();
+        Solver::new(&map).solve(map.player.0 .0, &boxes, limit)
+    }
+
+    #[test]
+    fn bundled_levels_minimal() {
+        for (level, xsb) in LEVELS.iter().enumerate() {
+            let solution = solve(xsb, usize::MAX).unwrap();
+            let pushes = solution.lurd.chars().filter(char::is_ascii_uppercase);
+            assert_eq!(solution.pushes.len(), MIN_PUSHES[level], "level {level}");
+            assert_eq!(pushes.count(), MIN_PUSHES[level], "level {level}");
+        }
+    }
+
+    #[test]
+    fn unsolvable() {
+        // 箱子在角落里
+        assert_eq!(
+            solve("####\n#@$#\n#.-#\n####", usize::MAX),
+            Err(SolveError::Unsolvable)
+        );
+    }
+
+    #[test]
+    fn state_limit() {
+        assert_eq!(solve(LEVELS[12], 1), Err(SolveError::TooManyStates));
+    }
+}
@@ -0,0 +1,377 @@
+//! XSB 关卡解析
+//!
+//! 支持完整的 XSB 字符集:
+//! - `#` 墙,`@` 玩家,`+` 玩家在目标点上,`$` 箱子,`*` 箱子在目标点上,`.` 目标点
+//! - `-`,`_` 和空格都是地板
+//! - 行程编码: 字符前面的数字表示重复次数,`|` 表示换行,例如 `3#|#@$.#|3#`
+//!
+//! 一个文件可以包含多个关卡,关卡之间用空行或者其他文字隔开.
+//! `Title:` 和 `Author:` 写在关卡前面或者后面都可以,其他文字被忽略.
+//! 地图中的地板统一保存为 `-`,每行末尾的地板被去掉,
+//! 所以 `Level` 输出的 XSB 再解析会得到相同的关卡.
+
+use alloc::{string::String, vec::Vec};
+use core::{fmt, str::FromStr};
+
+/// 解析错误,行号和列号从 1 开始
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XsbError {
+    /// 没有关卡
+    Empty,
+    /// 无法识别的字符
+    BadChar { line: usize, col: usize, char: char },
+    /// 行程编码的数字后面没有字符
+    BadRunLength { line: usize, col: usize },
+    /// 没有玩家,`line` 为关卡的第一行
+    NoPlayer { line: usize },
+    /// 多于一个玩家
+    ManyPlayers { line: usize, col: usize },
+    /// 箱子和目标点的数量不相等,`line` 为关卡的第一行
+    Mismatch {
+        line: usize,
+        boxes: usize,
+        goals: usize,
+    },
+    /// 墙没有封闭,玩家可以从这个位置走出地图
+    Unclosed { line: usize, col: usize },
+}
+
+/// 一个关卡
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Level {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub width: usize,
+    pub height: usize,
+    /// 按行保存的格子,地板为 `-`
+    cells: Vec<u8>,
+}
+
+impl Level {
+    /// 格子的 XSB 字符,超出地图时为地板
+    pub fn get(&self, x: usize, y: usize) -> char {
+        if x < self.width && y < self.height {
+            self.cells[y * self.width + x] as char
+        } else {
+            '-'
+        }
+    }
+
+    /// 所有格子和坐标
+    pub fn cells(&self) -> impl Iterator<Item = (usize, usize, char)> + '_ {
+        self.cells
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (i % self.width, i / self.width, *c as char))
+    }
+}
+
+/// 先写出元数据,再写出地图
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(title) = &self.title {
+            writeln!(f, "Title: {title}")?;
+        }
+        if let Some(author) = &self.author {
+            writeln!(f, "Author: {author}")?;
+        }
+        for row in self.cells.chunks(self.width) {
+            let len = row.iter().rposition(|c| *c != b'-').map_or(0, |i| i + 1);
+            for c in &row[..len] {
+                write!(f, "{}", *c as char)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// 解析第一个关卡
+impl FromStr for Level {
+    type Err = XsbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s)?.into_iter().next().ok_or(XsbError::Empty)
+    }
+}
+
+/// 地图中的一格和它在文本中的位置
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    char: u8,
+    line: usize,
+    col: usize,
+}
+
+/// 正在解析的关卡
+#[derive(Default)]
+struct Pending {
+    title: Option<String>,
+    author: Option<String>,
+    line: usize,
+    rows: Vec<Vec<Cell>>,
+}
+
+/// 解析文件中的所有关卡
+pub fn parse(text: &str) -> Result<Vec<Level>, XsbError> {
+    let mut levels: Vec<Level> = Vec::new();
+    let mut pending = Pending::default();
+    // 上一个关卡结束之后是否出现过新的地图行
+    let mut in_board = false;
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        if is_board(line) {
+            if !in_board {
+                pending.line = line_no;
+            }
+            in_board = true;
+            pending.rows.extend(expand(line, line_no)?);
+            continue;
+        }
+        if in_board {
+            levels.push(finish(&mut pending)?);
+            in_board = false;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = String::from(value.trim());
+        // 关卡后面的元数据属于上一个关卡,已经有这一项时属于下一个关卡
+        let last = levels.last_mut();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "title" => match last {
+                Some(l) if l.title.is_none() && pending.title.is_none() => l.title = Some(value),
+                _ => pending.title = Some(value),
+            },
+            "author" => match last {
+                Some(l) if l.author.is_none() && pending.author.is_none() => l.author = Some(value),
+                _ => pending.author = Some(value),
+            },
+            _ => {}
+        }
+    }
+    if in_board {
+        levels.push(finish(&mut pending)?);
+    }
+    if levels.is_empty() {
+        return Err(XsbError::Empty);
+    }
+    Ok(levels)
+}
+
+/// 去掉开头的地板和行程编码的数字之后以墙开始的行是地图行
+fn is_board(line: &str) -> bool {
+    line.trim_start_matches([' ', '-', '_'])
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .starts_with('#')
+}
+
+/// 展开行程编码,一行文本可能包含多行地图
+fn expand(line: &str, line_no: usize) -> Result<Vec<Vec<Cell>>, XsbError> {
+    let mut rows = vec![Vec::new()];
+    let mut count: Option<usize> = None;
+    for (i, char) in line.trim_end().chars().enumerate() {
+        let col = i + 1;
+        if let Some(d) = char.to_digit(10) {
+            count = Some(count.unwrap_or(0) * 10 + d as usize);
+            continue;
+        }
+        let c = match char {
+            '#' | '@' | '+' | '$' | '*' | '.' => char as u8,
+            '-' | '_' | ' ' => b'-',
+            '|' if count.is_none() => {
+                rows.push(Vec::new());
+                continue;
+            }
+            '|' => return Err(XsbError::BadRunLength { line: line_no, col }),
+            _ => {
+                return Err(XsbError::BadChar {
+                    line: line_no,
+                    col,
+                    char,
+                })
+            }
+        };
+        let cell = Cell {
+            char: c,
+            line: line_no,
+            col,
+        };
+        let row = rows.last_mut().unwrap();
+        row.extend(core::iter::repeat_n(cell, count.take().unwrap_or(1)));
+    }
+    if count.is_some() {
+        let col = line.trim_end().chars().count();
+        return Err(XsbError::BadRunLength { line: line_no, col });
+    }
+    Ok(rows)
+}
+
+/// 检查并生成关卡
+fn finish(pending: &mut Pending) -> Result<Level, XsbError> {
+    let Pending {
+        title,
+        author,
+        line,
+        mut rows,
+    } = core::mem::take(pending);
+    for row in rows.iter_mut() {
+        while row.last().is_some_and(|c| c.char == b'-') {
+            row.pop();
+        }
+    }
+    let width = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    let height = rows.len();
+    let at = |x: usize, y: usize| rows[y].get(x).map_or(b'-', |c| c.char);
+    // 补齐的格子的位置是这一行最后一个字符之后
+    let pos = |x: usize, y: usize| match rows[y].get(x) {
+        Some(c) => (c.line, c.col),
+        None => {
+            let last = rows[y].last().map_or((line + y, 0), |c| (c.line, c.col));
+            (last.0, last.1 + x + 1 - rows[y].len())
+        }
+    };
+
+    let mut player = None;
+    let (mut boxes, mut goals) = (0, 0);
+    for y in 0..height {
+        for x in 0..width {
+            let c = at(x, y);
+            if matches!(c, b'@' | b'+') {
+                if player.is_some() {
+                    let (line, col) = pos(x, y);
+                    return Err(XsbError::ManyPlayers { line, col });
+                }
+                player = Some((x, y));
+            }
+            boxes += matches!(c, b'$' | b'*') as usize;
+            goals += matches!(c, b'.' | b'*' | b'+') as usize;
+        }
+    }
+    let Some(player) = player else {
+        return Err(XsbError::NoPlayer { line });
+    };
+    if boxes != goals {
+        return Err(XsbError::Mismatch { line, boxes, goals });
+    }
+
+    // 从玩家出发能走到地图边缘说明墙没有封闭
+    let mut seen = vec![false; width * height];
+    let mut stack = vec![player];
+    seen[player.1 * width + player.0] = true;
+    while let Some((x, y)) = stack.pop() {
+        if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+            let (line, col) = pos(x, y);
+            return Err(XsbError::Unclosed { line, col });
+        }
+        for (nx, ny) in [(x, y - 1), (x + 1, y), (x, y + 1), (x - 1, y)] {
+            if at(nx, ny) != b'#' && !seen[ny * width + nx] {
+                seen[ny * width + nx] = true;
+                stack.push((nx, ny));
+            }
+        }
+    }
+
+    let mut cells = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            cells.push(at(x, y));
+        }
+    }
+    Ok(Level {
+        title,
+        author,
+        width,
+        height,
+        cells,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sokoban::levels::LEVELS;
+    use alloc::string::ToString;
+
+    #[test]
+    fn bundled_levels_round_trip() {
+        for xsb in LEVELS {
+            let level: Level = xsb.parse().unwrap();
+            assert_eq!(level.to_string().parse(), Ok(level));
+        }
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let text = "\
+Title: first
+5#|#@$.#|5#
+Author: someone
+
+; comment
+Title: second
+__#####
+###___#
+#.@$__#
+###_$.#
+#.##$_#
+#_#_._##
+#$_*$$.#
+#___.__#
+########
+Author: another
+";
+        let levels = parse(text).unwrap();
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels[0].title.as_deref(), Some("first"));
+        assert_eq!(levels[0].author.as_deref(), Some("someone"));
+        assert_eq!(levels[1].author.as_deref(), Some("another"));
+        assert_eq!((levels[0].width, levels[0].height), (5, 3));
+        assert_eq!(levels[1].get(3, 2), '$');
+        assert_eq!(levels[1].get(0, 0), '-');
+
+        let written = levels
+            .iter()
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(parse(&written), Ok(levels));
+        assert!(written.starts_with("Title: first\nAuthor: someone\n#####\n#@$.#\n"));
+    }
+
+    #[test]
+    fn errors() {
+        let cases = [
+            ("no map", XsbError::Empty),
+            (
+                "#####\n#@$x#\n#####",
+                XsbError::BadChar {
+                    line: 2,
+                    col: 4,
+                    char: 'x',
+                },
+            ),
+            ("5#|#@$.#|5", XsbError::BadRunLength { line: 1, col: 10 }),
+            ("#####\n#-$.#\n#####", XsbError::NoPlayer { line: 1 }),
+            (
+                "#####\n#@$@#\n#.###\n####",
+                XsbError::ManyPlayers { line: 2, col: 4 },
+            ),
+            (
+                "######\n#@$$.#\n######",
+                XsbError::Mismatch {
+                    line: 1,
+                    boxes: 2,
+                    goals: 1,
+                },
+            ),
+            (
+                "#####\n#@$.-\n#####",
+                XsbError::Unclosed { line: 2, col: 5 },
+            ),
+        ];
+        for (text, error) in cases {
+            assert_eq!(parse(text), Err(error), "{text}");
+        }
+    }
+}
@@ -0,0 +1,442 @@
+//! 持久化的设置和最高分
+//!
+//! flash 中的布局:
+//!
+//! ```text
+//! | magic "XF" | 版本 u8 | 长度 u16 | 数据 | crc32 u32 |
+//! ```
+//!
+//! 多字节整数均为小端,crc32 覆盖版本,长度和数据.
+//! 新的字段只追加在数据末尾,读取旧版本的数据时缺少的字段使用默认值.
+//!
+//! 设置作为一条记录保存在 [`Journal`] 中,日志为空时从旧的固定地址迁移,
+//! 没有文件头时按照最早的 8 字节布局迁移.迁移的结果立即写入日志,之后不再读取旧地址.
+
+use crate::{
+    calibration::Calibration,
+    journal::{Journal, JournalError},
+};
+use alloc::vec::Vec;
+use embedded_storage::nor_flash::NorFlash;
+
+/// 旧版本设置在 flash 中的固定地址,只用于迁移
+pub const LEGACY_ADDR: u32 = 0x9100;
+/// 日志的起始地址,位于默认分区表的 nvs 分区中
+pub const JOURNAL_ADDR: u32 = 0xa000;
+/// 日志占用的扇区数量
+pub const JOURNAL_SECTORS: u32 = 4;
+
+const MAGIC: &[u8; 2] = b"XF";
+/// 当前的数据版本
+const VERSION: u8 = 1;
+/// 文件头长度
+const HEADER_LEN: usize = 5;
+/// 数据的最大长度
+const MAX_LEN: usize = 256;
+
+/// 设置解析错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsError {
+    /// 没有文件头
+    Magic,
+    /// 数据长度超出范围
+    Length,
+    /// 校验失败
+    Crc,
+}
+
+/// 各个游戏的最高分
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Highest {
+    /// 贪吃蛇每种模式的最高分,按 `SnakeMode` 的顺序
+    pub snake: [u8; 4],
+    /// 方块人下过的楼梯数量,超过 255 时旧的单字节字段保存为 255
+    pub cube_man: u16,
+    /// 躲方块的最高分,超过 255 时旧的单字节字段保存为 255
+    pub dodge_cube: u16,
+}
+
+/// 设置
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub highest: Highest,
+    /// 是否开启声音
+    pub sound: bool,
+    /// 亮度
+    pub brightness: u8,
+    /// 最后一次进入的菜单
+    pub last_menu: u8,
+    pub calibration: Calibration,
+    /// 推箱子已解锁的最高关卡,从 0 开始
+    pub sokoban: u8,
+    /// 迷宫每种大小的最短用时,秒,按 `maze::SIZES` 的顺序,0 表示没有记录
+    pub maze: [u16; 8],
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            highest: Highest::default(),
+            sound: true,
+            brightness: 0x01,
+            last_menu: 0,
+            calibration: Calibration::default(),
+            sokoban: 0,
+            maze: [0; 8],
+        }
+    }
+}
+
+/// 按顺序读取数据,读完之后返回 `None`
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        if self.bytes.len() < N {
+            self.bytes = &[];
+            return None;
+        }
+        let (head, tail) = self.bytes.split_at(N);
+        self.bytes = tail;
+        head.try_into().ok()
+    }
+
+    fn u8(&mut self, v: &mut u8) {
+        if let Some([b]) = self.take() {
+            *v = b;
+        }
+    }
+
+    fn bool(&mut self, v: &mut bool) {
+        if let Some([b]) = self.take() {
+            *v = b != 0;
+        }
+    }
+
+    fn u16(&mut self, v: &mut u16) {
+        if let Some(b) = self.take() {
+            *v = u16::from_le_bytes(b);
+        }
+    }
+
+    fn f32(&mut self, v: &mut f32) {
+        if let Some(b) = self.take() {
+            *v = f32::from_le_bytes(b);
+        }
+    }
+}
+
+impl Settings {
+    /// 序列化数据部分,新字段只能追加在末尾
+    fn payload(&self) -> Vec<u8> {
+        let mut p = Vec::new();
+        p.push(self.highest.snake[0]);
+        p.push(self.highest.cube_man.min(u8::MAX as u16) as u8);
+        p.push(self.highest.dodge_cube.min(u8::MAX as u16) as u8);
+        p.push(self.sound as u8);
+        p.push(self.brightness);
+        p.push(self.last_menu);
+        for v in self.calibration.accel {
+            p.extend_from_slice(&v.to_le_bytes());
+        }
+        // 原来的角速度偏移,不再使用,保留位置
+        p.extend_from_slice(&[0; 12]);
+        p.push(self.sokoban);
+        for v in self.maze {
+            p.extend_from_slice(&v.to_le_bytes());
+        }
+        // 经典模式的最高分在最前面,其他模式的追加在末尾
+        p.extend_from_slice(&self.highest.snake[1..]);
+        p.extend_from_slice(&self.highest.cube_man.to_le_bytes());
+        p.extend_from_slice(&self.highest.dodge_cube.to_le_bytes());
+        p
+    }
+
+    /// 反序列化数据部分,缺少的字段保持默认值
+    fn from_payload(_version: u8, payload: &[u8]) -> Self {
+        let mut s = Self::default();
+        let mut r = Reader { bytes: payload };
+        r.u8(&mut s.highest.snake[0]);
+        let mut cube_man = 0;
+        r.u8(&mut cube_man);
+        s.highest.cube_man = cube_man as u16;
+        let mut dodge_cube = 0;
+        r.u8(&mut dodge_cube);
+        s.highest.dodge_cube = dodge_cube as u16;
+        r.bool(&mut s.sound);
+        r.u8(&mut s.brightness);
+        r.u8(&mut s.last_menu);
+        for v in s.calibration.accel.iter_mut() {
+            r.f32(v);
+        }
+        // 原来的角速度偏移
+        r.take::<12>();
+        r.u8(&mut s.sokoban);
+        for v in s.maze.iter_mut() {
+            r.u16(v);
+        }
+        for v in s.highest.snake[1..].iter_mut() {
+            r.u8(v);
+        }
+        // 完整的方块人最高分,没有时使用单字节字段
+        r.u16(&mut s.highest.cube_man);
+        // 完整的躲方块最高分
+        r.u16(&mut s.highest.dodge_cube);
+        s
+    }
+
+    /// 最早的布局: 第 0 个字节为贪吃蛇最高分,第 1 个字节为方块人最高分,未写入过为 0xff
+    fn from_legacy(bytes: &[u8; 8]) -> Self {
+        let score = |b: u8| if b == 0xff { 0 } else { b };
+        let mut s = Self::default();
+        s.highest.snake[0] = score(bytes[0]);
+        s.highest.cube_man = score(bytes[1]) as u16;
+        s
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(&self.payload())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SettingsError> {
+        if bytes.len() < HEADER_LEN || &bytes[..2] != MAGIC {
+            return Err(SettingsError::Magic);
+        }
+        let version = bytes[2];
+        let len = u16::from_le_bytes([bytes[3], bytes[4]]) as usize;
+        if len > MAX_LEN || bytes.len() < HEADER_LEN + len + 4 {
+            return Err(SettingsError::Length);
+        }
+        let end = HEADER_LEN + len;
+        let crc = u32::from_le_bytes(bytes[end..end + 4].try_into().unwrap());
+        if crc != crc32(&bytes[MAGIC.len()..end]) {
+            return Err(SettingsError::Crc);
+        }
+        Ok(Self::from_payload(version, &bytes[HEADER_LEN..end]))
+    }
+}
+
+/// 加上文件头和校验
+fn encode(payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len() + 4);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(payload);
+    let crc = crc32(&bytes[MAGIC.len()..]);
+    bytes.extend_from_slice(&crc.to_le_bytes());
+    bytes
+}
+
+/// CRC-32 (IEEE 802.3)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// 在 flash 中读写设置
+pub struct SettingsStore<F: NorFlash> {
+    journal: Journal<F>,
+}
+
+impl<F: NorFlash> SettingsStore<F> {
+    pub fn new(flash: F) -> Self {
+        Self {
+            journal: Journal::mount(flash, JOURNAL_ADDR, JOURNAL_SECTORS),
+        }
+    }
+
+    /// 读取设置,数据损坏时使用默认值,旧的布局会被迁移
+    pub fn load(&mut self) -> Settings {
+        match self.journal.latest() {
+            Ok(Some(bytes)) => match Settings::from_bytes(&bytes) {
+                Ok(s) => s,
+                Err(e) => {
+                    log::error!("load settings error: {e:?}");
+                    Settings::default()
+                }
+            },
+            Ok(None) => match self.load_legacy() {
+                Some(s) => {
+                    if let Err(e) = self.save(&s) {
+                        log::error!("save migrated settings error: {e:?}");
+                    }
+                    s
+                }
+                None => Settings::default(),
+            },
+            Err(e) => {
+                log::error!("load settings error: {e:?}");
+                Settings::default()
+            }
+        }
+    }
+
+    /// 读取旧的固定地址中的设置,读取失败或者数据损坏时返回 `None`
+    fn load_legacy(&mut self) -> Option<Settings> {
+        // 读取的长度需要按 4 字节对齐
+        let mut bytes = [0u8; (HEADER_LEN + MAX_LEN + 4).next_multiple_of(4)];
+        if let Err(e) = self.journal.flash().read(LEGACY_ADDR, &mut bytes) {
+            log::error!("read legacy settings error: {e:?}");
+            return None;
+        }
+        match Settings::from_bytes(&bytes) {
+            Ok(s) => Some(s),
+            Err(SettingsError::Magic) => {
+                let legacy = bytes[..8].try_into().unwrap();
+                Some(Settings::from_legacy(&legacy))
+            }
+            Err(e) => {
+                log::error!("load legacy settings error: {e:?}");
+                None
+            }
+        }
+    }
+
+    pub fn save(&mut self, settings: &Settings) -> Result<(), JournalError<F::Error>> {
+        self.journal.append(&settings.to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::fake::FakeNorFlash;
+    use embedded_storage::nor_flash::ReadNorFlash;
+
+    const FLASH_SIZE: usize = 0x10000;
+
+    fn settings() -> Settings {
+        Settings {
+            highest: Highest {
+                snake: [12, 34, 56, 78],
+                cube_man: 9,
+                dodge_cube: 10,
+            },
+            sound: false,
+            brightness: 0x20,
+            last_menu: 3,
+            calibration: Calibration {
+                accel: [0.01, -0.02, 0.03],
+            },
+            sokoban: 4,
+            maze: [0, 15, 30, 45, 60, 75, 90, 105],
+        }
+    }
+
+    /// 在旧的固定地址写入数据,长度补齐到 4 字节
+    fn write_legacy(flash: &mut FakeNorFlash, bytes: &[u8]) {
+        let mut bytes = bytes.to_vec();
+        bytes.resize(bytes.len().next_multiple_of(4), 0xff);
+        flash.write(LEGACY_ADDR, &bytes).unwrap();
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let s = settings();
+        assert_eq!(Settings::from_bytes(&s.to_bytes()), Ok(s));
+
+        let mut bytes = s.to_bytes();
+        bytes[HEADER_LEN] ^= 1;
+        assert_eq!(Settings::from_bytes(&bytes), Err(SettingsError::Crc));
+        assert_eq!(Settings::from_bytes(&[0xff; 8]), Err(SettingsError::Magic));
+    }
+
+    #[test]
+    fn old_payload_uses_defaults() {
+        // 只有前 6 个字段的旧数据
+        let s = settings();
+        let old = Settings::from_bytes(&encode(&s.payload()[..6])).unwrap();
+        assert_eq!(old.highest.snake, [12, 0, 0, 0]);
+        assert_eq!(old.highest.cube_man, 9);
+        assert_eq!(old.last_menu, 3);
+        assert_eq!(old.calibration, Calibration::default());
+        assert_eq!(old.maze, [0; 8]);
+    }
+
+    #[test]
+    fn wide_cube_man_score() {
+        let mut s = settings();
+        s.highest.cube_man = 1234;
+        assert_eq!(Settings::from_bytes(&s.to_bytes()), Ok(s));
+        // 旧版本只读到单字节字段
+        let payload = s.payload();
+        let old = Settings::from_bytes(&encode(&payload[..payload.len() - 4])).unwrap();
+        assert_eq!(old.highest.cube_man, 255);
+        assert_eq!(payload[1], 255);
+    }
+
+    #[test]
+    fn wide_dodge_cube_score() {
+        let mut s = settings();
+        s.highest.dodge_cube = 300;
+        assert_eq!(Settings::from_bytes(&s.to_bytes()), Ok(s));
+        // 旧版本只读到单字节字段
+        let payload = s.payload();
+        let old = Settings::from_bytes(&encode(&payload[..payload.len() - 2])).unwrap();
+        assert_eq!(old.highest.dodge_cube, 255);
+        assert_eq!(old.highest.cube_man, 9);
+        assert_eq!(payload[2], 255);
+    }
+
+    #[test]
+    fn store_round_trip() {
+        let mut store = SettingsStore::new(FakeNorFlash::new(FLASH_SIZE));
+        assert_eq!(store.load(), Settings::default());
+        store.save(&settings()).unwrap();
+        assert_eq!(store.load(), settings());
+
+        let flash = store.journal.flash().clone();
+        assert_eq!(SettingsStore::new(flash).load(), settings());
+    }
+
+    #[test]
+    fn migrate_legacy_once() {
+        let mut flash = FakeNorFlash::new(FLASH_SIZE);
+        write_legacy(&mut flash, &[5, 7, 0xff, 0xff]);
+        let mut store = SettingsStore::new(flash);
+        let s = store.load();
+        assert_eq!(s.highest.snake[0], 5);
+        assert_eq!(s.highest.cube_man, 7);
+        assert_eq!(s.sound, Settings::default().sound);
+
+        // 迁移之后旧地址不再读取
+        let mut flash = store.journal.flash().clone();
+        let sector = LEGACY_ADDR - LEGACY_ADDR % FakeNorFlash::ERASE_SIZE as u32;
+        flash
+            .erase(sector, sector + FakeNorFlash::ERASE_SIZE as u32)
+            .unwrap();
+        write_legacy(&mut flash, &[99, 99, 0xff, 0xff]);
+        assert_eq!(SettingsStore::new(flash).load(), s);
+    }
+
+    #[test]
+    fn migrate_legacy_with_header() {
+        let mut flash = FakeNorFlash::new(FLASH_SIZE);
+        write_legacy(&mut flash, &settings().to_bytes());
+        assert_eq!(SettingsStore::new(flash).load(), settings());
+    }
+
+    #[test]
+    fn corrupt_legacy_not_migrated() {
+        let mut flash = FakeNorFlash::new(FLASH_SIZE);
+        let mut bytes = settings().to_bytes();
+        bytes[HEADER_LEN] ^= 1;
+        write_legacy(&mut flash, &bytes);
+        let mut store = SettingsStore::new(flash);
+        assert_eq!(store.load(), Settings::default());
+        assert_eq!(store.journal.latest(), Ok(None));
+    }
+}
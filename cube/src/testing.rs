@@ -0,0 +1,60 @@
+//! 测试中运行 `App` 和游戏
+//!
+//! `App` 和蜂鸣器需要 `Spawner`,所以在单独的线程中启动 std 的执行器,
+//! 测试的 future 作为任务运行,结束或者 panic 之后测试线程才返回.
+//! 每个测试使用新的执行器和任务,前一个测试 panic 之后留下的任务不影响后面的测试.
+
+use crate::{buzzer::Buzzer, driver::fake::FakeTone, BUZZER};
+use core::{future::Future, pin::Pin};
+use embassy_executor::{raw::TaskStorage, Executor, Spawner};
+use std::{
+    boxed::Box,
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+/// `BUZZER` 和 `RNG` 是全局的,用到它们的测试不能同时运行
+static LOCK: Mutex<()> = Mutex::new(());
+
+type TestFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+async fn task(test: TestFuture, done: mpsc::Sender<()>) {
+    test.await;
+    done.send(()).ok();
+}
+
+/// 在执行器中运行 `test` 返回的 future,蜂鸣器静音
+pub fn run<F, Fut>(test: F)
+where
+    F: FnOnce(Spawner) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + 'static,
+{
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let (done, finished) = mpsc::channel();
+    thread::spawn(move || {
+        let executor = Box::leak(Box::new(Executor::new()));
+        executor.run(move |spawner| {
+            let mut buzzer = Buzzer::new(FakeTone::default(), spawner);
+            buzzer.open = false;
+            unsafe { BUZZER.write(buzzer) };
+            let storage = Box::leak(Box::new(TaskStorage::new()));
+            let test: TestFuture = Box::pin(test(spawner));
+            spawner.spawn(storage.spawn(|| task(test, done))).unwrap();
+        })
+    });
+    finished.recv().expect("test task panicked");
+}
+
+mod tests {
+    use super::*;
+    use std::panic;
+
+    #[test]
+    fn panic_does_not_affect_later_tests() {
+        let failed = panic::catch_unwind(|| run(|_| async { panic!("failed") }));
+        assert!(failed.is_err());
+        for _ in 0..2 {
+            run(|_| async {});
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! 倾斜输入
+//!
+//! 把加速度转换成屏幕坐标系中的倾斜,同时提供离散的方向 `Gd` 和连续的倾斜量:
+//!
+//! - 死区: 倾斜不超过 `dead_zone` 时没有方向
+//! - 迟滞: 已经按住的方向要回落到 `dead_zone - hysteresis` 以下才松开,
+//!   换到另一个轴的方向时新方向要比当前方向多倾斜 `hysteresis`,避免在阈值附近来回跳动
+//! - 自动重复: 和键盘一样,按下时产生一次事件,按住 `repeat_delay` 之后每隔 `repeat_interval` 重复一次
+//! - 力度: 超过死区之后沿当前方向的倾斜量,倾斜到 `full` 时为 1,游戏可以倾斜越大移动越快
+//!
+//! 过滤只由加速度和时间决定,`App::gravity_direction` 在每一帧调用,时间为 `App` 的逻辑时钟.
+
+use crate::{driver::Accel, Gd};
+
+/// 倾斜输入的参数,单位 g 和 ms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TiltConfig {
+    /// 死区,超过时按下
+    pub dead_zone: f32,
+    /// 迟滞,需要小于 `dead_zone`
+    pub hysteresis: f32,
+    /// 力度为 1 时的倾斜
+    pub full: f32,
+    /// 按住之后开始自动重复的时间
+    pub repeat_delay: u64,
+    /// 自动重复的间隔
+    pub repeat_interval: u64,
+}
+
+impl Default for TiltConfig {
+    fn default() -> Self {
+        Self {
+            dead_zone: 0.5,
+            hysteresis: 0.15,
+            full: 0.9,
+            repeat_delay: 500,
+            repeat_interval: 250,
+        }
+    }
+}
+
+/// 屏幕坐标系中的倾斜,x 向右,y 向下,单位 g
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Tilt {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Tilt {
+    pub fn from_accel(accel: Accel) -> Self {
+        Self {
+            x: -accel.x(),
+            y: accel.y(),
+        }
+    }
+
+    /// 沿 `gd` 方向的分量,`Gd::None` 为 0
+    pub fn along(&self, gd: Gd) -> f32 {
+        match gd {
+            Gd::None => 0.0,
+            Gd::Up => -self.y,
+            Gd::Right => self.x,
+            Gd::Down => self.y,
+            Gd::Left => -self.x,
+        }
+    }
+
+    /// 倾斜的大小,取两个轴中较大的一个
+    pub fn magnitude(&self) -> f32 {
+        self.x.max(-self.x).max(self.y).max(-self.y)
+    }
+
+    /// 倾斜较大的轴上超过 `threshold` 的方向
+    fn dominant(&self, threshold: f32) -> Gd {
+        let gd = if self.x.max(-self.x) > self.y.max(-self.y) {
+            if self.x > 0.0 {
+                Gd::Right
+            } else {
+                Gd::Left
+            }
+        } else if self.y > 0.0 {
+            Gd::Down
+        } else {
+            Gd::Up
+        };
+        if self.along(gd) > threshold {
+            gd
+        } else {
+            Gd::None
+        }
+    }
+}
+
+/// 倾斜产生的按键事件
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Press {
+    pub gd: Gd,
+    /// 按住不放时自动重复产生的
+    pub repeat: bool,
+}
+
+/// 倾斜输入的过滤
+#[derive(Debug, Default, Clone)]
+pub struct TiltInput {
+    pub config: TiltConfig,
+    /// 最后一个样本的倾斜
+    tilt: Tilt,
+    /// 按住的方向
+    gd: Gd,
+    /// 下一次自动重复的时间
+    next_repeat: u64,
+}
+
+impl TiltInput {
+    pub fn new(config: TiltConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// 输入一个样本和当前时间(ms),按下或者自动重复时返回
+    pub fn update(&mut self, accel: Accel, now: u64) -> Option<Press> {
+        self.tilt = Tilt::from_accel(accel);
+        let gd = self.filter();
+        if gd != self.gd {
+            self.gd = gd;
+            self.next_repeat = now + self.config.repeat_delay;
+            return (gd != Gd::None).then_some(Press { gd, repeat: false });
+        }
+        if gd == Gd::None || now < self.next_repeat {
+            return None;
+        }
+        self.next_repeat = now + self.config.repeat_interval;
+        Some(Press { gd, repeat: true })
+    }
+
+    /// 根据死区和迟滞得到按住的方向
+    fn filter(&self) -> Gd {
+        let c = &self.config;
+        let fresh = self.tilt.dominant(c.dead_zone);
+        let held = self.tilt.along(self.gd);
+        if self.gd == Gd::None || held < c.dead_zone - c.hysteresis {
+            return fresh;
+        }
+        // 另一个方向明显更大时才切换
+        if fresh != Gd::None && self.tilt.along(fresh) > held + c.hysteresis {
+            fresh
+        } else {
+            self.gd
+        }
+    }
+
+    /// 按住的方向
+    pub fn gd(&self) -> Gd {
+        self.gd
+    }
+
+    /// 最后一个样本的倾斜
+    pub fn tilt(&self) -> Tilt {
+        self.tilt
+    }
+
+    /// 沿按住的方向的力度,0 到 1,没有按住时为 0
+    pub fn strength(&self) -> f32 {
+        let c = &self.config;
+        if self.gd == Gd::None || c.full <= c.dead_zone {
+            return 0.0;
+        }
+        ((self.tilt.along(self.gd) - c.dead_zone) / (c.full - c.dead_zone)).clamp(0.0, 1.0)
+    }
+
+    /// 松开按住的方向,开始游戏时调用
+    pub fn reset(&mut self) {
+        self.gd = Gd::None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        driver::{fake::FakeMotion, Motion},
+        replay::{InputLog, Recorder, Replayer},
+    };
+    use alloc::vec::Vec;
+
+    /// 屏幕坐标系中的倾斜对应的加速度
+    fn tilt(x: f32, y: f32) -> Accel {
+        Accel::new(-x, y, 1.0)
+    }
+
+    fn press(gd: Gd) -> Option<Press> {
+        Some(Press { gd, repeat: false })
+    }
+
+    #[test]
+    fn dead_zone() {
+        let mut input = TiltInput::default();
+        assert_eq!(input.update(tilt(0.5, 0.0), 0), None);
+        assert_eq!(input.update(tilt(-0.3, 0.4), 10), None);
+        assert_eq!(input.gd(), Gd::None);
+        assert_eq!(input.update(tilt(0.51, 0.0), 20), press(Gd::Right));
+        // 迟滞: 回落到死区以内仍然按住,低于 `dead_zone - hysteresis` 才松开
+        assert_eq!(input.update(tilt(0.4, 0.0), 30), None);
+        assert_eq!(input.gd(), Gd::Right);
+        assert_eq!(input.update(tilt(0.34, 0.0), 40), None);
+        assert_eq!(input.gd(), Gd::None);
+        assert_eq!(input.update(tilt(0.0, -0.6), 50), press(Gd::Up));
+        assert_eq!(input.update(tilt(0.0, 0.7), 60), press(Gd::Down));
+    }
+
+    #[test]
+    fn axis_switch_hysteresis() {
+        let mut input = TiltInput::default();
+        assert_eq!(input.update(tilt(0.6, 0.0), 0), press(Gd::Right));
+        // 另一个轴更大,但是没有多出 `hysteresis`
+        assert_eq!(input.update(tilt(0.6, 0.7), 10), None);
+        assert_eq!(input.gd(), Gd::Right);
+        assert_eq!(input.update(tilt(0.6, 0.76), 20), press(Gd::Down));
+        // 切回去同样需要多出 `hysteresis`
+        assert_eq!(input.update(tilt(0.8, 0.7), 30), None);
+        assert_eq!(input.gd(), Gd::Down);
+        assert_eq!(input.update(tilt(0.86, 0.7), 40), press(Gd::Right));
+    }
+
+    #[test]
+    fn repeat_timing() {
+        let c = TiltConfig::default();
+        let mut input = TiltInput::default();
+        assert_eq!(input.update(tilt(-0.7, 0.0), 1000), press(Gd::Left));
+        let repeats = (1000..=2000)
+            .step_by(50)
+            .filter(|now| input.update(tilt(-0.7, 0.0), *now).is_some())
+            .collect::<Vec<_>>();
+        let delay = 1000 + c.repeat_delay;
+        let interval = c.repeat_interval;
+        assert_eq!(repeats, [delay, delay + interval, delay + 2 * interval]);
+
+        // 松开之后重新计时
+        assert_eq!(input.update(tilt(0.0, 0.0), 2050), None);
+        assert_eq!(input.update(tilt(-0.7, 0.0), 2100), press(Gd::Left));
+        assert_eq!(
+            input.update(tilt(-0.7, 0.0), 2100 + c.repeat_delay - 1),
+            None
+        );
+        assert_eq!(
+            input.update(tilt(-0.7, 0.0), 2100 + c.repeat_delay),
+            Some(Press {
+                gd: Gd::Left,
+                repeat: true
+            })
+        );
+    }
+
+    #[test]
+    fn strength() {
+        let mut input = TiltInput::default();
+        input.update(tilt(0.3, 0.0), 0);
+        assert_eq!(input.strength(), 0.0);
+        input.update(tilt(0.5001, 0.0), 10);
+        assert!(input.strength() < 0.01);
+        input.update(tilt(0.7, 0.1), 20);
+        assert!((input.strength() - 0.5).abs() < 1e-5);
+        input.update(tilt(1.2, 0.0), 30);
+        assert_eq!(input.strength(), 1.0);
+        // 迟滞区间内仍然按住,力度为 0
+        input.update(tilt(0.4, 0.0), 40);
+        assert_eq!(input.gd(), Gd::Right);
+        assert_eq!(input.strength(), 0.0);
+        input.reset();
+        assert_eq!(input.strength(), 0.0);
+    }
+
+    /// 按顺序输入样本,每 20ms 一个,返回按住的方向改变时的样本序号和方向
+    fn changes(samples: impl IntoIterator<Item = Accel>) -> Vec<(usize, Gd)> {
+        let mut input = TiltInput::default();
+        let mut gd = Gd::None;
+        let mut changes = Vec::new();
+        for (i, accel) in samples.into_iter().enumerate() {
+            input.update(accel, i as u64 * 20);
+            if input.gd() != gd {
+                gd = input.gd();
+                changes.push((i, gd));
+            }
+        }
+        changes
+    }
+
+    #[test]
+    fn recorded_trace() {
+        // 屏幕坐标系中的倾斜,单位 1/64 g,和日志的量化精度相同,边界上的值录制之后不变
+        let mut trace = Vec::new();
+        // 慢慢向右倾斜,等于死区时不按下
+        trace.extend((0..=33).map(|x| (x, 0)));
+        // 在死区附近抖动,然后回落到迟滞以下松开,在松开的阈值附近抖动
+        trace.extend([32, 33, 32, 31, 25, 23, 22, 23, 22, 23].map(|x| (x, 0)));
+        // 再向右倾斜,然后向下倾斜,比向右多出迟滞时切换
+        trace.extend((0..=38).map(|x| (x, 0)));
+        trace.extend((0..=48).map(|y| (38, y)));
+        let samples = trace
+            .iter()
+            .map(|&(x, y)| tilt(x as f32 / 64.0, y as f32 / 64.0))
+            .collect::<Vec<_>>();
+
+        let mut recorder = Recorder::new(FakeMotion::new(samples.clone()), 1);
+        for _ in 0..samples.len() {
+            recorder.accel();
+        }
+        let log = InputLog::from_bytes(&recorder.log().to_bytes()).unwrap();
+        let mut replayer = Replayer::new(&log);
+        let replayed = changes((0..samples.len()).map(|_| replayer.accel()));
+
+        // 回放和直接输入的结果相同
+        assert_eq!(replayed, changes(samples));
+        assert_eq!(
+            replayed
+                .iter()
+                .map(|&(i, gd)| (trace[i], gd))
+                .collect::<Vec<_>>(),
+            [
+                ((33, 0), Gd::Right),
+                ((22, 0), Gd::None),
+                ((33, 0), Gd::Right),
+                ((38, 48), Gd::Down),
+            ]
+        );
+    }
+}
@@ -1,6 +1,9 @@
 #![doc = include_str!("../../rfcs/004_timer.md")]
 
-use crate::{App, CubeRng, BUZZER, RNG};
+use crate::{
+    driver::{Matrix, Motion},
+    App, CubeRng, BUZZER, RNG,
+};
 use alloc::vec::Vec;
 use embassy_time::Timer;
 use embedded_graphics::geometry::Point;
@@ -9,26 +12,104 @@ use embedded_graphics_core::{
     Pixel,
 };
 
+/// 可选的时长,单位分钟
+pub const DURATIONS: [u8; 5] = [1, 3, 5, 10, 25];
+/// 沙子的数量
+const GRAINS: u64 = 32;
+/// 像素闪烁和下落动画的时长,ms
+const ANIMATION: u64 = 800;
+/// 沙子模式每一帧的间隔,ms
+const SAND_TICK: u64 = 100;
+
+/// 沙漏模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    /// 每次随机掉下一个像素
+    Drop,
+    /// 沙子跟随重力流动
+    Sand,
+}
+
+impl TimerMode {
+    #[rustfmt::skip]
+    fn ui(&self) -> [u8; 8] {
+        match self {
+            TimerMode::Drop => [
+                0b11111111,
+                0b11111111,
+                0b11111111,
+                0b11111111,
+                0b00000000,
+                0b00000000,
+                0b00000000,
+                0b00000000,
+            ],
+            TimerMode::Sand => [
+                0b00000000,
+                0b01111110,
+                0b00111100,
+                0b00011000,
+                0b00011000,
+                0b00111100,
+                0b01111110,
+                0b00000000,
+            ],
+        }
+    }
+}
+
 /// 沙漏
 #[derive(Debug, Clone)]
 pub struct Timers {
     pixels: Vec<TimerPixel>,
+    /// 时长,单位分钟
+    minutes: u8,
 }
 
 impl core::default::Default for Timers {
     fn default() -> Self {
-        let mut pixels = Vec::<TimerPixel>::with_capacity(32);
+        Self::new(DURATIONS[0])
+    }
+}
+
+impl Timers {
+    pub fn new(minutes: u8) -> Self {
+        let mut pixels = Vec::<TimerPixel>::with_capacity(GRAINS as usize);
         for y in 0..4 {
             for x in 0..8 {
                 pixels.push(TimerPixel::new(Point::new(x, y), 0.3));
             }
         }
-        Self { pixels }
+        Self { pixels, minutes }
     }
-}
 
-impl Timers {
-    fn init<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<T>) {
+    /// 每个像素(沙子)落下的间隔,ms
+    fn interval(&self) -> u64 {
+        self.minutes as u64 * 60_000 / GRAINS
+    }
+
+    /// 子菜单: 先选择模式,再选择时长,向下倾斜返回上一级
+    pub async fn menu<M: Motion, L: Matrix>(app: &mut App<M, L>) -> Option<(TimerMode, u8)> {
+        let modes = [TimerMode::Drop, TimerMode::Sand];
+        loop {
+            let mode = app
+                .choose(modes.len(), 0, |app, i| {
+                    app.ledc.write_bytes(modes[i].ui());
+                })
+                .await?;
+            let Some(minutes) = app
+                .choose(DURATIONS.len(), 0, |app, i| {
+                    app.ledc.draw_score(DURATIONS[i]);
+                })
+                .await
+            else {
+                continue;
+            };
+            return Some((modes[mode], DURATIONS[minutes]));
+        }
+    }
+
+    fn init<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
         app.ledc.clear();
         app.gravity_direction();
         app.ledc.write_pixels(self.pixels());
@@ -60,10 +141,12 @@ impl Timers {
         self.pixels.iter().position(|p| p == last)
     }
 
-    pub async fn run<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<'_, T>) {
+    pub async fn run<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
         self.init(app);
 
         let mut rxs = vec![0, 1, 2, 3, 4, 5, 6, 7];
+        // 扣除动画的时间,总时长与选择的时长一致
+        let waiting_time = self.interval().saturating_sub(ANIMATION);
 
         loop {
             if self.pixels.is_empty() {
@@ -79,11 +162,199 @@ impl Timers {
                 continue;
             };
 
-            Timer::after_millis(1000).await;
+            if app.wait(waiting_time).await {
+                return;
+            }
             let mut pixel = self.pixels.remove(index);
             pixel.blink(app).await;
             pixel.r#move(app).await;
         }
+        unsafe { BUZZER.assume_init_mut().timers_over().await };
+        Timer::after_millis(1000).await;
+    }
+
+    /// 沙子模式,沙子跟随加速度流动,翻转小方后沙子流向另一边
+    pub async fn run_sand<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
+        let seed = unsafe { RNG.assume_init_mut().random() as u64 };
+        let mut sand = Sand::new(seed);
+        let interval = self.interval();
+        // 按逻辑时钟计时,暂停的时间不计入,回放时沙子落下的时间相同
+        let mut last_pass = app.play_millis();
+        let mut passed = false;
+        app.ledc.write_bytes(sand.rows);
+
+        loop {
+            if app.wait(SAND_TICK).await {
+                return;
+            }
+
+            let accel = app.accel();
+            // 屏幕坐标系中的重力方向,与 `gravity_direction` 一致
+            let gravity = (-accel.x(), accel.y());
+            let can_pass = app.play_millis() - last_pass >= interval;
+            if sand.step(gravity, can_pass) {
+                last_pass = app.play_millis();
+                passed = true;
+                unsafe { BUZZER.assume_init_mut().timer_pixel_blinky().await };
+            }
+            app.ledc.write_bytes(sand.rows);
+
+            if passed && sand.upper(gravity.1) == Some(0) {
+                break;
+            }
+        }
+        unsafe { BUZZER.assume_init_mut().timers_over().await };
+        Timer::after_millis(1000).await;
+    }
+}
+
+/// 沙子模拟,上下两半各 4 行,中间只有第 3,4 列相通
+///
+/// 每一帧每粒沙子最多移动一格,经过中间的通道每次只能通过一粒.
+struct Sand {
+    /// 按行存储,与 `write_bytes` 相同
+    rows: [u8; 8],
+    rng: CubeRng,
+}
+
+impl Sand {
+    /// 重力分量小于这个值时视为没有重力
+    const THRESHOLD: f32 = 0.35;
+
+    fn new(seed: u64) -> Self {
+        Self {
+            rows: [0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0],
+            rng: CubeRng(seed),
+        }
+    }
+
+    fn get(&self, x: i32, y: i32) -> bool {
+        self.rows[y as usize] & (1 << (7 - x)) != 0
+    }
+
+    fn set(&mut self, x: i32, y: i32, on: bool) {
+        if on {
+            self.rows[y as usize] |= 1 << (7 - x);
+        } else {
+            self.rows[y as usize] &= !(1 << (7 - x));
+        }
+    }
+
+    /// 两个位置之间是否是通道
+    fn neck(from: Point, to: Point) -> bool {
+        from.x == to.x && (from.x == 3 || from.x == 4)
+    }
+
+    /// 能否从 `from` 移动到 `to`,`can_pass` 为是否允许通过通道
+    fn movable(&self, from: Point, to: Point, can_pass: bool) -> bool {
+        if !(0..8).contains(&to.x) || !(0..8).contains(&to.y) || self.get(to.x, to.y) {
+            return false;
+        }
+        let crossing = (from.y < 4) != (to.y < 4);
+        !crossing || (can_pass && Self::neck(from, to))
+    }
+
+    /// 沙子在重力方向上的分量
+    fn sign(v: f32) -> i32 {
+        if v > Self::THRESHOLD {
+            1
+        } else if v < -Self::THRESHOLD {
+            -1
+        } else {
+            0
+        }
+    }
+
+    /// 模拟一帧,返回是否有沙子通过了通道
+    fn step(&mut self, gravity: (f32, f32), mut can_pass: bool) -> bool {
+        let (sx, sy) = (Self::sign(gravity.0), Self::sign(gravity.1));
+        if sx == 0 && sy == 0 {
+            return false;
+        }
+
+        // 先移动重力方向上靠前的沙子,避免挡住后面的沙子
+        let mut grains = Vec::with_capacity(GRAINS as usize);
+        for y in 0..8 {
+            for x in 0..8 {
+                if self.get(x, y) {
+                    grains.push(Point::new(x, y));
+                }
+            }
+        }
+        grains.sort_by_key(|p| -(p.x * sx + p.y * sy));
+
+        let mut passed = false;
+        for p in grains {
+            let mut candidates = Vec::with_capacity(4);
+            candidates.push(p + Point::new(sx, sy));
+            let (mut a, mut b) = match (sx, sy) {
+                (0, _) => (p + Point::new(-1, sy), p + Point::new(1, sy)),
+                (_, 0) => (p + Point::new(sx, -1), p + Point::new(sx, 1)),
+                _ => (p + Point::new(sx, 0), p + Point::new(0, sy)),
+            };
+            if self.rng.random(0, 2) == 0 {
+                core::mem::swap(&mut a, &mut b);
+            }
+            candidates.push(a);
+            candidates.push(b);
+            if let Some(to) = self.slide(p, sy) {
+                candidates.push(to);
+            }
+
+            let Some(to) = candidates
+                .into_iter()
+                .find(|to| self.movable(p, *to, can_pass))
+            else {
+                continue;
+            };
+            if (p.y < 4) != (to.y < 4) {
+                can_pass = false;
+                passed = true;
+            }
+            self.set(p.x, p.y, false);
+            self.set(to.x, to.y, true);
+        }
+        passed
+    }
+
+    /// 下方被挡住时水平滑动的目标,形成沙堆的斜坡
+    ///
+    /// 落在中间的隔板上时向通道滑动,否则向下一行最近的空位滑动.
+    /// 刚通过通道的沙子下方已经堆满时挤到这一行最近的空位,避免堵住通道.
+    fn slide(&self, p: Point, sy: i32) -> Option<Point> {
+        let below = p.y + sy;
+        if sy == 0 || !(0..8).contains(&below) {
+            return None;
+        }
+        if (p.y < 4) != (below < 4) {
+            if Self::neck(p, Point::new(p.x, below)) {
+                return None;
+            }
+            return Some(p + Point::new(if p.x < 4 { 1 } else { -1 }, 0));
+        }
+        let nearest = |y: i32| {
+            (0..8)
+                .filter(|x| !self.get(*x, y))
+                .min_by_key(|x| (x - p.x).abs())
+        };
+        if let Some(x) = nearest(below) {
+            return Some(p + Point::new((x - p.x).signum(), 0));
+        }
+        let entrance = (p.y < 4) != (p.y - sy < 4) && (p.x == 3 || p.x == 4);
+        if entrance {
+            return nearest(p.y).map(|x| Point::new(x, p.y));
+        }
+        None
+    }
+
+    /// 重力方向上方那一半的沙子数量,没有上下之分时返回 `None`
+    fn upper(&self, gy: f32) -> Option<u32> {
+        let half = match Self::sign(gy) {
+            1 => &self.rows[..4],
+            -1 => &self.rows[4..],
+            _ => return None,
+        };
+        Some(half.iter().map(|r| r.count_ones()).sum())
     }
 }
 
@@ -102,7 +373,7 @@ impl TimerPixel {
     }
 
     /// 闪烁一下选中的像素,
-    async fn blink<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<'_, T>) {
+    async fn blink<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
         for _ in 0..3 {
             self.pixel.1 = BinaryColor::from(self.pixel.1).invert().into();
             app.ledc.write_pixel(self.pixel);
@@ -112,7 +383,7 @@ impl TimerPixel {
     }
 
     /// 执行像素的下落过程
-    async fn r#move<T: esp_hal::i2c::Instance>(&mut self, app: &mut App<'_, T>) {
+    async fn r#move<M: Motion, L: Matrix>(&mut self, app: &mut App<M, L>) {
         self.pixel.1 = BinaryColor::On.into();
         self.pixel.0.y += 4;
         Timer::after_millis(500).await;
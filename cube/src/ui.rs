@@ -22,10 +22,12 @@ pub enum Ui {
     DodgeCube,
     /// 声音
     Sound,
+    /// 校准
+    Calibration,
 }
 
 impl Ui {
-    pub fn uis() -> [Ui; 10] {
+    pub fn uis() -> [Ui; 11] {
         [
             Ui::Timer,
             Ui::MusicSpectrum,
@@ -37,6 +39,7 @@ impl Ui {
             Ui::Sokoban,
             Ui::DodgeCube,
             Ui::Sound,
+            Ui::Calibration,
         ]
     }
 
@@ -143,6 +146,16 @@ impl Ui {
                 0b00110000,
                 0b00000000,
             ],
+            Ui::Calibration => [
+                0b00000000,
+                0b00000000,
+                0b01111110,
+                0b10011001,
+                0b10011001,
+                0b01111110,
+                0b00000000,
+                0b00000000,
+            ],
         }
     }
 }
@@ -6,6 +6,7 @@ use core::ops::{Bound, RangeBounds};
 
 use rand_core::{Error, RngCore};
 
+#[derive(Debug, Clone)]
 pub struct CubeRng(pub u64);
 
 impl CubeRng {
@@ -54,14 +55,16 @@ impl CubeRng {
             panic_empty_range();
         }
 
-        let range_size = high - low;
-        (low + (self.next_u32() % range_size)) as usize
+        // 包含 `high`,用 u64 计算避免整个 u32 范围时溢出
+        let range_size = (high - low) as u64 + 1;
+        (low as u64 + self.next_u32() as u64 % range_size) as usize
     }
 }
 
 impl RngCore for CubeRng {
+    /// 线性同余的低位周期很短,只用高 32 位
     fn next_u32(&mut self) -> u32 {
-        self.next_u64() as u32
+        (self.next_u64() >> 32) as u32
     }
 
     fn next_u64(&mut self) -> u64 {
@@ -78,3 +81,50 @@ impl RngCore for CubeRng {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn low_bits_not_periodic() {
+        // 低位直接取模时 `random(0, 2)` 交替出现 0 和 1
+        let mut rng = CubeRng(42);
+        let mut bits = [0; 64];
+        bits.iter_mut().for_each(|b| *b = rng.random(0, 2));
+        assert!(bits.windows(2).any(|w| w[0] == w[1]));
+        assert!(bits.contains(&0) && bits.contains(&1));
+    }
+
+    #[test]
+    fn random_in_range() {
+        let mut rng = CubeRng(7);
+        let mut seen = [false; 6];
+        for _ in 0..200 {
+            let n = rng.random(1, 7);
+            assert!((1..7).contains(&n));
+            seen[n as usize - 1] = true;
+        }
+        assert!(seen.iter().all(|s| *s));
+        assert_eq!(rng.random(3, 3), 3);
+    }
+
+    #[test]
+    fn random_range_bounds() {
+        let mut rng = CubeRng(3);
+        let mut seen = [false; 8];
+        for _ in 0..400 {
+            seen[rng.random_range(0..8)] = true;
+        }
+        assert!(seen.iter().all(|s| *s));
+        let mut seen = [false; 3];
+        for _ in 0..100 {
+            let n = rng.random_range(5..=7);
+            assert!((5..=7).contains(&n));
+            seen[n - 5] = true;
+        }
+        assert!(seen.iter().all(|s| *s));
+        assert_eq!(rng.random_range(4..5), 4);
+        assert_eq!(rng.random_range(9..=9), 9);
+    }
+}
@@ -0,0 +1,231 @@
+//! 将录制的画面导出为 GIF 动画或 PNG 序列帧
+//!
+//! 连续重复的画面只保留第一帧,GIF 中的停留时间会相应变长.
+
+use cube::ledc::Frame;
+use embedded_graphics::{pixelcolor::Rgb888, prelude::RgbColor};
+use std::{fs::File, io::BufWriter, path::Path};
+
+/// 每个 led 占的像素
+const CELL: usize = 12;
+/// led 之间的间隔
+const GAP: usize = 2;
+/// 一帧画面的边长
+const SIZE: usize = CELL * 8;
+/// 最后一帧停留的时间,单位毫秒
+const LAST_DELAY: u64 = 1000;
+/// PNG 每行的帧数
+const COLUMNS: usize = 16;
+/// PNG 最多导出的帧数,超过时只保留前面的
+const MAX_FRAMES: usize = COLUMNS * 16;
+
+/// 熄灭的 led 和间隔的颜色
+const OFF: [u8; 3] = [40, 40, 40];
+const BACKGROUND: [u8; 3] = [0, 0, 0];
+
+/// 根据后缀名导出,`.png` 为每行 `COLUMNS` 帧的序列帧,其它为 GIF 动画
+pub fn export(path: &Path, frames: &[Frame]) -> std::io::Result<()> {
+    if frames.is_empty() {
+        return Ok(());
+    }
+    let frames = distinct(frames);
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("png") => write_png(path, &frames),
+        _ => write_gif(path, &frames),
+    }
+}
+
+/// 合并连续重复的画面
+fn distinct(frames: &[Frame]) -> Vec<Frame> {
+    let mut distinct: Vec<Frame> = Vec::with_capacity(frames.len());
+    for frame in frames {
+        if distinct.last().map(|last| last.pixels) != Some(frame.pixels) {
+            distinct.push(*frame);
+        }
+    }
+    distinct
+}
+
+/// 渲染一帧画面为 RGB 数据,宽 `stride` 个像素,左上角在 (`left`, `top`)
+fn render(frame: &Frame, buf: &mut [u8], stride: usize, left: usize, top: usize) {
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let (lx, ly) = (x / CELL, y / CELL);
+            let gap = x % CELL >= CELL - GAP || y % CELL >= CELL - GAP;
+            let c = frame.pixels[ly * 8 + lx];
+            let rgb = if gap {
+                BACKGROUND
+            } else if c == Rgb888::BLACK {
+                OFF
+            } else {
+                [c.r(), c.g(), c.b()]
+            };
+            let i = ((top + y) * stride + left + x) * 3;
+            buf[i..i + 3].copy_from_slice(&rgb);
+        }
+    }
+}
+
+fn write_gif(path: &Path, frames: &[Frame]) -> std::io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder =
+        gif::Encoder::new(file, SIZE as u16, SIZE as u16, &[]).map_err(std::io::Error::other)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(std::io::Error::other)?;
+
+    let mut buf = vec![0; SIZE * SIZE * 3];
+    for (i, frame) in frames.iter().enumerate() {
+        let delay = frames.get(i + 1).map_or(LAST_DELAY, |n| n.at - frame.at);
+        render(frame, &mut buf, SIZE, 0, 0);
+        let mut f = gif::Frame::from_rgb(SIZE as u16, SIZE as u16, &buf);
+        // GIF 的延时单位为 10 毫秒
+        f.delay = (delay / 10).min(u16::MAX as u64) as u16;
+        encoder.write_frame(&f).map_err(std::io::Error::other)?;
+    }
+    Ok(())
+}
+
+fn write_png(path: &Path, frames: &[Frame]) -> std::io::Result<()> {
+    let frames = &frames[..frames.len().min(MAX_FRAMES)];
+    let width = SIZE * frames.len().min(COLUMNS);
+    let height = SIZE * frames.len().div_ceil(COLUMNS);
+    let mut buf = vec![0; width * height * 3];
+    for (i, frame) in frames.iter().enumerate() {
+        render(
+            frame,
+            &mut buf,
+            width,
+            i % COLUMNS * SIZE,
+            i / COLUMNS * SIZE,
+        );
+    }
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(std::io::Error::other)?;
+    writer
+        .write_image_data(&buf)
+        .map_err(std::io::Error::other)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// 只点亮第 `led` 个 led 的画面
+    fn frame(at: u64, led: usize, c: Rgb888) -> Frame {
+        let mut pixels = [Rgb888::BLACK; 64];
+        pixels[led] = c;
+        Frame { at, pixels }
+    }
+
+    fn temp(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("cube_sim_{}_{name}", std::process::id()))
+    }
+
+    /// 解码 PNG,返回宽,高和像素
+    fn decode_png(path: &Path) -> (u32, u32, Vec<u8>) {
+        let decoder = png::Decoder::new(File::open(path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        buf.truncate(info.buffer_size());
+        (info.width, info.height, buf)
+    }
+
+    /// 和 `tests/golden` 中的图片比较,设置 `UPDATE_GOLDEN` 时更新图片
+    fn assert_golden(path: &Path, name: &str) {
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::copy(path, &golden).unwrap();
+        }
+        assert!(
+            decode_png(path) == decode_png(&golden),
+            "{name} differs from golden image"
+        );
+    }
+
+    #[test]
+    fn distinct_frames() {
+        let frames = [
+            frame(0, 0, Rgb888::RED),
+            frame(100, 0, Rgb888::RED),
+            frame(200, 1, Rgb888::RED),
+            frame(300, 0, Rgb888::RED),
+        ];
+        let at: Vec<u64> = distinct(&frames).iter().map(|f| f.at).collect();
+        assert_eq!(at, [0, 200, 300]);
+    }
+
+    #[test]
+    fn png_grid() {
+        let mut frames = Vec::new();
+        for i in 0..20 {
+            let f = frame(i * 100, i as usize, Rgb888::new(255, i as u8 * 10, 0));
+            frames.push(f);
+            if i % 4 == 0 {
+                frames.push(Frame { at: f.at + 50, ..f });
+            }
+        }
+        let path = temp("grid.png");
+        export(&path, &frames).unwrap();
+        let (width, height, _) = decode_png(&path);
+        assert_eq!(
+            (width, height),
+            ((SIZE * COLUMNS) as u32, (SIZE * 2) as u32)
+        );
+        assert_golden(&path, "grid.png");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn png_single_frame() {
+        let path = temp("single.png");
+        export(&path, &[frame(0, 9, Rgb888::GREEN)]).unwrap();
+        assert_golden(&path, "single.png");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn png_frames_capped() {
+        let frames: Vec<Frame> = (0..MAX_FRAMES + 40)
+            .map(|i| frame(i as u64, i % 64, Rgb888::new(50 * (i / 64 + 1) as u8, 0, 0)))
+            .collect();
+        let path = temp("capped.png");
+        export(&path, &frames).unwrap();
+        let (width, height, _) = decode_png(&path);
+        let rows = MAX_FRAMES / COLUMNS;
+        assert_eq!(
+            (width, height),
+            ((SIZE * COLUMNS) as u32, (SIZE * rows) as u32)
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn gif_repeats_extend_delay() {
+        let frames = [
+            frame(0, 0, Rgb888::RED),
+            frame(300, 0, Rgb888::RED),
+            frame(500, 1, Rgb888::BLUE),
+        ];
+        let path = temp("delay.gif");
+        export(&path, &frames).unwrap();
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+        let mut delays = Vec::new();
+        while let Some(f) = decoder.read_next_frame().unwrap() {
+            delays.push(f.delay);
+        }
+        assert_eq!(delays, [50, (LAST_DELAY / 10) as u16]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,153 @@
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use cube::{
+    calibration::Calibration,
+    driver::{Accel, Motion},
+    replay::InputLog,
+};
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// 按键之后保持倾斜的时间,终端收不到按键松开的事件
+const HOLD: Duration = Duration::from_millis(300);
+
+/// 屏幕朝下保持的时间,超过退出手势需要的 2 秒
+const FACE_DOWN_HOLD: Duration = Duration::from_millis(2500);
+/// 翻转时屏幕朝下的时间,不到退出手势需要的 2 秒
+const FLIP_HOLD: Duration = Duration::from_millis(800);
+
+/// 平放时的加速度
+const FLAT: Accel = Accel::new(0.0, 0.0, 1.0);
+/// 敲击时的冲击
+const TAP: Accel = Accel::new(0.0, 0.0, 2.0);
+/// 上下摇晃时交替出现的加速度,只有前一个超过摇晃的阈值
+const SHAKE: [Accel; 2] = [Accel::new(0.0, 0.0, 2.5), Accel::new(0.0, 0.0, -0.5)];
+/// 摇晃一下的样本数量
+const SHAKE_SAMPLES: usize = 16;
+/// 持续摇晃的样本数量,按每 20ms 读取一次超过 2 秒
+const LONG_SHAKE_SAMPLES: usize = 150;
+
+#[derive(Debug)]
+struct Tilt {
+    accel: Accel,
+    until: Instant,
+    /// 倾斜是否已经被读取过,保证每次按键至少被读取一次
+    read: bool,
+    /// 按顺序读取的样本,读完之后才读取倾斜
+    samples: VecDeque<Accel>,
+}
+
+/// 录制输入,退出时写入文件
+pub struct Recording {
+    pub path: PathBuf,
+    pub log: InputLog,
+}
+
+/// 用键盘模拟倾斜,方向和 `App::gravity_direction` 的判断一致
+pub struct KeyboardMotion {
+    tilt: Arc<Mutex<Tilt>>,
+    recording: Option<Arc<Mutex<Recording>>>,
+}
+
+impl KeyboardMotion {
+    /// 启动读取键盘的线程,按下退出键时调用 `quit`
+    pub fn spawn(
+        recording: Option<Arc<Mutex<Recording>>>,
+        quit: impl Fn() + Send + 'static,
+    ) -> Self {
+        let tilt = Arc::new(Mutex::new(Tilt {
+            accel: FLAT,
+            until: Instant::now(),
+            read: true,
+            samples: VecDeque::new(),
+        }));
+        let shared = tilt.clone();
+        thread::spawn(move || loop {
+            let Ok(Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press | KeyEventKind::Repeat,
+                ..
+            })) = event::read()
+            else {
+                continue;
+            };
+            let mut hold = HOLD;
+            let accel = match code {
+                KeyCode::Up => Accel::new(0.0, -1.0, 0.0),
+                KeyCode::Down => Accel::new(0.0, 1.0, 0.0),
+                KeyCode::Left => Accel::new(1.0, 0.0, 0.0),
+                KeyCode::Right => Accel::new(-1.0, 0.0, 0.0),
+                KeyCode::Char('s') => Accel::new(0.7, 0.7, 0.0),
+                KeyCode::Char('f') => {
+                    hold = FACE_DOWN_HOLD;
+                    Accel::new(0.0, 0.0, -1.0)
+                }
+                KeyCode::Char('h') => {
+                    hold = FLIP_HOLD;
+                    Accel::new(0.0, 0.0, -1.0)
+                }
+                KeyCode::Char('p') => {
+                    // 敲击两下,两次冲击之间隔 8 个样本,与手势检测的间隔相符
+                    let mut tilt = shared.lock().unwrap();
+                    tilt.samples.push_back(TAP);
+                    tilt.samples.extend([FLAT; 8]);
+                    tilt.samples.push_back(TAP);
+                    tilt.samples.push_back(FLAT);
+                    continue;
+                }
+                KeyCode::Char(c @ ('x' | 'r')) => {
+                    let n = if c == 'x' {
+                        SHAKE_SAMPLES
+                    } else {
+                        LONG_SHAKE_SAMPLES
+                    };
+                    let mut tilt = shared.lock().unwrap();
+                    tilt.samples.extend(SHAKE.iter().cycle().take(n));
+                    tilt.samples.push_back(FLAT);
+                    continue;
+                }
+                KeyCode::Char('q') | KeyCode::Esc => return quit(),
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return quit(),
+                _ => continue,
+            };
+            let mut tilt = shared.lock().unwrap();
+            tilt.accel = accel;
+            tilt.until = Instant::now() + hold;
+            tilt.read = false;
+        });
+        Self { tilt, recording }
+    }
+}
+
+impl Motion for KeyboardMotion {
+    fn accel(&mut self) -> Accel {
+        let mut tilt = self.tilt.lock().unwrap();
+        if let Some(accel) = tilt.samples.pop_front() {
+            return match &self.recording {
+                Some(r) => r.lock().unwrap().log.record(accel),
+                None => accel,
+            };
+        }
+        if tilt.read && Instant::now() >= tilt.until {
+            tilt.accel = FLAT;
+        }
+        tilt.read = true;
+        match &self.recording {
+            Some(r) => r.lock().unwrap().log.record(tilt.accel),
+            None => tilt.accel,
+        }
+    }
+
+    /// 录制时记下开机时的加速度偏移
+    fn calibration(&mut self, stored: Calibration) -> Calibration {
+        if let Some(r) = &self.recording {
+            r.lock().unwrap().log.offset = stored.accel;
+        }
+        stored
+    }
+}
@@ -0,0 +1,236 @@
+//! 小方模拟器
+//!
+//! 在终端中运行真实的 `App` 菜单和游戏,点阵用 ANSI 真彩色方块显示,
+//! 方向键代替倾斜方向,`s` 模拟摇晃,`q`/`Esc` 退出.
+//! `p` 敲击两下暂停,`f` 屏幕朝下退出游戏,`h` 翻转一下,`x` 摇晃一下,`r` 持续摇晃.
+//!
+//! ```sh
+//! cargo run -p cube_sim -- --seed 42
+//! # 录制输入,退出时写入文件
+//! cargo run -p cube_sim -- --record bug.cube
+//! # 回放录制的输入
+//! cargo run -p cube_sim -- --replay bug.cube
+//! # 录制画面,退出时导出为 GIF 动画,后缀为 .png 时导出为序列帧
+//! cargo run -p cube_sim -- --capture face.gif
+//! # 求解所有的推箱子关卡,包括生成的关卡,有无解的关卡时返回错误
+//! cargo run -p cube_sim -- --solve
+//! # 解析并求解 XSB 文件中的所有关卡
+//! cargo run -p cube_sim -- --xsb levels.xsb
+//! ```
+
+mod capture;
+mod keyboard;
+mod terminal;
+
+use cube::{
+    buzzer::Buzzer,
+    calibration::Calibration,
+    driver::{
+        fake::{FakeMicrophone, FakeNorFlash},
+        Accel, Motion, ToneOutput,
+    },
+    ledc::{Frame, LedControl},
+    music_spectrum::SAMPLE_RATE,
+    replay::{self, InputLog, Replayer},
+    sokoban::{xsb, Sokoban, LEVEL_COUNT},
+    App,
+};
+use embassy_executor::Spawner;
+use keyboard::{KeyboardMotion, Recording};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use terminal::TerminalMatrix;
+
+/// 模拟的 flash 大小,需要覆盖 `App` 使用的地址
+const FLASH_SIZE: usize = 0x10000;
+
+/// 模拟器中不发声
+struct Silent;
+
+impl ToneOutput for Silent {
+    fn drive(&mut self, _frequency: u32, _duty_pct: u8) {}
+}
+
+/// 模拟麦克风的旋律,每个音符为 (频率 Hz, 时长 ms)
+const MELODY: [(f32, u32); 8] = [
+    (131.0, 300),
+    (196.0, 300),
+    (262.0, 300),
+    (392.0, 300),
+    (523.0, 300),
+    (784.0, 300),
+    (1047.0, 300),
+    (1568.0, 300),
+];
+
+/// 按照 ADC 的读数生成旋律的采样,每个音符带一个高八度的泛音
+fn melody() -> FakeMicrophone {
+    let mut samples = Vec::new();
+    for (freq, ms) in MELODY {
+        let n = SAMPLE_RATE * ms / 1000;
+        samples.extend((0..n).map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let phase = 2.0 * std::f32::consts::PI * freq * t;
+            (2048.0 + 600.0 * phase.sin() + 200.0 * (2.0 * phase).sin()) as u16
+        }));
+    }
+    FakeMicrophone::new(samples)
+}
+
+/// 输入来源
+enum Input {
+    Keyboard(KeyboardMotion),
+    Replay(Replayer),
+}
+
+impl Motion for Input {
+    fn accel(&mut self) -> Accel {
+        match self {
+            Input::Keyboard(k) => k.accel(),
+            Input::Replay(r) => r.accel(),
+        }
+    }
+
+    fn calibration(&mut self, stored: Calibration) -> Calibration {
+        match self {
+            Input::Keyboard(k) => k.calibration(stored),
+            Input::Replay(r) => r.calibration(stored),
+        }
+    }
+}
+
+/// 求解所有的推箱子关卡,包括生成的关卡,输出最少的推动次数和完整的步骤,返回无解的关卡数量
+fn solve_levels() -> usize {
+    let mut failed = 0;
+    for level in 0..LEVEL_COUNT {
+        match Sokoban::with_level(level).solve(usize::MAX) {
+            Ok(s) if !replays(Sokoban::with_level(level), &s.lurd) => {
+                println!("level {:2}: invalid solution {}", level + 1, s.lurd);
+                failed += 1;
+            }
+            Ok(s) => println!(
+                "level {:2}: {:3} pushes  {}",
+                level + 1,
+                s.pushes.len(),
+                s.lurd
+            ),
+            Err(e) => {
+                println!("level {:2}: {e:?}", level + 1);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+/// 在关卡上重放解,检查最后所有箱子都在目标点上
+fn replays(mut sokoban: Sokoban, lurd: &str) -> bool {
+    sokoban.replay(lurd).is_ok() && sokoban.solved()
+}
+
+/// 求解 XSB 文件中的所有关卡,解析失败时输出错误的位置,返回无解的关卡数量
+fn solve_file(path: &str) -> usize {
+    let text = std::fs::read_to_string(path).expect("read xsb file");
+    let levels = match xsb::parse(&text) {
+        Ok(levels) => levels,
+        Err(e) => {
+            println!("{path}: {e:?}");
+            return 1;
+        }
+    };
+    let mut failed = 0;
+    for (i, level) in levels.iter().enumerate() {
+        let title = level.title.as_deref().unwrap_or("");
+        match Sokoban::with_xsb(level).solve(usize::MAX) {
+            Ok(s) if !replays(Sokoban::with_xsb(level), &s.lurd) => {
+                println!("{:3} {title}: invalid solution {}", i + 1, s.lurd);
+                failed += 1;
+            }
+            Ok(s) => println!("{:3} {title}: {} pushes  {}", i + 1, s.pushes.len(), s.lurd),
+            Err(e) => {
+                println!("{:3} {title}: {e:?}\n{level}", i + 1);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+/// 获取参数 `name` 的值
+fn arg(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    args.find(|a| a == name)?;
+    args.next()
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    if std::env::args().any(|a| a == "--solve") {
+        std::process::exit(solve_levels().min(1) as i32);
+    }
+    if let Some(path) = arg("--xsb") {
+        std::process::exit(solve_file(&path).min(1) as i32);
+    }
+
+    // 随机数种子,`--seed <N>` 指定,否则使用当前时间
+    let mut seed = arg("--seed")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        });
+    let replay = arg("--replay").map(|path| {
+        let bytes = std::fs::read(path).expect("read replay file");
+        InputLog::from_bytes(&bytes).expect("parse replay file")
+    });
+    if let Some(log) = &replay {
+        seed = log.seed;
+    }
+
+    let recording = arg("--record").filter(|_| replay.is_none()).map(|path| {
+        Arc::new(Mutex::new(Recording {
+            path: path.into(),
+            log: InputLog::new(seed),
+        }))
+    });
+    let capture = arg("--capture").map(|path| (PathBuf::from(path), Arc::default()));
+
+    let quit = {
+        let recording = recording.clone();
+        let capture: Option<(PathBuf, Arc<Mutex<Vec<Frame>>>)> = capture.clone();
+        move || {
+            terminal::restore();
+            if let Some(r) = &recording {
+                let r = r.lock().unwrap();
+                std::fs::write(&r.path, r.log.to_bytes()).expect("write record file");
+            }
+            if let Some((path, frames)) = &capture {
+                capture::export(path, &frames.lock().unwrap()).expect("write capture file");
+            }
+            std::process::exit(0)
+        }
+    };
+    let keyboard = KeyboardMotion::spawn(recording, quit);
+    // 回放时键盘只用来退出
+    let input = match &replay {
+        Some(log) => Input::Replay(Replayer::new(log)),
+        None => Input::Keyboard(keyboard),
+    };
+
+    replay::seed_rng(seed);
+    unsafe { cube::BUZZER.write(Buzzer::new(Silent, spawner)) };
+
+    let mut ledc = LedControl::with_matrix(TerminalMatrix::new(seed));
+    if let Some((_, frames)) = capture {
+        ledc.capture(move |frame| frames.lock().unwrap().push(frame));
+    }
+
+    App::new(input, ledc, spawner)
+        .with_microphone(melody())
+        .run(FakeNorFlash::new(FLASH_SIZE))
+        .await
+}
@@ -0,0 +1,89 @@
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    execute, queue,
+    style::{Color, Print, ResetColor, SetForegroundColor},
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use cube::driver::Matrix;
+use embedded_graphics::{pixelcolor::Rgb888, prelude::RgbColor, Pixel};
+use std::io::{stdout, Write};
+
+/// 熄灭的 led 显示的颜色,便于看清点阵的边界
+const OFF: Color = Color::Rgb {
+    r: 40,
+    g: 40,
+    b: 40,
+};
+
+/// 在终端中显示的点阵,每个 led 占两个字符宽
+pub struct TerminalMatrix {
+    buffer: [Rgb888; 64],
+    seed: u64,
+}
+
+impl TerminalMatrix {
+    pub fn new(seed: u64) -> Self {
+        terminal::enable_raw_mode().unwrap();
+        execute!(stdout(), EnterAlternateScreen, Hide).unwrap();
+        Self {
+            buffer: [Rgb888::BLACK; 64],
+            seed,
+        }
+    }
+}
+
+/// 恢复终端
+pub fn restore() {
+    execute!(stdout(), ResetColor, Show, LeaveAlternateScreen).ok();
+    terminal::disable_raw_mode().ok();
+}
+
+impl Matrix for TerminalMatrix {
+    fn draw<I>(&mut self, pixels: I)
+    where
+        I: IntoIterator<Item = Pixel<Rgb888>>,
+    {
+        for Pixel(p, c) in pixels {
+            if (0..8).contains(&p.x) && (0..8).contains(&p.y) {
+                self.buffer[p.y as usize * 8 + p.x as usize] = c;
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        let mut out = stdout().lock();
+        for (y, row) in self.buffer.chunks(8).enumerate() {
+            queue!(out, MoveTo(0, y as u16)).unwrap();
+            for c in row {
+                let color = if *c == Rgb888::BLACK {
+                    OFF
+                } else {
+                    Color::Rgb {
+                        r: c.r(),
+                        g: c.g(),
+                        b: c.b(),
+                    }
+                };
+                queue!(out, SetForegroundColor(color), Print("██")).unwrap();
+            }
+        }
+        queue!(
+            out,
+            ResetColor,
+            MoveTo(0, 9),
+            Print(format!("seed: {}", self.seed)),
+            MoveTo(0, 10),
+            Print("←↑→↓: 倾斜  s: 摇晃  q: 退出"),
+            MoveTo(0, 11),
+            Print("p: 敲击两下暂停  f: 朝下退出  h: 翻转  x: 摇晃一下  r: 持续摇晃"),
+        )
+        .unwrap();
+        out.flush().unwrap();
+    }
+
+    fn clear(&mut self, color: Rgb888) {
+        self.buffer = [color; 64];
+    }
+
+    fn set_brightness(&mut self, _brightness: u8) {}
+}
@@ -0,0 +1,359 @@
+//! Maze generation algorithms
+//!
+//! Walls take up a tile too. Rooms sit on odd coordinates, and two adjacent rooms are
+//! connected when the tile between them is floor.
+//! Every algorithm generates a perfect maze with exactly one path between any two rooms;
+//! loops only appear after braiding.
+
+use crate::{Maze, TILE_FLOOR, TILE_WALL};
+use alloc::vec::Vec;
+use rand::{seq::SliceRandom, Rng};
+
+/// Generation algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    /// Recursive backtracker: long corridors, few branches
+    Backtracker,
+    /// Prim: grows out from one room, many branches, short corridors
+    Prim,
+    /// Kruskal: joins random adjacent rooms that are not yet connected, many branches
+    Kruskal,
+    /// Eller: row by row, only remembers the sets of the current row
+    Eller,
+    /// Wilson: loop-erased random walks, every maze is equally likely
+    Wilson,
+    /// Aldous-Broder: random walk, every maze is equally likely, slow
+    AldousBroder,
+    /// Binary tree: every room opens up or left, the top row and left column are straight corridors
+    BinaryTree,
+    /// Recursive division: keeps adding walls to an empty room, long straight walls
+    RecursiveDivision,
+}
+
+impl Algorithm {
+    /// All algorithms
+    pub const ALL: [Algorithm; 8] = [
+        Algorithm::Backtracker,
+        Algorithm::Prim,
+        Algorithm::Kruskal,
+        Algorithm::Eller,
+        Algorithm::Wilson,
+        Algorithm::AldousBroder,
+        Algorithm::BinaryTree,
+        Algorithm::RecursiveDivision,
+    ];
+}
+
+/// Room coordinates, room `(x, y)` is the tile `(2 * x + 1, 2 * y + 1)`
+type Room = (usize, usize);
+
+/// Union-find lookup with path compression
+fn find(sets: &mut [usize], mut i: usize) -> usize {
+    while sets[i] != i {
+        sets[i] = sets[sets[i]];
+        i = sets[i];
+    }
+    i
+}
+
+impl Maze {
+    /// Generate the maze data with the given algorithm.
+    pub fn generate_with<R>(mut self, algorithm: Algorithm, rng: &mut R) -> Self
+    where
+        R: Rng + ?Sized,
+    {
+        match algorithm {
+            Algorithm::Backtracker => self.backtrack(rng),
+            Algorithm::Prim => self.prim(rng),
+            Algorithm::Kruskal => self.kruskal(rng),
+            Algorithm::Eller => self.eller(rng),
+            Algorithm::Wilson => self.wilson(rng),
+            Algorithm::AldousBroder => self.aldous_broder(rng),
+            Algorithm::BinaryTree => self.binary_tree(rng),
+            Algorithm::RecursiveDivision => self.division(rng),
+        }
+        self
+    }
+
+    /// Braid the maze: open `percent` percent of the dead ends into loops,
+    /// preferably joining another dead end.
+    pub fn braid<R>(mut self, percent: u8, rng: &mut R) -> Self
+    where
+        R: Rng + ?Sized,
+    {
+        let mut dead = self
+            .all_rooms()
+            .filter(|r| self.is_dead_end(*r))
+            .collect::<Vec<_>>();
+        dead.shuffle(rng);
+        for room in dead {
+            // No longer a dead end after being joined to an earlier one
+            if !self.is_dead_end(room) || rng.gen_range(0..100) >= percent {
+                continue;
+            }
+            let closed = self
+                .adjacent(room)
+                .filter(|r| !self.is_connected(room, *r))
+                .collect::<Vec<_>>();
+            let dead_ends = closed
+                .iter()
+                .copied()
+                .filter(|r| self.is_dead_end(*r))
+                .collect::<Vec<_>>();
+            if let Some(to) = dead_ends.choose(rng).or_else(|| closed.choose(rng)) {
+                self.connect(room, *to);
+            }
+        }
+        self
+    }
+
+    /// Number of room columns and rows
+    fn rooms(&self) -> (usize, usize) {
+        (
+            self.width.saturating_sub(1) / 2,
+            self.height.saturating_sub(1) / 2,
+        )
+    }
+
+    fn all_rooms(&self) -> impl Iterator<Item = Room> {
+        let (w, h) = self.rooms();
+        (0..w).flat_map(move |x| (0..h).map(move |y| (x, y)))
+    }
+
+    fn random_room<R: Rng + ?Sized>(&self, rng: &mut R) -> Room {
+        let (w, h) = self.rooms();
+        (rng.gen_range(0..w), rng.gen_range(0..h))
+    }
+
+    /// Rooms above, below, left and right
+    fn adjacent(&self, (x, y): Room) -> impl Iterator<Item = Room> {
+        let (w, h) = self.rooms();
+        [
+            (x, y.wrapping_sub(1)),
+            (x + 1, y),
+            (x, y + 1),
+            (x.wrapping_sub(1), y),
+        ]
+        .into_iter()
+        .filter(move |(x, y)| *x < w && *y < h)
+    }
+
+    fn random_adjacent<R: Rng + ?Sized>(&self, room: Room, rng: &mut R) -> Room {
+        let adjacent = self.adjacent(room).collect::<Vec<_>>();
+        adjacent[rng.gen_range(0..adjacent.len())]
+    }
+
+    fn open(&mut self, (x, y): Room) {
+        self.data[2 * x + 1][2 * y + 1] = TILE_FLOOR;
+    }
+
+    fn is_open(&self, (x, y): Room) -> bool {
+        self.data[2 * x + 1][2 * y + 1] == TILE_FLOOR
+    }
+
+    /// Connect two adjacent rooms
+    fn connect(&mut self, a: Room, b: Room) {
+        self.open(a);
+        self.open(b);
+        self.data[a.0 + b.0 + 1][a.1 + b.1 + 1] = TILE_FLOOR;
+    }
+
+    fn is_connected(&self, a: Room, b: Room) -> bool {
+        self.data[a.0 + b.0 + 1][a.1 + b.1 + 1] == TILE_FLOOR
+    }
+
+    /// Connected to exactly one room
+    fn is_dead_end(&self, room: Room) -> bool {
+        self.is_open(room)
+            && self
+                .adjacent(room)
+                .filter(|r| self.is_connected(room, *r))
+                .count()
+                == 1
+    }
+
+    fn prim<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        let start = self.random_room(rng);
+        self.open(start);
+        let mut frontier = self.adjacent(start).collect::<Vec<_>>();
+        while !frontier.is_empty() {
+            let room = frontier.swap_remove(rng.gen_range(0..frontier.len()));
+            // A room can be added to the frontier more than once
+            if self.is_open(room) {
+                continue;
+            }
+            let opened = self
+                .adjacent(room)
+                .filter(|r| self.is_open(*r))
+                .collect::<Vec<_>>();
+            self.connect(room, opened[rng.gen_range(0..opened.len())]);
+            frontier.extend(self.adjacent(room).filter(|r| !self.is_open(*r)));
+        }
+    }
+
+    fn kruskal<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        let (w, h) = self.rooms();
+        let mut edges = Vec::new();
+        for (x, y) in self.all_rooms() {
+            if x + 1 < w {
+                edges.push(((x, y), (x + 1, y)));
+            }
+            if y + 1 < h {
+                edges.push(((x, y), (x, y + 1)));
+            }
+        }
+        edges.shuffle(rng);
+        let mut sets = (0..w * h).collect::<Vec<_>>();
+        for (a, b) in edges {
+            let sa = find(&mut sets, a.1 * w + a.0);
+            let sb = find(&mut sets, b.1 * w + b.0);
+            if sa != sb {
+                sets[sa] = sb;
+                self.connect(a, b);
+            }
+        }
+        // A single room has no edges
+        self.open((0, 0));
+    }
+
+    fn eller<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        let (w, h) = self.rooms();
+        // The set of each room in this row; rooms opened from the row above keep its set
+        let mut row: Vec<Option<usize>> = vec![None; w];
+        let mut next_set = 0;
+        for y in 0..h {
+            for (x, set) in row.iter_mut().enumerate() {
+                self.open((x, y));
+                if set.is_none() {
+                    *set = Some(next_set);
+                    next_set += 1;
+                }
+            }
+            // Randomly merge adjacent sets, the last row merges all of them
+            let last = y + 1 == h;
+            for x in 0..w - 1 {
+                if row[x] != row[x + 1] && (last || rng.gen_bool(0.5)) {
+                    let (from, to) = (row[x + 1], row[x]);
+                    for set in row.iter_mut().filter(|s| **s == from) {
+                        *set = to;
+                    }
+                    self.connect((x, y), (x + 1, y));
+                }
+            }
+            if last {
+                break;
+            }
+            // Every set opens at least one room downwards
+            let mut xs = (0..w).collect::<Vec<_>>();
+            xs.shuffle(rng);
+            let mut down = Vec::new();
+            let mut next = vec![None; w];
+            for x in xs {
+                if !down.contains(&row[x]) || rng.gen_bool(0.3) {
+                    down.push(row[x]);
+                    next[x] = row[x];
+                    self.connect((x, y), (x, y + 1));
+                }
+            }
+            row = next;
+        }
+    }
+
+    fn wilson<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        let w = self.rooms().0;
+        let mut rooms = self.all_rooms().collect::<Vec<_>>();
+        rooms.shuffle(rng);
+        self.open(rooms[0]);
+        // The last exit taken from each room during the walk; overwriting it on a
+        // revisit erases the loop
+        let mut exits: Vec<Option<Room>> = vec![None; rooms.len()];
+        for &start in &rooms[1..] {
+            let mut room = start;
+            while !self.is_open(room) {
+                let next = self.random_adjacent(room, rng);
+                exits[room.1 * w + room.0] = Some(next);
+                room = next;
+            }
+            let mut room = start;
+            let mut path = vec![room];
+            while !self.is_open(room) {
+                room = exits[room.1 * w + room.0].unwrap();
+                path.push(room);
+            }
+            for p in path.windows(2) {
+                self.connect(p[0], p[1]);
+            }
+        }
+    }
+
+    fn aldous_broder<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        let (w, h) = self.rooms();
+        let mut room = self.random_room(rng);
+        self.open(room);
+        let mut left = w * h - 1;
+        while left > 0 {
+            let next = self.random_adjacent(room, rng);
+            if !self.is_open(next) {
+                self.connect(room, next);
+                left -= 1;
+            }
+            room = next;
+        }
+    }
+
+    fn binary_tree<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        for (x, y) in self.all_rooms() {
+            self.open((x, y));
+            let mut choices = Vec::new();
+            if y > 0 {
+                choices.push((x, y - 1));
+            }
+            if x > 0 {
+                choices.push((x - 1, y));
+            }
+            if let Some(to) = choices.choose(rng) {
+                self.connect((x, y), *to);
+            }
+        }
+    }
+
+    fn division<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        let (w, h) = self.rooms();
+        // Start from a room without walls
+        for column in &mut self.data[1..2 * w] {
+            column[1..2 * h].fill(TILE_FLOOR);
+        }
+        // Areas to divide as (x, y, width, height) in rooms
+        let mut stack = vec![(0, 0, w, h)];
+        while let Some((x, y, w, h)) = stack.pop() {
+            if w < 2 && h < 2 {
+                continue;
+            }
+            let horizontal = match w.cmp(&h) {
+                core::cmp::Ordering::Less => true,
+                core::cmp::Ordering::Greater => false,
+                core::cmp::Ordering::Equal => rng.gen_bool(0.5),
+            };
+            if horizontal {
+                // Wall between room row y + r and the next row, leaving one gap
+                let r = rng.gen_range(0..h - 1);
+                let wy = 2 * (y + r + 1);
+                for column in &mut self.data[2 * x..=2 * (x + w)] {
+                    column[wy] = TILE_WALL;
+                }
+                let gap = x + rng.gen_range(0..w);
+                self.data[2 * gap + 1][wy] = TILE_FLOOR;
+                stack.push((x, y, w, r + 1));
+                stack.push((x, y + r + 1, w, h - r - 1));
+            } else {
+                let c = rng.gen_range(0..w - 1);
+                let wx = 2 * (x + c + 1);
+                self.data[wx][2 * y..=2 * (y + h)].fill(TILE_WALL);
+                let gap = y + rng.gen_range(0..h);
+                self.data[wx][2 * gap + 1] = TILE_FLOOR;
+                stack.push((x, y, c + 1, h));
+                stack.push((x + c + 1, y, w - c - 1, h));
+            }
+        }
+    }
+}
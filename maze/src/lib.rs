@@ -9,11 +9,18 @@ use alloc::vec::Vec;
 use core::convert::TryInto;
 use core::ops::{Add, Index, IndexMut, Mul};
 use core::slice::{Iter, IterMut};
+use core::str::FromStr;
 use rand::{prelude::SliceRandom, Rng};
 
 #[macro_use]
 extern crate alloc;
 
+mod algorithm;
+mod path;
+
+pub use algorithm::Algorithm;
+pub use path::Cell;
+
 const TILE_FLOOR: u8 = 0;
 const TILE_WALL: u8 = 1;
 
@@ -22,6 +29,8 @@ const TILE_WALL: u8 = 1;
 pub enum MazeGenerationError {
     /// Maze dimensions must be odd and >= 5
     InvalidDimensions,
+    /// Serialized data is truncated or malformed
+    InvalidData,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -71,17 +80,17 @@ impl Mul<isize> for TinyVec {
 ///
 /// The maze's dimensions need to be odd numbers >= 5. After creation,
 /// call [generate](#method.generate) to generate the data.
-/// You can access the generated maze data by indexing (e.g. ```maze[x][y]```) or by
-/// using [iter](#method.iter), [into_iter](#method.into_iter)
-/// or [iter_mut](#method.iter_mut).
+/// Read a tile with [get](#method.get) or [is_wall](#method.is_wall), which take `(x, y)`.
+/// Indexing (e.g. ```maze[x][y]```), [iter](#method.iter), [into_iter](#method.into_iter)
+/// and [iter_mut](#method.iter_mut) access the data column-wise.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Maze {
     /// Width of the maze. Must be an odd number >= 5.
-    pub width: usize,
+    width: usize,
     /// Height of the maze. Must be an odd number >= 5.
-    pub height: usize,
-    /// map data
-    pub data: Vec<Vec<u8>>,
+    height: usize,
+    /// map data, `data[x][y]`
+    data: Vec<Vec<u8>>,
 }
 
 impl Index<usize> for Maze {
@@ -107,21 +116,51 @@ impl IntoIterator for Maze {
     }
 }
 
+/// One row per line, `#` for walls and `-` for floor.
 impl core::fmt::Display for Maze {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        for d in &self.data {
-            writeln!(f, "{:?}", d)?;
+        for y in 0..self.height {
+            for column in &self.data[..self.width] {
+                let c = if column[y] == TILE_WALL { '#' } else { '-' };
+                write!(f, "{c}")?;
+            }
+            writeln!(f)?;
         }
         Ok(())
     }
 }
 
+/// Parse the text written by `Display`.
+impl FromStr for Maze {
+    type Err = MazeGenerationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rows = s.lines().map(str::trim).filter(|l| !l.is_empty());
+        let height = rows.clone().count();
+        let width = rows.clone().next().map_or(0, |r| r.chars().count());
+        let mut maze = Self::new(width, height)?;
+        for (y, row) in rows.enumerate() {
+            if row.chars().count() != width {
+                return Err(MazeGenerationError::InvalidData);
+            }
+            for (x, c) in row.chars().enumerate() {
+                maze.data[x][y] = match c {
+                    '#' => TILE_WALL,
+                    '-' => TILE_FLOOR,
+                    _ => return Err(MazeGenerationError::InvalidData),
+                };
+            }
+        }
+        Ok(maze)
+    }
+}
+
 impl Maze {
     /// Construct the maze. Only odd values >= 5 can be passed.
     pub fn new(width: usize, height: usize) -> Result<Self, MazeGenerationError> {
-        // if width < 5 || width % 2 == 0 || height < 5 || height % 2 == 0 {
-        //     return Err(MazeGenerationError::InvalidDimensions);
-        // }
+        if width < 5 || width % 2 == 0 || height < 5 || height % 2 == 0 {
+            return Err(MazeGenerationError::InvalidDimensions);
+        }
         Ok(Self {
             width,
             height,
@@ -129,18 +168,84 @@ impl Maze {
         })
     }
 
+    /// Width of the maze.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of the maze.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The tile at column `x` and row `y`: 0 for floor, 1 for wall.
+    /// Returns `None` outside the maze.
+    pub fn get(&self, x: usize, y: usize) -> Option<u8> {
+        self.data.get(x)?.get(y).copied()
+    }
+
+    /// Whether the tile at column `x` and row `y` is a wall. Outside the maze is wall.
+    pub fn is_wall(&self, x: usize, y: usize) -> bool {
+        self.get(x, y) != Some(TILE_FLOOR)
+    }
+
+    /// Pack the maze into bytes: width and height as little-endian `u16`,
+    /// then one bit per tile row by row, 1 for walls, least significant bit first.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + (self.width * self.height).div_ceil(8));
+        bytes.extend_from_slice(&(self.width as u16).to_le_bytes());
+        bytes.extend_from_slice(&(self.height as u16).to_le_bytes());
+        for i in 0..self.width * self.height {
+            if i % 8 == 0 {
+                bytes.push(0);
+            }
+            if self.data[i % self.width][i / self.width] == TILE_WALL {
+                *bytes.last_mut().unwrap() |= 1 << (i % 8);
+            }
+        }
+        bytes
+    }
+
+    /// Unpack bytes written by [to_bytes](#method.to_bytes).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MazeGenerationError> {
+        if bytes.len() < 4 {
+            return Err(MazeGenerationError::InvalidData);
+        }
+        let width = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+        let height = u16::from_le_bytes([bytes[2], bytes[3]]) as usize;
+        // Check the length before allocating, so a bogus header can't ask for a huge maze.
+        let bits = &bytes[4..];
+        if bits.len() != (width * height).div_ceil(8) {
+            return Err(MazeGenerationError::InvalidData);
+        }
+        let mut maze = Self::new(width, height)?;
+        for i in 0..width * height {
+            if bits[i / 8] & (1 << (i % 8)) == 0 {
+                maze.data[i % width][i / width] = TILE_FLOOR;
+            }
+        }
+        Ok(maze)
+    }
+
     /// Iterate over the maze data column-wise.
-    pub fn iter(&self) -> Iter<Vec<u8>> {
+    pub fn iter(&self) -> Iter<'_, Vec<u8>> {
         self.data.iter()
     }
 
     /// Mutably iterate over the maze data column-wise.
-    pub fn iter_mut(&mut self) -> IterMut<Vec<u8>> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, Vec<u8>> {
         self.data.iter_mut()
     }
 
-    /// Generate the maze data.
-    pub fn generate<R>(mut self, rng: &mut R) -> Self
+    /// Generate the maze data with [`Algorithm::Backtracker`].
+    pub fn generate<R>(self, rng: &mut R) -> Self
+    where
+        R: Rng + ?Sized,
+    {
+        self.generate_with(Algorithm::Backtracker, rng)
+    }
+
+    fn backtrack<R>(&mut self, rng: &mut R)
     where
         R: Rng + ?Sized,
     {
@@ -183,6 +288,92 @@ impl Maze {
                 }
             }
         }
-        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    /// Every algorithm, with and without braiding, on square and non-square mazes
+    fn mazes() -> impl Iterator<Item = (Algorithm, u8, Maze)> {
+        Algorithm::ALL.into_iter().flat_map(|algorithm| {
+            [(0, 5, 5), (0, 21, 13), (50, 21, 13), (100, 33, 33)]
+                .into_iter()
+                .map(move |(braid, width, height)| {
+                    let mut rng = SmallRng::seed_from_u64(width as u64 * 31 + braid as u64);
+                    let maze = Maze::new(width, height)
+                        .unwrap()
+                        .generate_with(algorithm, &mut rng)
+                        .braid(braid, &mut rng);
+                    (algorithm, braid, maze)
+                })
+        })
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        for (algorithm, braid, maze) in mazes() {
+            let bytes = maze.to_bytes();
+            assert_eq!(bytes.len(), 4 + (maze.width * maze.height).div_ceil(8));
+            assert_eq!(
+                Maze::from_bytes(&bytes),
+                Ok(maze),
+                "{algorithm:?} braid {braid}"
+            );
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        for (algorithm, braid, maze) in mazes() {
+            assert_eq!(
+                maze.to_string().parse(),
+                Ok(maze),
+                "{algorithm:?} braid {braid}"
+            );
+        }
+    }
+
+    #[test]
+    fn generated_mazes_connected() {
+        for (algorithm, braid, maze) in mazes() {
+            let start = maze.floors().next().unwrap();
+            let dist = maze.distances(start);
+            let connected = maze.floors().all(|(x, y)| dist[x][y].is_some());
+            assert!(connected, "{algorithm:?} braid {braid}\n{maze}");
+        }
+    }
+
+    #[test]
+    fn invalid_data() {
+        let maze = Maze::new(5, 7).unwrap();
+        let bytes = maze.to_bytes();
+        assert_eq!(
+            Maze::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MazeGenerationError::InvalidData)
+        );
+        assert_eq!(
+            Maze::from_bytes(&bytes[..3]),
+            Err(MazeGenerationError::InvalidData)
+        );
+        assert_eq!(
+            Maze::from_bytes(&[0xff, 0xff, 0xff, 0xff, 0]),
+            Err(MazeGenerationError::InvalidData)
+        );
+        assert_eq!(
+            Maze::from_bytes(&[4, 0, 5, 0, 0, 0, 0]),
+            Err(MazeGenerationError::InvalidDimensions)
+        );
+        assert_eq!(
+            "#####\n#-#-#\n#####\n#####\n####\n".parse::<Maze>(),
+            Err(MazeGenerationError::InvalidData)
+        );
+        assert_eq!(
+            "#####\n#-x-#\n#####\n#####\n#####\n".parse::<Maze>(),
+            Err(MazeGenerationError::InvalidData)
+        );
     }
 }
@@ -0,0 +1,101 @@
+//! Path finding in the maze
+//!
+//! Every step in the maze costs the same, so shortest paths come from a breadth-first search.
+//! A generated maze has exactly one path between any two tiles, so two searches find the
+//! longest shortest path: search from any tile to the farthest tile A, then search from A to
+//! the farthest tile B, and A to B is the longest shortest path.
+//! In a maze with loops (e.g. after braiding) the two searches give an approximation.
+
+use crate::{Maze, TILE_FLOOR};
+use alloc::{collections::VecDeque, vec::Vec};
+
+/// Tile coordinates `(x, y)`, `x` is the column and `y` the row, as in `Maze::get`
+pub type Cell = (usize, usize);
+
+impl Maze {
+    /// Whether the tile is floor. Outside the maze is not floor.
+    pub fn is_floor(&self, (x, y): Cell) -> bool {
+        x < self.width && y < self.height && self.data[x][y] == TILE_FLOOR
+    }
+
+    /// All floor tiles.
+    pub fn floors(&self) -> impl Iterator<Item = Cell> + '_ {
+        (0..self.width)
+            .flat_map(move |x| (0..self.height).map(move |y| (x, y)))
+            .filter(|c| self.is_floor(*c))
+    }
+
+    /// Floor tiles above, below, left and right of the tile.
+    fn neighbours(&self, (x, y): Cell) -> impl Iterator<Item = Cell> + '_ {
+        [
+            (x, y.wrapping_sub(1)),
+            (x + 1, y),
+            (x, y + 1),
+            (x.wrapping_sub(1), y),
+        ]
+        .into_iter()
+        .filter(|c| self.is_floor(*c))
+    }
+
+    /// Steps from `from` to every tile, indexed `[x][y]`.
+    /// Walls and unreachable tiles are `None`.
+    pub fn distances(&self, from: Cell) -> Vec<Vec<Option<usize>>> {
+        let mut dist = vec![vec![None; self.height]; self.width];
+        if !self.is_floor(from) {
+            return dist;
+        }
+        dist[from.0][from.1] = Some(0);
+        let mut queue = VecDeque::from([(from, 0)]);
+        while let Some((c, d)) = queue.pop_front() {
+            for n in self.neighbours(c) {
+                if dist[n.0][n.1].is_none() {
+                    dist[n.0][n.1] = Some(d + 1);
+                    queue.push_back((n, d + 1));
+                }
+            }
+        }
+        dist
+    }
+
+    /// The tile farthest from `from` and its number of steps.
+    pub fn farthest(&self, from: Cell) -> Option<(Cell, usize)> {
+        let dist = self.distances(from);
+        self.floors()
+            .filter_map(|c| dist[c.0][c.1].map(|d| (c, d)))
+            .max_by_key(|(_, d)| *d)
+    }
+
+    /// Both ends of the longest shortest path, `None` if there is no floor.
+    pub fn farthest_pair(&self) -> Option<(Cell, Cell)> {
+        let (a, _) = self.farthest(self.floors().next()?)?;
+        let (b, _) = self.farthest(a)?;
+        Some((a, b))
+    }
+
+    /// The tile whose number of steps from `from` is closest to `distance`.
+    pub fn at_distance(&self, from: Cell, distance: usize) -> Option<Cell> {
+        let dist = self.distances(from);
+        self.floors()
+            .filter_map(|c| dist[c.0][c.1].map(|d| (c, d)))
+            .min_by_key(|(_, d)| d.abs_diff(distance))
+            .map(|(c, _)| c)
+    }
+
+    /// Shortest path from `from` to `to`, including both ends, `None` if `to` can't be reached.
+    pub fn shortest_path(&self, from: Cell, to: Cell) -> Option<Vec<Cell>> {
+        if !self.is_floor(from) {
+            return None;
+        }
+        // Search from the goal, then walk from the start along decreasing steps.
+        let dist = self.distances(to);
+        let mut d = dist[from.0][from.1]?;
+        let mut path = vec![from];
+        let mut c = from;
+        while d > 0 {
+            d -= 1;
+            c = self.neighbours(c).find(|n| dist[n.0][n.1] == Some(d))?;
+            path.push(c);
+        }
+        Some(path)
+    }
+}